@@ -0,0 +1,3 @@
+DROP TABLE login_failures;
+
+DROP TABLE rate_limits
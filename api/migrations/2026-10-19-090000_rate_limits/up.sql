@@ -0,0 +1,18 @@
+CREATE TABLE rate_limits
+(
+  key VARCHAR(255) PRIMARY KEY,
+  attempts INTEGER NOT NULL,
+  window_start TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX rate_limits_window_start ON rate_limits (window_start);
+
+CREATE TABLE login_failures
+(
+  key_hash VARCHAR(255) PRIMARY KEY,
+  failures INTEGER NOT NULL,
+  locked_until TIMESTAMP WITH TIME ZONE,
+  last_failed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX login_failures_last_failed_at ON login_failures (last_failed_at)
@@ -0,0 +1,15 @@
+CREATE TABLE mfa
+(
+  user_id uuid PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+  secret VARCHAR(255) NOT NULL,
+  confirmed_at TIMESTAMP WITH TIME ZONE,
+  last_step BIGINT
+);
+
+CREATE TABLE mfa_recovery_codes
+(
+  id uuid PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  code_hash VARCHAR(255) NOT NULL,
+  used_at TIMESTAMP WITH TIME ZONE
+)
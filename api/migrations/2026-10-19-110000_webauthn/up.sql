@@ -0,0 +1,19 @@
+CREATE TABLE webauthn_credentials
+(
+  id uuid PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  credential_id BYTEA NOT NULL UNIQUE,
+  public_key BYTEA NOT NULL,
+  sign_count BIGINT NOT NULL,
+  name VARCHAR(255),
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+  last_used_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE TABLE webauthn_challenges
+(
+  challenge BYTEA PRIMARY KEY,
+  kind VARCHAR(16) NOT NULL,
+  user_id uuid REFERENCES users (id) ON DELETE CASCADE,
+  expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+)
@@ -0,0 +1,3 @@
+DROP TABLE oidc_states;
+
+DROP TABLE identities
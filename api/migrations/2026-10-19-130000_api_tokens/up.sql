@@ -0,0 +1,11 @@
+CREATE TABLE api_tokens
+(
+  id uuid PRIMARY KEY,
+  user_id uuid NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  name VARCHAR(255) NOT NULL,
+  scopes TEXT[] NOT NULL DEFAULT '{}',
+  token_hash BYTEA NOT NULL UNIQUE,
+  expires_at TIMESTAMP WITH TIME ZONE,
+  last_used_at TIMESTAMP WITH TIME ZONE,
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+)
@@ -0,0 +1,7 @@
+DROP TABLE audit_events;
+
+ALTER TABLE users DROP COLUMN role;
+
+DROP TABLE role_permissions;
+
+DROP TABLE roles
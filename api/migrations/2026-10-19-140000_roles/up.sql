@@ -0,0 +1,43 @@
+CREATE TABLE roles
+(
+  name VARCHAR(32) PRIMARY KEY
+);
+
+CREATE TABLE role_permissions
+(
+  role VARCHAR(32) NOT NULL REFERENCES roles (name) ON DELETE CASCADE,
+  permission VARCHAR(64) NOT NULL,
+  PRIMARY KEY (role, permission)
+);
+
+INSERT INTO roles (name) VALUES ('superadmin'), ('support'), ('user');
+
+INSERT INTO role_permissions (role, permission) VALUES
+  ('superadmin', 'list_users'),
+  ('superadmin', 'read_users'),
+  ('superadmin', 'update_users'),
+  ('superadmin', 'delete_users'),
+  ('superadmin', 'suspend_users'),
+  ('superadmin', 'impersonate_users'),
+  ('superadmin', 'assign_roles'),
+  ('support', 'list_users'),
+  ('support', 'read_users'),
+  ('support', 'suspend_users');
+
+ALTER TABLE users
+  ADD COLUMN role VARCHAR(32) NOT NULL DEFAULT 'user' REFERENCES roles (name);
+
+CREATE TABLE audit_events
+(
+  id uuid PRIMARY KEY,
+  -- Not references, so events outlive who they're about.
+  actor_id uuid,
+  action VARCHAR(64) NOT NULL,
+  target_id uuid,
+  ip VARCHAR(45),
+  request_id VARCHAR(255) NOT NULL,
+  details JSONB NOT NULL DEFAULT '{}',
+  created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_events_target_id ON audit_events (target_id, created_at)
@@ -0,0 +1,5 @@
+ALTER TABLE users
+  ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'active'
+    CHECK (status IN ('active', 'suspended', 'pending')),
+  ADD COLUMN status_reason TEXT,
+  ADD COLUMN status_expires_at TIMESTAMP WITH TIME ZONE
@@ -0,0 +1,7 @@
+DROP INDEX groups_deleted_at;
+
+DROP INDEX users_deleted_at;
+
+ALTER TABLE groups DROP COLUMN deleted_at;
+
+ALTER TABLE users DROP COLUMN deleted_at
@@ -0,0 +1,8 @@
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+ALTER TABLE groups ADD COLUMN deleted_at TIMESTAMP WITH TIME ZONE;
+
+-- For purging, most rows aren't deleted.
+CREATE INDEX users_deleted_at ON users (deleted_at) WHERE deleted_at IS NOT NULL;
+
+CREATE INDEX groups_deleted_at ON groups (deleted_at) WHERE deleted_at IS NOT NULL
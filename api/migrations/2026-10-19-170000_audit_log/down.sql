@@ -0,0 +1,13 @@
+DELETE FROM role_permissions WHERE permission = 'read_audit_events';
+
+DROP TRIGGER audit_events_append_only ON audit_events;
+
+DROP FUNCTION audit_events_append_only();
+
+DROP INDEX audit_events_action;
+
+DROP INDEX audit_events_actor_id;
+
+ALTER TABLE audit_events
+  DROP COLUMN diff,
+  DROP COLUMN user_agent
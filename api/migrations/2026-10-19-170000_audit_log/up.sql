@@ -0,0 +1,20 @@
+ALTER TABLE audit_events
+  ADD COLUMN user_agent TEXT,
+  -- Fields changed, as `{ "field": { "from": ..., "to": ... } }`.
+  ADD COLUMN diff JSONB NOT NULL DEFAULT '{}';
+
+CREATE INDEX audit_events_actor_id ON audit_events (actor_id, created_at);
+
+CREATE INDEX audit_events_action ON audit_events (action, created_at);
+
+CREATE FUNCTION audit_events_append_only() RETURNS TRIGGER AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+  BEFORE UPDATE OR DELETE ON audit_events
+  FOR EACH ROW EXECUTE PROCEDURE audit_events_append_only();
+
+INSERT INTO role_permissions (role, permission) VALUES ('superadmin', 'read_audit_events')
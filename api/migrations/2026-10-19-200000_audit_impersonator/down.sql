@@ -0,0 +1,3 @@
+DROP INDEX audit_events_impersonator_id;
+
+ALTER TABLE audit_events DROP COLUMN impersonator_id
@@ -0,0 +1,4 @@
+-- Admin acting as the actor, when impersonating them.
+ALTER TABLE audit_events ADD COLUMN impersonator_id UUID;
+
+CREATE INDEX audit_events_impersonator_id ON audit_events (impersonator_id, created_at)
@@ -1,23 +1,201 @@
 use crate::error::Error;
-use clap::{App, Arg, ArgGroup};
+use crate::introspection::SchemaFormat;
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use log::LevelFilter;
+use serde_json;
 use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
 // Todo: Add validators (i.e. min length for token & salt, etc)
 // Todo: Remove `testing` and use compiler flags for identifying tests
 
 pub struct Config {
-  pub address: [u8; 4],
+  /// `SameSite` attribute of session cookies.
+  pub cookie_same_site: SameSite,
+  /// Whether logins set a session cookie for
+  /// browser clients instead of returning tokens.
+  pub cookie_sessions: bool,
+  /// Whether cross-origin requests can include
+  /// cookies.
+  pub cors_credentials: bool,
+  /// Request headers cross-origin clients can send.
+  pub cors_headers: Vec<String>,
+  /// How long browsers can cache preflight responses.
+  pub cors_max_age: Duration,
+  /// Methods cross-origin clients can use.
+  pub cors_methods: Vec<String>,
+  /// Origins allowed to call `/graphql` from browsers,
+  /// with `*` wildcards, none when empty.
+  pub cors_origins: Vec<String>,
   pub db_name: String,
   pub db_user: String,
   pub db_password: String,
   pub db_server: String,
+  /// How long soft deleted users and groups can be
+  /// restored for before they're purged.
+  pub deletion_grace_period: Duration,
+  /// Directory users' data exports are written to.
+  pub export_dir: PathBuf,
+  /// How long data export download URLs are valid for.
+  pub export_ttl: Duration,
   pub hash_salt: String,
+  pub ide: Ide,
+  pub ide_path: String,
+  pub introspection: Introspection,
+  pub listen: SocketAddr,
+  pub lockout_duration: Duration,
+  pub lockout_threshold: u32,
+  pub log_level: LevelFilter,
+  /// Name shown in authenticator apps for TOTP
+  /// codes and passkeys.
+  pub mfa_issuer: String,
+  /// External identity providers users can log in with.
+  pub oidc_providers: Vec<OidcProvider>,
+  pub rate_limit_account: u32,
+  pub rate_limit_ip: u32,
+  pub rate_limit_window: Duration,
+  pub shutdown_timeout: Duration,
   pub testing: bool,
+  pub tls_cert: Option<PathBuf>,
+  pub tls_key: Option<PathBuf>,
   pub token_secret: String,
+  pub trace_exporter: TraceExporter,
+  pub trust_forwarded_for: bool,
+  /// Origin passkey ceremonies must come from.
+  pub webauthn_origin: String,
+  /// Domain passkeys are registered to.
+  pub webauthn_rp_id: String,
+}
+
+/// GraphQL IDE served at `Config::ide_path`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Ide {
+  GraphiQL,
+  Playground,
+  Disabled,
+}
+
+impl FromStr for Ide {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Ide, Error> {
+    match s {
+      "graphiql" => Ok(Ide::GraphiQL),
+      "playground" => Ok(Ide::Playground),
+      "none" => Ok(Ide::Disabled),
+      _ => Err(Error::Str("Invalid IDE")),
+    }
+  }
+}
+
+/// Which cross-site requests browsers send
+/// session cookies with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SameSite {
+  Strict,
+  Lax,
+  None,
+}
+
+impl SameSite {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      SameSite::Strict => "Strict",
+      SameSite::Lax => "Lax",
+      SameSite::None => "None",
+    }
+  }
+}
+
+impl FromStr for SameSite {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<SameSite, Error> {
+    match s {
+      "strict" => Ok(SameSite::Strict),
+      "lax" => Ok(SameSite::Lax),
+      "none" => Ok(SameSite::None),
+      _ => Err(Error::Str("Invalid SameSite setting")),
+    }
+  }
+}
+
+/// Who can run `__schema` and `__type` introspection queries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Introspection {
+  Enabled,
+  Authenticated,
+  Disabled,
+}
+
+impl Introspection {
+  /// Whether introspection is allowed for a caller.
+  pub fn allows(self, authenticated: bool) -> bool {
+    match self {
+      Introspection::Enabled => true,
+      Introspection::Authenticated => authenticated,
+      Introspection::Disabled => false,
+    }
+  }
+}
+
+impl FromStr for Introspection {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Introspection, Error> {
+    match s {
+      "enabled" => Ok(Introspection::Enabled),
+      "authenticated" => Ok(Introspection::Authenticated),
+      "disabled" => Ok(Introspection::Disabled),
+      _ => Err(Error::Str("Invalid introspection setting")),
+    }
+  }
+}
+
+/// Where finished tracing spans are sent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceExporter {
+  Disabled,
+  Stdout,
+  /// Appends spans to a local file.
+  File(PathBuf),
+  /// Posts spans to an OTLP/HTTP collector's base URL.
+  Otlp(String),
+}
+
+/// An OpenID Connect provider, read from the JSON
+/// file given by `--oidc-providers`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct OidcProvider {
+  /// Name clients pick the provider by, i.e. `google`.
+  pub name: String,
+  /// Issuer URL, its discovery document is fetched
+  /// from `{issuer}/.well-known/openid-configuration`.
+  pub issuer: String,
+  pub client_id: String,
+  /// Omitted for public clients, which rely on PKCE.
+  pub client_secret: Option<String>,
+  /// Client page the provider returns users to
+  /// with the code and state.
+  pub redirect_uri: String,
 }
 
 impl Config {
-  /// Creates a new `Config` instance.
+  /// Creates a new `Config` instance. Listens on
+  /// `127.0.0.1:8000` with the GraphiQL IDE and
+  /// introspection enabled on debug builds, and
+  /// `0.0.0.0:8000` without them on release builds.
+  /// Waits up to 30 seconds for in-flight requests
+  /// on shutdown. Allows 20 attempts per IP address
+  /// and 10 per account each minute at rate limited
+  /// mutations, and locks accounts for a minute
+  /// after 5 failed logins. Deleted users and groups
+  /// can be restored for 30 days, and data exports
+  /// are written to `exports` and downloadable for a
+  /// day.
   ///
   /// Example usage:
   ///
@@ -45,21 +223,55 @@ impl Config {
     testing: bool,
     token_secret: &str,
   ) -> Config {
-    let address = if cfg!(debug_assertions) {
-      [127, 0, 0, 1]
+    let (address, ide, introspection) = if cfg!(debug_assertions) {
+      ([127, 0, 0, 1], Ide::GraphiQL, Introspection::Enabled)
     } else {
-      [0, 0, 0, 0]
+      ([0, 0, 0, 0], Ide::Disabled, Introspection::Disabled)
     };
 
     Config {
-      address,
+      cookie_same_site: SameSite::Lax,
+      cookie_sessions: false,
+      cors_credentials: false,
+      cors_headers: vec![
+        "authorization".to_string(),
+        "content-type".to_string(),
+        "traceparent".to_string(),
+        "x-csrf-token".to_string(),
+        "x-request-id".to_string(),
+      ],
+      cors_max_age: Duration::from_secs(600),
+      cors_methods: vec!["GET".to_string(), "POST".to_string()],
+      cors_origins: Vec::new(),
       db_name: db_name.to_string(),
       db_user: db_user.to_string(),
       db_password: db_password.to_string(),
       db_server: db_server.to_string(),
+      deletion_grace_period: Duration::from_secs(30 * 24 * 60 * 60),
+      export_dir: PathBuf::from("exports"),
+      export_ttl: Duration::from_secs(24 * 60 * 60),
       hash_salt: hash_salt.to_string(),
+      ide,
+      ide_path: "/".to_string(),
+      introspection,
+      listen: SocketAddr::from((address, 8000)),
+      lockout_duration: Duration::from_secs(60),
+      lockout_threshold: 5,
+      log_level: LevelFilter::Info,
+      mfa_issuer: "Graphy".to_string(),
+      oidc_providers: Vec::new(),
+      rate_limit_account: 10,
+      rate_limit_ip: 20,
+      rate_limit_window: Duration::from_secs(60),
+      shutdown_timeout: Duration::from_secs(30),
       testing,
+      tls_cert: None,
+      tls_key: None,
       token_secret: token_secret.to_string(),
+      trace_exporter: TraceExporter::Disabled,
+      trust_forwarded_for: false,
+      webauthn_origin: "http://localhost:8000".to_string(),
+      webauthn_rp_id: "localhost".to_string(),
     }
   }
 
@@ -75,120 +287,10 @@ impl Config {
   /// let config = Config::from_args().unwrap();
   /// ```
   pub fn from_args() -> Result<Config, Error> {
-    let args = App::new(env!("CARGO_PKG_NAME"))
-      .version(env!("CARGO_PKG_VERSION"))
-      .author(env!("CARGO_PKG_AUTHORS"))
-      .about(env!("CARGO_PKG_DESCRIPTION"))
-      .bin_name(env!("CARGO_PKG_NAME"))
-      .arg(
-        Arg::with_name("db-name")
-          .short("d")
-          .long("db-name")
-          .value_name("NAME")
-          .help("Sets database name")
-          .takes_value(true),
-      )
-      .arg(
-        Arg::with_name("db-name-file")
-          .long("db-name-file")
-          .value_name("FILE")
-          .help("Sets database name via file")
-          .takes_value(true),
-      )
-      .group(
-        ArgGroup::with_name("database-name")
-          .args(&["db-name", "db-name-file"])
-          .required(true),
-      )
-      .arg(
-        Arg::with_name("db-user")
-          .short("u")
-          .long("db-user")
-          .value_name("USERNAME")
-          .help("Sets database username")
-          .takes_value(true),
-      )
-      .arg(
-        Arg::with_name("db-user-file")
-          .long("db-user-file")
-          .value_name("FILE")
-          .help("Sets database username via file")
-          .takes_value(true),
-      )
-      .group(
-        ArgGroup::with_name("database-user")
-          .args(&["db-user", "db-user-file"])
-          .required(true),
-      )
-      .arg(
-        Arg::with_name("db-password")
-          .short("p")
-          .long("db-password")
-          .value_name("PASSWORD")
-          .help("Sets database password")
-          .takes_value(true),
-      )
-      .arg(
-        Arg::with_name("db-password-file")
-          .long("db-password-file")
-          .value_name("FILE")
-          .help("Sets database password via file")
-          .takes_value(true),
-      )
-      .group(
-        ArgGroup::with_name("database-password")
-          .args(&["db-password", "db-password-file"])
-          .required(true),
-      )
-      .arg(
-        Arg::with_name("db-server")
-          .short("s")
-          .long("db-server")
-          .value_name("SERVER")
-          .help("Sets database server")
-          .takes_value(true)
-          .default_value("127.0.0.1"),
-      )
-      .arg(
-        Arg::with_name("hash-salt")
-          .long("hash-salt")
-          .value_name("SALT")
-          .help("Sets hash salt")
-          .takes_value(true),
-      )
-      .arg(
-        Arg::with_name("hash-salt-file")
-          .long("hash-salt-file")
-          .value_name("FILE")
-          .help("Sets hash salt via file")
-          .takes_value(true),
-      )
-      .group(
-        ArgGroup::with_name("hasher-salt")
-          .args(&["hash-salt", "hash-salt-file"])
-          .required(true),
-      )
-      .arg(
-        Arg::with_name("token-secret")
-          .long("token-secret")
-          .value_name("SECRET")
-          .help("Sets token secret")
-          .takes_value(true),
-      )
-      .arg(
-        Arg::with_name("token-secret-file")
-          .long("token-secret-file")
-          .value_name("FILE")
-          .help("Sets token secret via file")
-          .takes_value(true),
-      )
-      .group(
-        ArgGroup::with_name("tokeniser-secret")
-          .args(&["token-secret", "token-secret-file"])
-          .required(true),
-      )
-      .get_matches();
+    Config::from_matches(&app().get_matches())
+  }
 
+  fn from_matches(args: &ArgMatches) -> Result<Config, Error> {
     let find_arg = |val, file| -> Result<String, Error> {
       if let Some(val_arg) = args.value_of(val) {
         return Ok(val_arg.to_string());
@@ -201,7 +303,7 @@ impl Config {
       Err(Error::Str("Args missing"))
     };
 
-    Ok(Config::new(
+    let mut config = Config::new(
       &find_arg("db-name", "db-name-file")?,
       &find_arg("db-user", "db-user-file")?,
       &find_arg("db-password", "db-password-file")?,
@@ -209,6 +311,579 @@ impl Config {
       &find_arg("hash-salt", "hash-salt-file")?,
       false,
       &find_arg("token-secret", "token-secret-file")?,
-    ))
+    );
+
+    config.cookie_sessions = args.is_present("cookie-sessions");
+
+    if let Some(same_site) = args.value_of("cookie-same-site") {
+      config.cookie_same_site = same_site.parse()?;
+    }
+
+    if let Some(origins) = args.value_of("cors-origins") {
+      config.cors_origins = list(origins, str::to_lowercase);
+    }
+
+    if let Some(methods) = args.value_of("cors-methods") {
+      config.cors_methods = list(methods, str::to_uppercase);
+    }
+
+    if let Some(headers) = args.value_of("cors-headers") {
+      config.cors_headers = list(headers, str::to_lowercase);
+    }
+
+    if let Some(max_age) = args.value_of("cors-max-age") {
+      config.cors_max_age = Duration::from_secs(
+        max_age
+          .parse()
+          .map_err(|_| Error::Str("Invalid CORS max age"))?,
+      );
+    }
+
+    config.cors_credentials = args.is_present("cors-credentials");
+
+    // Any origin could read responses made with
+    // the user's cookies.
+    if config.cors_credentials && config.cors_origins.iter().any(|origin| origin == "*") {
+      return Err(Error::Str(
+        "CORS credentials can't be allowed for any origin",
+      ));
+    }
+
+    if let Some(days) = args.value_of("deletion-grace-period") {
+      config.deletion_grace_period = Duration::from_secs(
+        days
+          .parse::<u64>()
+          .map_err(|_| Error::Str("Invalid deletion grace period"))?
+          * 24
+          * 60
+          * 60,
+      );
+    }
+
+    if let Some(dir) = args.value_of("export-dir") {
+      config.export_dir = PathBuf::from(dir);
+    }
+
+    if let Some(ttl) = args.value_of("export-ttl") {
+      config.export_ttl =
+        Duration::from_secs(ttl.parse().map_err(|_| Error::Str("Invalid export TTL"))?);
+    }
+
+    if let Some(ide) = args.value_of("ide") {
+      config.ide = ide.parse()?;
+    }
+
+    if let Some(introspection) = args.value_of("introspection") {
+      config.introspection = introspection.parse()?;
+    }
+
+    if let Some(listen) = args.value_of("listen") {
+      config.listen = listen
+        .to_socket_addrs()?
+        .next()
+        .ok_or(Error::Str("Invalid listen address"))?;
+    }
+
+    if let Some(level) = args.value_of("log-level") {
+      config.log_level = level.parse().map_err(|_| Error::Str("Invalid log level"))?;
+    }
+
+    if let Some(issuer) = args.value_of("mfa-issuer") {
+      config.mfa_issuer = issuer.to_string();
+    }
+
+    if let Some(file) = args.value_of("oidc-providers") {
+      config.oidc_providers = serde_json::from_str(&fs::read_to_string(file)?)?;
+    }
+
+    if let Some(attempts) = args.value_of("rate-limit-ip") {
+      config.rate_limit_ip = attempts
+        .parse()
+        .map_err(|_| Error::Str("Invalid IP rate limit"))?;
+    }
+
+    if let Some(attempts) = args.value_of("rate-limit-account") {
+      config.rate_limit_account = attempts
+        .parse()
+        .map_err(|_| Error::Str("Invalid account rate limit"))?;
+    }
+
+    if let Some(window) = args.value_of("rate-limit-window") {
+      config.rate_limit_window = Duration::from_secs(
+        window
+          .parse()
+          .map_err(|_| Error::Str("Invalid rate limit window"))?,
+      );
+    }
+
+    if let Some(failures) = args.value_of("lockout-threshold") {
+      config.lockout_threshold = failures
+        .parse()
+        .map_err(|_| Error::Str("Invalid lockout threshold"))?;
+    }
+
+    if let Some(duration) = args.value_of("lockout-duration") {
+      config.lockout_duration = Duration::from_secs(
+        duration
+          .parse()
+          .map_err(|_| Error::Str("Invalid lockout duration"))?,
+      );
+    }
+
+    if let Some(timeout) = args.value_of("shutdown-timeout") {
+      config.shutdown_timeout = Duration::from_secs(
+        timeout
+          .parse()
+          .map_err(|_| Error::Str("Invalid shutdown timeout"))?,
+      );
+    }
+
+    config.trace_exporter = match args.value_of("trace-exporter") {
+      Some("stdout") => TraceExporter::Stdout,
+      Some("file") => TraceExporter::File(PathBuf::from(args.value_of("trace-file").unwrap())),
+      Some("otlp") => TraceExporter::Otlp(args.value_of("trace-endpoint").unwrap().to_string()),
+      _ => TraceExporter::Disabled,
+    };
+
+    config.ide_path = args.value_of("ide-path").unwrap().to_string();
+    config.tls_cert = args.value_of("tls-cert").map(PathBuf::from);
+    config.tls_key = args.value_of("tls-key").map(PathBuf::from);
+    config.trust_forwarded_for = args.is_present("trust-forwarded-for");
+
+    if let Some(origin) = args.value_of("webauthn-origin") {
+      config.webauthn_origin = origin.trim_end_matches('/').to_string();
+    }
+
+    if let Some(rp_id) = args.value_of("webauthn-rp-id") {
+      config.webauthn_rp_id = rp_id.to_string();
+    }
+
+    Ok(config)
+  }
+}
+
+/// Command to run, parsed from the command line.
+pub enum Command {
+  /// Runs the API server.
+  Serve(Box<Config>),
+  /// Prints the GraphQL schema, without a database.
+  Schema(SchemaFormat),
+  /// Gives a user (by email) a role.
+  AssignRole {
+    config: Box<Config>,
+    email: String,
+    role: String,
+  },
+}
+
+impl Command {
+  /// For usage in binary implementation. Parses
+  /// `args` from the command line to create a new
+  /// `Command`. Config `args` are only required
+  /// when no subcommand is given.
+  ///
+  /// Example usage:
+  ///
+  /// ```no_run
+  /// use api::config::Command;
+  ///
+  /// let command = Command::from_args().unwrap();
+  /// ```
+  pub fn from_args() -> Result<Command, Error> {
+    let args = app().get_matches();
+
+    match args.subcommand() {
+      ("schema", Some(schema_args)) => Ok(Command::Schema(
+        schema_args.value_of("format").unwrap().parse()?,
+      )),
+      ("assign-role", Some(role_args)) => Ok(Command::AssignRole {
+        config: Box::new(Config::from_matches(&args)?),
+        email: role_args.value_of("email").unwrap().to_string(),
+        role: role_args.value_of("role").unwrap().to_string(),
+      }),
+      _ => Ok(Command::Serve(Box::new(Config::from_matches(&args)?))),
+    }
   }
 }
+
+/// Trimmed, non-empty entries of a comma
+/// separated `list`, normalised by `case`.
+fn list(list: &str, case: fn(&str) -> String) -> Vec<String> {
+  list
+    .split(',')
+    .map(str::trim)
+    .filter(|entry| !entry.is_empty())
+    .map(case)
+    .collect()
+}
+
+fn app() -> App<'static, 'static> {
+  App::new(env!("CARGO_PKG_NAME"))
+    .version(env!("CARGO_PKG_VERSION"))
+    .author(env!("CARGO_PKG_AUTHORS"))
+    .about(env!("CARGO_PKG_DESCRIPTION"))
+    .bin_name(env!("CARGO_PKG_NAME"))
+    .arg(
+      Arg::with_name("db-name")
+        .short("d")
+        .long("db-name")
+        .value_name("NAME")
+        .help("Sets database name")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("db-name-file")
+        .long("db-name-file")
+        .value_name("FILE")
+        .help("Sets database name via file")
+        .takes_value(true),
+    )
+    .group(
+      ArgGroup::with_name("database-name")
+        .args(&["db-name", "db-name-file"])
+        .required(true),
+    )
+    .arg(
+      Arg::with_name("db-user")
+        .short("u")
+        .long("db-user")
+        .value_name("USERNAME")
+        .help("Sets database username")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("db-user-file")
+        .long("db-user-file")
+        .value_name("FILE")
+        .help("Sets database username via file")
+        .takes_value(true),
+    )
+    .group(
+      ArgGroup::with_name("database-user")
+        .args(&["db-user", "db-user-file"])
+        .required(true),
+    )
+    .arg(
+      Arg::with_name("db-password")
+        .short("p")
+        .long("db-password")
+        .value_name("PASSWORD")
+        .help("Sets database password")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("db-password-file")
+        .long("db-password-file")
+        .value_name("FILE")
+        .help("Sets database password via file")
+        .takes_value(true),
+    )
+    .group(
+      ArgGroup::with_name("database-password")
+        .args(&["db-password", "db-password-file"])
+        .required(true),
+    )
+    .arg(
+      Arg::with_name("db-server")
+        .short("s")
+        .long("db-server")
+        .value_name("SERVER")
+        .help("Sets database server")
+        .takes_value(true)
+        .default_value("127.0.0.1"),
+    )
+    .arg(
+      Arg::with_name("deletion-grace-period")
+        .long("deletion-grace-period")
+        .value_name("DAYS")
+        .help("Sets how long deleted users and groups can be restored for before they're purged, defaults to 30")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("export-dir")
+        .long("export-dir")
+        .value_name("DIR")
+        .help("Sets the directory users' data exports are written to, defaults to `exports`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("export-ttl")
+        .long("export-ttl")
+        .value_name("SECONDS")
+        .help("Sets how long data export download URLs are valid for, defaults to 86400")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("hash-salt")
+        .long("hash-salt")
+        .value_name("SALT")
+        .help("Sets hash salt")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("hash-salt-file")
+        .long("hash-salt-file")
+        .value_name("FILE")
+        .help("Sets hash salt via file")
+        .takes_value(true),
+    )
+    .group(
+      ArgGroup::with_name("hasher-salt")
+        .args(&["hash-salt", "hash-salt-file"])
+        .required(true),
+    )
+    .arg(
+      Arg::with_name("token-secret")
+        .long("token-secret")
+        .value_name("SECRET")
+        .help("Sets token secret")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("token-secret-file")
+        .long("token-secret-file")
+        .value_name("FILE")
+        .help("Sets token secret via file")
+        .takes_value(true),
+    )
+    .group(
+      ArgGroup::with_name("tokeniser-secret")
+        .args(&["token-secret", "token-secret-file"])
+        .required(true),
+    )
+    .arg(
+      Arg::with_name("listen")
+        .short("l")
+        .long("listen")
+        .value_name("ADDRESS")
+        .help("Sets host and port to listen on, defaults to `127.0.0.1:8000` on debug builds and `0.0.0.0:8000` on release builds")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("log-level")
+        .long("log-level")
+        .value_name("LEVEL")
+        .help("Sets the level of JSON logs written to stderr, defaults to `info`")
+        .takes_value(true)
+        .possible_values(&["off", "error", "warn", "info", "debug", "trace"]),
+    )
+    .arg(
+      Arg::with_name("mfa-issuer")
+        .long("mfa-issuer")
+        .value_name("NAME")
+        .help("Sets the name shown in authenticator apps for TOTP codes and passkeys, defaults to `Graphy`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("oidc-providers")
+        .long("oidc-providers")
+        .value_name("FILE")
+        .help("Sets OpenID Connect providers to log in with from a JSON array of `name`, `issuer`, `client_id`, `client_secret` and `redirect_uri`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("rate-limit-ip")
+        .long("rate-limit-ip")
+        .value_name("ATTEMPTS")
+        .help("Sets attempts per IP address per window at `login` and `createUser`, `0` for no limit, defaults to 20")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("rate-limit-account")
+        .long("rate-limit-account")
+        .value_name("ATTEMPTS")
+        .help("Sets attempts per account per window at `login`, `0` for no limit, defaults to 10")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("rate-limit-window")
+        .long("rate-limit-window")
+        .value_name("SECONDS")
+        .help("Sets the window rate limits are counted over, defaults to 60")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("lockout-threshold")
+        .long("lockout-threshold")
+        .value_name("FAILURES")
+        .help("Sets failed logins before an account is locked, `0` for no lockout, defaults to 5")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("lockout-duration")
+        .long("lockout-duration")
+        .value_name("SECONDS")
+        .help("Sets the first lockout, doubled for each further failure up to a day, defaults to 60")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("trust-forwarded-for")
+        .long("trust-forwarded-for")
+        .help("Takes client IP addresses from the last `X-Forwarded-For` entry, for use behind a gateway"),
+    )
+    .arg(
+      Arg::with_name("cookie-sessions")
+        .long("cookie-sessions")
+        .help("Logs browser clients in with an `HttpOnly` session cookie, checking CSRF tokens on mutations"),
+    )
+    .arg(
+      Arg::with_name("cors-origins")
+        .long("cors-origins")
+        .value_name("ORIGINS")
+        .help("Sets comma separated origins allowed to call `/graphql` from browsers, with `*` wildcards, i.e. `https://*.example.com`, defaults to none")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("cors-methods")
+        .long("cors-methods")
+        .value_name("METHODS")
+        .help("Sets comma separated methods cross-origin clients can use, defaults to `GET,POST`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("cors-headers")
+        .long("cors-headers")
+        .value_name("HEADERS")
+        .help("Sets comma separated headers cross-origin clients can send, defaults to `authorization,content-type,traceparent,x-csrf-token,x-request-id`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("cors-credentials")
+        .long("cors-credentials")
+        .help("Allows cross-origin requests to include cookies, i.e. for `--cookie-sessions`"),
+    )
+    .arg(
+      Arg::with_name("cors-max-age")
+        .long("cors-max-age")
+        .value_name("SECONDS")
+        .help("Sets how long browsers can cache preflight responses, defaults to 600")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("cookie-same-site")
+        .long("cookie-same-site")
+        .value_name("SAME_SITE")
+        .help("Sets the session cookie's `SameSite` attribute, defaults to `lax`")
+        .takes_value(true)
+        .possible_values(&["strict", "lax", "none"]),
+    )
+    .arg(
+      Arg::with_name("webauthn-origin")
+        .long("webauthn-origin")
+        .value_name("ORIGIN")
+        .help("Sets the origin passkeys are used from, i.e. `https://example.com`, defaults to `http://localhost:8000`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("webauthn-rp-id")
+        .long("webauthn-rp-id")
+        .value_name("DOMAIN")
+        .help("Sets the domain passkeys are registered to, i.e. `example.com`, defaults to `localhost`")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("shutdown-timeout")
+        .long("shutdown-timeout")
+        .value_name("SECONDS")
+        .help("Sets how long to wait for in-flight requests on SIGINT or SIGTERM, defaults to 30")
+        .takes_value(true),
+    )
+    .arg(
+      Arg::with_name("tls-cert")
+        .long("tls-cert")
+        .value_name("FILE")
+        .help("Sets TLS certificate chain (PEM) to serve HTTPS with, reloaded on change")
+        .takes_value(true)
+        .requires("tls-key"),
+    )
+    .arg(
+      Arg::with_name("tls-key")
+        .long("tls-key")
+        .value_name("FILE")
+        .help("Sets TLS private key (PKCS #8 PEM) to serve HTTPS with, reloaded on change")
+        .takes_value(true)
+        .requires("tls-cert"),
+    )
+    .arg(
+      Arg::with_name("trace-exporter")
+        .long("trace-exporter")
+        .value_name("EXPORTER")
+        .help("Sets where tracing spans are exported, defaults to `none`")
+        .takes_value(true)
+        .possible_values(&["none", "stdout", "file", "otlp"]),
+    )
+    .arg(
+      Arg::with_name("trace-file")
+        .long("trace-file")
+        .value_name("FILE")
+        .help("Sets file that the `file` trace exporter appends spans to")
+        .takes_value(true)
+        .default_value("traces.jsonl"),
+    )
+    .arg(
+      Arg::with_name("trace-endpoint")
+        .long("trace-endpoint")
+        .value_name("URL")
+        .help("Sets OTLP/HTTP collector that the `otlp` trace exporter posts spans to")
+        .takes_value(true)
+        .default_value("http://127.0.0.1:4318"),
+    )
+    .arg(
+      Arg::with_name("ide")
+        .long("ide")
+        .value_name("IDE")
+        .help("Sets GraphQL IDE, defaults to `graphiql` on debug builds")
+        .takes_value(true)
+        .possible_values(&["graphiql", "playground", "none"]),
+    )
+    .arg(
+      Arg::with_name("ide-path")
+        .long("ide-path")
+        .value_name("PATH")
+        .help("Sets GraphQL IDE path")
+        .takes_value(true)
+        .default_value("/"),
+    )
+    .arg(
+      Arg::with_name("introspection")
+        .long("introspection")
+        .value_name("ACCESS")
+        .help("Sets GraphQL introspection access, defaults to `enabled` on debug builds")
+        .takes_value(true)
+        .possible_values(&["enabled", "authenticated", "disabled"]),
+    )
+    .setting(AppSettings::SubcommandsNegateReqs)
+    .subcommand(
+      SubCommand::with_name("schema")
+        .about("Prints the GraphQL schema")
+        .arg(
+          Arg::with_name("format")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Sets schema format")
+            .takes_value(true)
+            .possible_values(&["sdl", "json"])
+            .default_value("sdl"),
+        ),
+    )
+    .subcommand(
+      SubCommand::with_name("assign-role")
+        .about("Gives a user a role, i.e. to set up the first superadmin")
+        .arg(
+          Arg::with_name("email")
+            .long("email")
+            .value_name("EMAIL")
+            .help("Sets the user's email")
+            .takes_value(true)
+            .required(true),
+        )
+        .arg(
+          Arg::with_name("role")
+            .long("role")
+            .value_name("ROLE")
+            .help("Sets the role")
+            .takes_value(true)
+            .required(true),
+        ),
+    )
+}
@@ -1,15 +1,188 @@
-use crate::db::Db;
+use crate::cookie::{self, Cookies, Csrf};
+use crate::db::{Connection, Db};
+use crate::error::Error;
+use crate::exporter::Exporter;
 use crate::hasher::Hasher;
+use crate::limiter::Limiter;
+use crate::models::audit_event::{AuditEvent, AuditEventCreate};
+use crate::models::session::Session;
+use crate::oidc::Oidc;
+use crate::scope::Scope;
 use crate::tokeniser::Tokeniser;
+use crate::trace::SpanContext;
+use crate::webauthn::Webauthn;
+use chrono::Utc;
 use juniper::Context as JuniperContext;
-use std::sync::Arc;
+use serde_json::Value;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 use uuid::Uuid;
 
 pub struct Context {
+  /// Admin acting as the user, when impersonating.
+  pub actor: Option<Uuid>,
+  pub cookies: Arc<Cookies>,
+  /// Whether mutations are allowed, for users
+  /// authenticated by cookie.
+  pub csrf: Csrf,
   pub db: Arc<Db>,
+  /// How long deleted users and groups can be restored for.
+  pub deletion_grace_period: Duration,
+  pub exporter: Arc<Exporter>,
   pub hasher: Arc<Hasher>,
+  /// Client IP address, when known.
+  pub ip: Option<IpAddr>,
+  pub limiter: Arc<Limiter>,
+  /// Issuer shown in authenticator apps.
+  pub mfa_issuer: String,
+  pub oidc: Arc<Oidc>,
+  pub request_id: String,
+  /// `Set-Cookie` headers to respond with.
+  pub set_cookies: Mutex<Vec<String>>,
+  /// Scopes the user's token was granted.
+  pub scopes: Vec<Scope>,
+  /// Session of the user's token, unless it's
+  /// an API token.
+  pub session: Option<Uuid>,
   pub tokeniser: Arc<Tokeniser>,
+  /// The HTTP request's span, parenting operation spans.
+  pub trace: SpanContext,
   pub user: Option<Uuid>,
+  /// Client `User-Agent` header, when sent.
+  pub user_agent: Option<String>,
+  pub webauthn: Arc<Webauthn>,
+}
+
+impl Context {
+  /// Refuses tokens missing any of `scopes`,
+  /// declared by resolvers before acting.
+  pub fn require(&self, scopes: &[Scope]) -> Result<(), Error> {
+    let missing: Vec<Scope> = scopes
+      .iter()
+      .cloned()
+      .filter(|scope| !self.scopes.contains(scope))
+      .collect();
+
+    if missing.is_empty() {
+      Ok(())
+    } else {
+      Err(Error::Forbidden(missing))
+    }
+  }
+
+  /// Starts a session for `token`, just issued by
+  /// logging in, returning it for the client. With
+  /// cookie sessions, it's set as a cookie instead,
+  /// returning the CSRF token mutations need.
+  pub fn logged_in(&self, connection: &Connection, token: String) -> Result<String, Error> {
+    let claims = (self.tokeniser.verify)(&token)?;
+    Session::start(
+      connection,
+      &claims,
+      self.ip.map(|ip| ip.to_string()),
+      self.user_agent.as_deref(),
+    )?;
+
+    if !self.cookies.enabled {
+      return Ok(token);
+    }
+
+    let csrf = cookie::csrf_token()?;
+    let max_age = claims.exp - Utc::now().timestamp();
+    self.respond_with(self.cookies.login(&token, &csrf, max_age));
+
+    Ok(csrf)
+  }
+
+  /// Clears session cookies, if enabled.
+  pub fn logged_out(&self) {
+    if self.cookies.enabled {
+      self.respond_with(self.cookies.logout());
+    }
+  }
+
+  fn respond_with(&self, cookies: Vec<String>) {
+    self
+      .set_cookies
+      .lock()
+      .unwrap_or_else(PoisonError::into_inner)
+      .extend(cookies);
+  }
+
+  /// Records the user doing `action` to `target_id`
+  /// in the audit trail.
+  pub fn audit(
+    &self,
+    connection: &Connection,
+    action: &str,
+    target_id: Option<Uuid>,
+    details: Value,
+  ) -> Result<(), Error> {
+    AuditEvent::record(
+      connection,
+      &AuditEventCreate {
+        target_id,
+        details,
+        ..self.audit_event(action)
+      },
+    )
+  }
+
+  /// Records the user changing `target_id`, with
+  /// the fields changed (see `audit_event::diff`).
+  pub fn audit_change(
+    &self,
+    connection: &Connection,
+    action: &str,
+    target_id: Option<Uuid>,
+    diff: Value,
+  ) -> Result<(), Error> {
+    AuditEvent::record(
+      connection,
+      &AuditEventCreate {
+        target_id,
+        diff,
+        ..self.audit_event(action)
+      },
+    )
+  }
+
+  /// Records a login attempt as `user_id`, who
+  /// isn't the context's user yet, if known.
+  pub fn audit_login(
+    &self,
+    connection: &Connection,
+    user_id: Option<Uuid>,
+    details: Value,
+  ) -> Result<(), Error> {
+    AuditEvent::record(
+      connection,
+      &AuditEventCreate {
+        actor_id: user_id,
+        target_id: user_id,
+        details,
+        ..self.audit_event("login")
+      },
+    )
+  }
+
+  /// An event for `action` by this request's user,
+  /// to fill in the rest of.
+  fn audit_event<'a>(&'a self, action: &'a str) -> AuditEventCreate<'a> {
+    AuditEventCreate {
+      id: Uuid::new_v4(),
+      actor_id: self.user,
+      impersonator_id: self.actor,
+      action,
+      target_id: None,
+      ip: self.ip.map(|ip| ip.to_string()),
+      user_agent: self.user_agent.as_deref(),
+      request_id: &self.request_id,
+      details: json!({}),
+      diff: json!({}),
+    }
+  }
 }
 
 impl<'a> JuniperContext for Context {}
@@ -0,0 +1,159 @@
+use crate::config::{Config, SameSite};
+use crate::error::Error;
+use ring::constant_time::verify_slices_are_equal;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Holds the login token, out of reach of scripts.
+pub const SESSION_COOKIE: &str = "graphy_session";
+/// Holds the CSRF token, for scripts to copy into
+/// `CSRF_HEADER`.
+pub const CSRF_COOKIE: &str = "graphy_csrf";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// Issues the cookies browser clients are logged
+/// in with, when enabled, so they never handle
+/// tokens themselves. Mutations made with them need
+/// the CSRF token sent back in `CSRF_HEADER` too
+/// (double-submit), which other sites can't read.
+pub struct Cookies {
+  pub enabled: bool,
+  same_site: SameSite,
+}
+
+/// Whether a request's CSRF token checks out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Csrf {
+  /// Not authenticated by cookie, so forged
+  /// requests carry no credentials.
+  NotRequired,
+  Valid,
+  Invalid,
+}
+
+impl Csrf {
+  /// Compares the CSRF cookie with the header,
+  /// in constant time.
+  pub fn check(cookie: Option<&str>, header: Option<&str>) -> Csrf {
+    match (cookie, header) {
+      (Some(cookie), Some(header))
+        if !cookie.is_empty()
+          && verify_slices_are_equal(cookie.as_bytes(), header.as_bytes()).is_ok() =>
+      {
+        Csrf::Valid
+      }
+      _ => Csrf::Invalid,
+    }
+  }
+}
+
+impl Cookies {
+  pub fn new(config: &Config) -> Cookies {
+    Cookies {
+      enabled: config.cookie_sessions,
+      same_site: config.cookie_same_site,
+    }
+  }
+
+  /// Creates `Cookies` that are never set, for
+  /// contexts without clients (i.e. exporting
+  /// the schema).
+  pub fn disabled() -> Cookies {
+    Cookies {
+      enabled: false,
+      same_site: SameSite::Strict,
+    }
+  }
+
+  /// `Set-Cookie` headers logging in with `token`
+  /// and `csrf` for `max_age` seconds.
+  pub fn login(&self, token: &str, csrf: &str, max_age: i64) -> Vec<String> {
+    vec![
+      self.cookie(SESSION_COOKIE, token, max_age, true),
+      self.cookie(CSRF_COOKIE, csrf, max_age, false),
+    ]
+  }
+
+  /// `Set-Cookie` headers clearing the cookies.
+  pub fn logout(&self) -> Vec<String> {
+    vec![
+      self.cookie(SESSION_COOKIE, "", 0, true),
+      self.cookie(CSRF_COOKIE, "", 0, false),
+    ]
+  }
+
+  fn cookie(&self, name: &str, value: &str, max_age: i64, http_only: bool) -> String {
+    format!(
+      "{}={}; Max-Age={}; Path=/;{} Secure; SameSite={}",
+      name,
+      value,
+      max_age.max(0),
+      if http_only { " HttpOnly;" } else { "" },
+      self.same_site.as_str()
+    )
+  }
+}
+
+/// Value of cookie `name` in a `Cookie` header.
+pub fn get<'a>(header: &'a str, name: &str) -> Option<&'a str> {
+  header
+    .split(';')
+    .filter_map(|cookie| cookie.trim().split_once('='))
+    .find(|(key, _)| *key == name)
+    .map(|(_, value)| value)
+}
+
+/// Random base64url token for `CSRF_COOKIE`.
+pub fn csrf_token() -> Result<String, Error> {
+  let mut bytes = [0; 32];
+  SystemRandom::new()
+    .fill(&mut bytes)
+    .map_err(|_| Error::Str("Failed to generate random bytes"))?;
+
+  Ok(base64::encode_config(&bytes, base64::URL_SAFE_NO_PAD))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_get() {
+    let header = "theme=dark; graphy_session=abc.def; graphy_csrf=123";
+
+    assert_eq!(get(header, SESSION_COOKIE), Some("abc.def"));
+    assert_eq!(get(header, CSRF_COOKIE), Some("123"));
+    assert_eq!(get(header, "missing"), None);
+  }
+
+  #[test]
+  fn test_login_logout() {
+    let cookies = Cookies {
+      enabled: true,
+      same_site: SameSite::Lax,
+    };
+
+    assert_eq!(
+      cookies.login("token", "csrf", 900),
+      vec![
+        "graphy_session=token; Max-Age=900; Path=/; HttpOnly; Secure; SameSite=Lax",
+        "graphy_csrf=csrf; Max-Age=900; Path=/; Secure; SameSite=Lax",
+      ]
+    );
+    assert_eq!(
+      cookies.logout(),
+      vec![
+        "graphy_session=; Max-Age=0; Path=/; HttpOnly; Secure; SameSite=Lax",
+        "graphy_csrf=; Max-Age=0; Path=/; Secure; SameSite=Lax",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_csrf_check() {
+    assert_eq!(Csrf::check(Some("abc"), Some("abc")), Csrf::Valid);
+    assert_eq!(Csrf::check(Some("abc"), Some("abd")), Csrf::Invalid);
+    assert_eq!(Csrf::check(Some("abc"), None), Csrf::Invalid);
+    assert_eq!(Csrf::check(None, None), Csrf::Invalid);
+    assert_eq!(Csrf::check(Some(""), Some("")), Csrf::Invalid);
+  }
+}
@@ -1,16 +1,25 @@
+mod traced;
+
+pub use self::traced::TracedConnection;
+
 use crate::error::Error;
+use crate::metrics;
 use diesel::{
   r2d2::ConnectionManager, r2d2::CustomizeConnection, sql_query, Connection as Diesel_Connection,
   PgConnection, RunQueryDsl,
 };
-use r2d2::{Pool, PooledConnection};
+use diesel_migrations::MigrationConnection;
+use r2d2::{Pool, PooledConnection, State};
+use std::collections::HashSet;
+use std::time::Duration;
 
-pub type Connection = PooledConnection<ConnectionManager<PgConnection>>;
-type ConnectionPool = Pool<ConnectionManager<PgConnection>>;
+pub type Connection = PooledConnection<ConnectionManager<TracedConnection>>;
+type ConnectionPool = Pool<ConnectionManager<TracedConnection>>;
 embed_migrations!();
 
 pub struct Db {
   connection_pool: ConnectionPool,
+  migrations: HashSet<String>,
 }
 
 impl Db {
@@ -42,7 +51,7 @@ impl Db {
         .expect(&format!("Failed to create database \"{}\".", db_name));
     }
 
-    let manager = ConnectionManager::<PgConnection>::new(connection_url);
+    let manager = ConnectionManager::<TracedConnection>::new(connection_url);
 
     let connection_pool = if testing {
       Pool::builder()
@@ -53,21 +62,72 @@ impl Db {
       Pool::builder().max_size(15).build(manager)?
     };
 
-    embedded_migrations::run(&connection_pool.clone().get()?)?;
+    let conn = connection_pool.clone().get()?;
+    embedded_migrations::run(&conn)?;
+    let migrations = conn.previously_run_migration_versions()?;
 
-    Ok(Db { connection_pool })
+    Ok(Db {
+      connection_pool,
+      migrations,
+    })
+  }
+
+  /// Creates an instance of `Db` that never connects,
+  /// for tasks that don't touch the database
+  /// (i.e. exporting the schema).
+  pub fn offline() -> Db {
+    let manager = ConnectionManager::<TracedConnection>::new("postgres://offline.invalid");
+
+    Db {
+      connection_pool: Pool::builder()
+        .min_idle(Some(0))
+        .max_size(1)
+        .build_unchecked(manager),
+      migrations: HashSet::new(),
+    }
   }
 
   pub fn connect(&self) -> Result<Connection, Error> {
+    let _timer = metrics::DB_POOL_WAIT.start_timer();
+
     Ok(self.connection_pool.clone().get()?)
   }
+
+  /// Takes a connection from the pool, waiting at
+  /// most `timeout` rather than the pool's default.
+  pub fn connect_timeout(&self, timeout: Duration) -> Result<Connection, Error> {
+    Ok(self.connection_pool.get_timeout(timeout)?)
+  }
+
+  /// Current number of open and idle connections.
+  pub fn state(&self) -> State {
+    self.connection_pool.state()
+  }
+
+  /// Lists migrations that were applied at startup
+  /// but no longer are, i.e. the schema has been
+  /// rolled back underneath the server.
+  pub fn missing_migrations(&self, conn: &Connection) -> Result<Vec<String>, Error> {
+    let applied = conn.previously_run_migration_versions()?;
+    let mut missing = self
+      .migrations
+      .difference(&applied)
+      .cloned()
+      .collect::<Vec<_>>();
+    missing.sort();
+
+    Ok(missing)
+  }
 }
 
 #[derive(Debug)]
 struct TestTransaction;
 
-impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for TestTransaction {
-  fn on_acquire(&self, conn: &mut PgConnection) -> ::std::result::Result<(), diesel::r2d2::Error> {
+impl CustomizeConnection<TracedConnection, diesel::r2d2::Error> for TestTransaction {
+  fn on_acquire(
+    &self,
+    conn: &mut TracedConnection,
+  ) -> ::std::result::Result<(), diesel::r2d2::Error> {
     use diesel::Connection;
 
     conn.begin_test_transaction().unwrap();
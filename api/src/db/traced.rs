@@ -0,0 +1,107 @@
+use crate::trace::{Span, SpanKind};
+use diesel::connection::{AnsiTransactionManager, SimpleConnection};
+use diesel::deserialize::{Queryable, QueryableByName};
+use diesel::pg::{Pg, PgQueryBuilder};
+use diesel::query_builder::{AsQuery, QueryBuilder, QueryFragment, QueryId};
+use diesel::sql_types::HasSqlType;
+use diesel::{Connection, ConnectionResult, PgConnection, QueryResult};
+
+/// A `PgConnection` that records a span for each
+/// query, with its SQL but not its bind values.
+pub struct TracedConnection {
+  inner: PgConnection,
+}
+
+impl TracedConnection {
+  fn span<T: QueryFragment<Pg>>(query: &T) -> Span {
+    let mut builder = PgQueryBuilder::new();
+    let statement = query
+      .to_sql(&mut builder)
+      .map(|_| builder.finish())
+      .unwrap_or_default();
+
+    TracedConnection::statement_span(&statement)
+  }
+
+  fn statement_span(statement: &str) -> Span {
+    let operation = statement.split_whitespace().next().unwrap_or("QUERY");
+    let mut span = Span::child(operation, SpanKind::Client);
+    span.set_attribute("db.system", "postgresql");
+    span.set_attribute("db.statement", statement);
+    span
+  }
+}
+
+/// Marks `span` as failed if `result` is an error.
+fn record<T>(mut span: Span, result: QueryResult<T>) -> QueryResult<T> {
+  if let Err(ref err) = result {
+    span.set_error(&err.to_string());
+  }
+
+  result
+}
+
+impl SimpleConnection for TracedConnection {
+  fn batch_execute(&self, query: &str) -> QueryResult<()> {
+    record(
+      TracedConnection::statement_span(query),
+      self.inner.batch_execute(query),
+    )
+  }
+}
+
+impl Connection for TracedConnection {
+  type Backend = Pg;
+  type TransactionManager = AnsiTransactionManager;
+
+  fn establish(database_url: &str) -> ConnectionResult<TracedConnection> {
+    PgConnection::establish(database_url).map(|inner| TracedConnection { inner })
+  }
+
+  fn execute(&self, query: &str) -> QueryResult<usize> {
+    record(
+      TracedConnection::statement_span(query),
+      self.inner.execute(query),
+    )
+  }
+
+  fn query_by_index<T, U>(&self, source: T) -> QueryResult<Vec<U>>
+  where
+    T: AsQuery,
+    T::Query: QueryFragment<Pg> + QueryId,
+    Pg: HasSqlType<T::SqlType>,
+    U: Queryable<T::SqlType, Pg>,
+  {
+    let query = source.as_query();
+
+    record(
+      TracedConnection::span(&query),
+      self.inner.query_by_index(query),
+    )
+  }
+
+  fn query_by_name<T, U>(&self, source: &T) -> QueryResult<Vec<U>>
+  where
+    T: QueryFragment<Pg> + QueryId,
+    U: QueryableByName<Pg>,
+  {
+    record(
+      TracedConnection::span(source),
+      self.inner.query_by_name(source),
+    )
+  }
+
+  fn execute_returning_count<T>(&self, source: &T) -> QueryResult<usize>
+  where
+    T: QueryFragment<Pg> + QueryId,
+  {
+    record(
+      TracedConnection::span(source),
+      self.inner.execute_returning_count(source),
+    )
+  }
+
+  fn transaction_manager(&self) -> &AnsiTransactionManager {
+    self.inner.transaction_manager()
+  }
+}
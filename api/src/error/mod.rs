@@ -1,8 +1,13 @@
+use crate::metrics;
+use crate::scope::Scope;
 use argon2;
 use diesel;
 use diesel_migrations;
+use juniper::{FieldError, IntoFieldError, Object, Value};
 use jwt;
+use native_tls;
 use r2d2;
+use serde_json;
 use std::fmt;
 use std::io;
 
@@ -12,25 +17,96 @@ use std::io;
 /// type and bubble up.
 #[derive(Debug)]
 pub enum Error {
+  AccountPending,
+  AccountSuspended,
   Diesel(diesel::result::Error),
   DieselMigrations(diesel_migrations::RunMigrationsError),
+  EmailTaken,
+  /// Logged in, but the token wasn't granted these scopes.
+  Forbidden(Vec<Scope>),
   Hasher(argon2::Error),
+  InvalidCredentials,
+  InvalidMfaCode,
+  /// OIDC login refused, with why.
+  InvalidOidcLogin(&'static str),
+  /// Passkey refused, with why.
+  InvalidPasskey(&'static str),
   Jwt(jwt::errors::Error),
   Io(io::Error),
+  Json(serde_json::Error),
+  /// Account locked after failed logins, for this many seconds.
+  Locked(u64),
+  /// Password accepted, but an MFA code is needed. Holds
+  /// the challenge token to verify the code with.
+  MfaRequired(String),
+  /// OIDC provider unreachable or misbehaving, with how.
+  OidcProvider(&'static str),
   R2d2(r2d2::Error),
+  /// Too many attempts, retry after this many seconds.
+  RateLimited(u64),
   Str(&'static str),
+  Tls(native_tls::Error),
+  /// Not allowed to do something, with why.
+  Unauthorised(&'static str),
+}
+
+impl Error {
+  /// Machine readable code for the error, returned
+  /// to GraphQL clients in the `code` extension.
+  pub fn code(&self) -> &'static str {
+    match *self {
+      Error::Diesel(diesel::result::Error::NotFound) => "NOT_FOUND",
+      Error::Diesel(_) | Error::DieselMigrations(_) => "DATABASE",
+      Error::R2d2(_) => "DATABASE_UNAVAILABLE",
+      Error::Forbidden(_) => "FORBIDDEN",
+      Error::Jwt(_) => "INVALID_TOKEN",
+      Error::Locked(_) => "ACCOUNT_LOCKED",
+      Error::MfaRequired(_) => "MFA_REQUIRED",
+      Error::RateLimited(_) => "RATE_LIMITED",
+      Error::Unauthorised(_) => "UNAUTHORISED",
+      Error::InvalidCredentials => "INVALID_CREDENTIALS",
+      Error::InvalidMfaCode => "INVALID_MFA_CODE",
+      Error::InvalidPasskey(_) => "INVALID_PASSKEY",
+      Error::InvalidOidcLogin(_) => "INVALID_OIDC_LOGIN",
+      Error::OidcProvider(_) => "OIDC_PROVIDER_ERROR",
+      Error::EmailTaken => "EMAIL_TAKEN",
+      Error::AccountSuspended => "ACCOUNT_SUSPENDED",
+      Error::AccountPending => "ACCOUNT_PENDING",
+      Error::Str(_) => "BAD_REQUEST",
+      Error::Hasher(_) | Error::Io(_) | Error::Json(_) | Error::Tls(_) => "INTERNAL",
+    }
+  }
 }
 
 impl fmt::Display for Error {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match *self {
+      Error::AccountPending => write!(f, "Account pending"),
+      Error::AccountSuspended => write!(f, "Account suspended"),
       Error::Diesel(ref err) => err.fmt(f),
       Error::DieselMigrations(ref err) => err.fmt(f),
+      Error::EmailTaken => write!(f, "Email already registered"),
+      Error::Forbidden(ref scopes) => {
+        write!(f, "Forbidden - Missing scope {}", Scope::join(scopes))
+      }
       Error::Hasher(ref err) => err.fmt(f),
+      Error::InvalidCredentials => write!(f, "Invalid credentials"),
+      Error::InvalidMfaCode => write!(f, "Invalid MFA code"),
+      Error::InvalidOidcLogin(err) | Error::InvalidPasskey(err) | Error::OidcProvider(err) => {
+        err.fmt(f)
+      }
       Error::Jwt(ref err) => err.fmt(f),
       Error::Io(ref err) => err.fmt(f),
+      Error::Json(ref err) => err.fmt(f),
+      Error::Locked(seconds) => write!(f, "Account locked, try again in {} seconds", seconds),
+      Error::MfaRequired(_) => write!(f, "MFA code required"),
       Error::R2d2(ref err) => err.fmt(f),
+      Error::RateLimited(seconds) => {
+        write!(f, "Too many attempts, try again in {} seconds", seconds)
+      }
       Error::Str(ref err) => err.fmt(f),
+      Error::Tls(ref err) => err.fmt(f),
+      Error::Unauthorised(err) => err.fmt(f),
     }
   }
 }
@@ -65,8 +141,59 @@ impl From<io::Error> for Error {
   }
 }
 
+impl From<serde_json::Error> for Error {
+  fn from(err: serde_json::Error) -> Self {
+    Error::Json(err)
+  }
+}
+
 impl From<r2d2::Error> for Error {
   fn from(err: r2d2::Error) -> Self {
     Error::R2d2(err)
   }
 }
+
+impl From<native_tls::Error> for Error {
+  fn from(err: native_tls::Error) -> Self {
+    Error::Tls(err)
+  }
+}
+
+impl IntoFieldError for Error {
+  fn into_field_error(self) -> FieldError {
+    let code = self.code();
+    metrics::RESOLVER_ERRORS.with_label_values(&[code]).inc();
+
+    match self {
+      Error::Locked(seconds) | Error::RateLimited(seconds) => {
+        let retry_after = seconds as i32;
+
+        FieldError::new(
+          self,
+          graphql_value!({ "code": code, "retryAfter": retry_after }),
+        )
+      }
+      Error::Forbidden(ref scopes) => {
+        let mut extensions = Object::with_capacity(2);
+        extensions.add_field("code", Value::scalar(code));
+        extensions.add_field(
+          "missingScopes",
+          Value::list(
+            scopes
+              .iter()
+              .map(|scope| Value::scalar(scope.as_str()))
+              .collect(),
+          ),
+        );
+
+        FieldError::new(&self, Value::Object(extensions))
+      }
+      Error::MfaRequired(ref token) => {
+        let token = token.as_str();
+
+        FieldError::new(&self, graphql_value!({ "code": code, "mfaToken": token }))
+      }
+      _ => FieldError::new(self, graphql_value!({ "code": code })),
+    }
+  }
+}
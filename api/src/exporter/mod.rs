@@ -0,0 +1,270 @@
+use crate::config::Config;
+use crate::error::Error;
+use crate::logger;
+use crate::models::data_export::DataExport;
+use chrono::{TimeZone, Utc};
+use log::Level;
+use ring::{digest, hkdf, hmac};
+use serde_json::Value;
+use std::fs::{self, DirEntry};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// HKDF info for the download URL key, so it's
+/// unrelated to other keys from the same secret.
+const KEY_INFO: &[u8] = b"graphy export downloads";
+
+/// Writes users' data exports to a local directory,
+/// handing out signed download URLs that expire
+/// after `ttl`. Exports are purged once no URL
+/// for them can still be valid.
+pub struct Exporter {
+  pub dir: PathBuf,
+  pub ttl: Duration,
+  key: hmac::SigningKey,
+}
+
+impl Exporter {
+  pub fn new(config: &Config) -> Exporter {
+    Exporter {
+      dir: config.export_dir.clone(),
+      ttl: config.export_ttl,
+      key: key(&config.token_secret),
+    }
+  }
+
+  /// Creates an `Exporter` with nowhere to write,
+  /// for contexts without clients (i.e. exporting
+  /// the schema).
+  pub fn disabled() -> Exporter {
+    Exporter {
+      dir: PathBuf::new(),
+      ttl: Duration::from_secs(0),
+      key: hmac::SigningKey::new(&digest::SHA256, &[]),
+    }
+  }
+
+  /// Stores `archive`, returning a URL to download it.
+  pub fn write(&self, archive: &Value) -> Result<DataExport, Error> {
+    let id = Uuid::new_v4();
+    let expires_at = Utc::now()
+      + chrono::Duration::from_std(self.ttl).map_err(|_| Error::Str("Invalid export TTL"))?;
+
+    fs::create_dir_all(&self.dir)?;
+    fs::write(self.path(&id), serde_json::to_vec_pretty(archive)?)?;
+
+    Ok(DataExport {
+      url: format!(
+        "/exports/{}?expires={}&signature={}",
+        id,
+        expires_at.timestamp(),
+        self.sign(&id, expires_at.timestamp())
+      ),
+      expires_at,
+    })
+  }
+
+  /// Reads export `id` if `signature` was issued
+  /// for it and `expires` hasn't passed.
+  pub fn read(&self, id: &Uuid, expires: i64, signature: &str) -> Result<Vec<u8>, Error> {
+    let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+      .map_err(|_| Error::Str("Invalid export signature"))?;
+
+    hmac::verify_with_own_key(&self.key, message(id, expires).as_bytes(), &signature)
+      .map_err(|_| Error::Str("Invalid export signature"))?;
+
+    if Utc.timestamp(expires, 0) <= Utc::now() {
+      return Err(Error::Str("Export expired"));
+    }
+
+    Ok(fs::read(self.path(id))?)
+  }
+
+  /// Deletes exports older than `ttl`, returning
+  /// how many. Only files named like exports are
+  /// touched, in case `dir` is shared, and failures
+  /// are logged so the rest are still purged.
+  pub fn purge(&self) -> Result<usize, Error> {
+    let entries = match fs::read_dir(&self.dir) {
+      Ok(entries) => entries,
+      Err(_) => return Ok(0),
+    };
+    let mut purged = 0;
+
+    for entry in entries {
+      match self.purge_entry(entry) {
+        Ok(true) => purged += 1,
+        Ok(false) => {}
+        Err(err) => logger::event(
+          Level::Error,
+          "Export purge failed",
+          json!({ "dir": self.dir.display().to_string(), "error": err.to_string() }),
+        ),
+      }
+    }
+
+    Ok(purged)
+  }
+
+  /// Deletes `entry` if it's an export older than
+  /// `ttl`, returning whether it was.
+  fn purge_entry(&self, entry: io::Result<DirEntry>) -> Result<bool, Error> {
+    let entry = entry?;
+
+    if !entry.file_type()?.is_file() || !is_export(&entry.file_name().to_string_lossy()) {
+      return Ok(false);
+    }
+
+    let age = SystemTime::now()
+      .duration_since(entry.metadata()?.modified()?)
+      .unwrap_or_default();
+
+    if age < self.ttl {
+      return Ok(false);
+    }
+
+    fs::remove_file(entry.path())?;
+
+    Ok(true)
+  }
+
+  fn path(&self, id: &Uuid) -> PathBuf {
+    self.dir.join(format!("{}.json", id))
+  }
+
+  fn sign(&self, id: &Uuid, expires: i64) -> String {
+    base64::encode_config(
+      hmac::sign(&self.key, message(id, expires).as_bytes()).as_ref(),
+      base64::URL_SAFE_NO_PAD,
+    )
+  }
+}
+
+/// Whether `file_name` is `<uuid>.json`, as exports
+/// are written.
+fn is_export(file_name: &str) -> bool {
+  file_name
+    .strip_suffix(".json")
+    .is_some_and(|id| id.parse::<Uuid>().is_ok())
+}
+
+/// Key download URLs are signed with, derived from
+/// `secret` so it isn't the key tokens are signed
+/// with too.
+fn key(secret: &str) -> hmac::SigningKey {
+  let mut key = [0; 32];
+  hkdf::extract_and_expand(
+    &hmac::SigningKey::new(&digest::SHA256, &[]),
+    secret.as_bytes(),
+    KEY_INFO,
+    &mut key,
+  );
+
+  hmac::SigningKey::new(&digest::SHA256, &key)
+}
+
+fn message(id: &Uuid, expires: i64) -> String {
+  format!("{}:{}", id, expires)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+
+  fn exporter(ttl: Duration) -> Exporter {
+    Exporter {
+      dir: env::temp_dir().join(format!("graphy-exports-{}", Uuid::new_v4())),
+      ttl,
+      key: key("secret"),
+    }
+  }
+
+  /// Export ID, expiry and signature from a download URL.
+  fn parse(url: &str) -> (Uuid, i64, String) {
+    let url = url::Url::parse(&format!("http://localhost{}", url)).unwrap();
+    let id = url
+      .path_segments()
+      .unwrap()
+      .next_back()
+      .unwrap()
+      .parse()
+      .unwrap();
+    let query: std::collections::HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+    (
+      id,
+      query["expires"].parse().unwrap(),
+      query["signature"].clone(),
+    )
+  }
+
+  #[test]
+  fn test_write_read() {
+    let exporter = exporter(Duration::from_secs(60));
+    let export = exporter.write(&json!({ "profile": {} })).unwrap();
+    let (id, expires, signature) = parse(&export.url);
+    let archive: Value =
+      serde_json::from_slice(&exporter.read(&id, expires, &signature).unwrap()).unwrap();
+
+    assert_eq!(archive, json!({ "profile": {} }));
+    assert_eq!(expires, export.expires_at.timestamp());
+  }
+
+  #[test]
+  fn test_read_tampered() {
+    let exporter = exporter(Duration::from_secs(60));
+    let (id, expires, signature) = parse(&exporter.write(&json!({})).unwrap().url);
+
+    assert!(exporter.read(&id, expires + 60, &signature).is_err());
+    assert!(exporter.read(&Uuid::new_v4(), expires, &signature).is_err());
+    assert!(exporter.read(&id, expires, "c2lnbmF0dXJl").is_err());
+  }
+
+  #[test]
+  fn test_read_signed_with_secret() {
+    let exporter = exporter(Duration::from_secs(60));
+    let (id, expires, _) = parse(&exporter.write(&json!({})).unwrap().url);
+    let signature = base64::encode_config(
+      hmac::sign(
+        &hmac::SigningKey::new(&digest::SHA256, b"secret"),
+        message(&id, expires).as_bytes(),
+      )
+      .as_ref(),
+      base64::URL_SAFE_NO_PAD,
+    );
+
+    assert!(exporter.read(&id, expires, &signature).is_err());
+  }
+
+  #[test]
+  fn test_read_expired() {
+    let exporter = exporter(Duration::from_secs(0));
+    let (id, expires, signature) = parse(&exporter.write(&json!({})).unwrap().url);
+
+    assert!(exporter.read(&id, expires, &signature).is_err());
+    assert_eq!(exporter.purge().unwrap(), 1);
+  }
+
+  #[test]
+  fn test_purge_only_exports() {
+    let exporter = exporter(Duration::from_secs(0));
+    exporter.write(&json!({})).unwrap();
+    fs::write(exporter.dir.join("notes.txt"), "keep").unwrap();
+    fs::write(exporter.dir.join("other.json"), "{}").unwrap();
+    fs::create_dir(exporter.dir.join(format!("{}.json", Uuid::new_v4()))).unwrap();
+
+    assert_eq!(exporter.purge().unwrap(), 1);
+    assert_eq!(fs::read_dir(&exporter.dir).unwrap().count(), 3);
+  }
+
+  #[test]
+  fn test_is_export() {
+    assert!(is_export(&format!("{}.json", Uuid::new_v4())));
+    assert!(!is_export("passwd"));
+    assert!(!is_export("notes.json"));
+    assert!(!is_export(&Uuid::new_v4().to_string()));
+  }
+}
@@ -1,5 +1,8 @@
 use crate::error::Error;
+use crate::metrics;
+use crate::trace::{Span, SpanKind};
 use argon2::{hash_encoded, verify_encoded, Config, ThreadMode, Variant, Version};
+use uuid::Uuid;
 
 pub type HashGenerator = Box<dyn Fn(&str) -> Result<String, Error> + Send + Sync>;
 pub type HashVerifier = Box<dyn Fn(&str, &str) -> Result<bool, Error> + Send + Sync>;
@@ -7,11 +10,13 @@ pub type HashVerifier = Box<dyn Fn(&str, &str) -> Result<bool, Error> + Send + S
 pub struct Hasher {
   pub generate: HashGenerator,
   pub verify: HashVerifier,
+  dummy: String,
 }
 
 impl Hasher {
-  /// Creates a new `Hasher` instance.
-  pub fn new(salt: &str) -> Hasher {
+  /// Creates a new `Hasher` instance, failing if
+  /// passwords can't be hashed with `salt`.
+  pub fn new(salt: &str) -> Result<Hasher, Error> {
     let config = Config {
       variant: Variant::Argon2id,
       version: Version::Version13,
@@ -25,16 +30,53 @@ impl Hasher {
     };
 
     let salt_clone = salt.to_string();
+    let generate: HashGenerator = Box::new(move |password: &str| {
+      let _timer = metrics::HASH_DURATION
+        .with_label_values(&["generate"])
+        .start_timer();
+      let _span = Span::child("argon2 generate", SpanKind::Internal);
 
-    Hasher {
-      generate: Box::new(move |password: &str| {
-        Ok(hash_encoded(password.as_bytes(), salt_clone.as_bytes(), &config)?)
-      }),
+      Ok(hash_encoded(
+        password.as_bytes(),
+        salt_clone.as_bytes(),
+        &config,
+      )?)
+    });
+    // Up front, so the first unknown email isn't
+    // slower than the rest.
+    let dummy = generate(&Uuid::new_v4().to_string())?;
+
+    Ok(Hasher {
+      generate,
       verify: Box::new(move |hash: &str, password: &str| {
+        let _timer = metrics::HASH_DURATION
+          .with_label_values(&["verify"])
+          .start_timer();
+        let _span = Span::child("argon2 verify", SpanKind::Internal);
+
         Ok(verify_encoded(hash, password.as_bytes())?)
       }),
+      dummy,
+    })
+  }
+
+  /// Creates a `Hasher` that refuses to hash, for
+  /// contexts without clients (i.e. exporting the
+  /// schema).
+  pub fn disabled() -> Hasher {
+    Hasher {
+      generate: Box::new(|_: &str| Err(Error::Str("Hashing is disabled"))),
+      verify: Box::new(|_: &str, _: &str| Err(Error::Str("Hashing is disabled"))),
+      dummy: String::new(),
     }
   }
+
+  /// Hash of a random password, verified against
+  /// when there's no hash to check so that takes
+  /// as long as a wrong password.
+  pub fn dummy(&self) -> &str {
+    &self.dummy
+  }
 }
 
 #[cfg(test)]
@@ -46,7 +88,7 @@ mod tests {
     let salt = "somesalt";
     let password = "password";
 
-    let hasher = Hasher::new(salt);
+    let hasher = Hasher::new(salt).unwrap();
     let hash = (hasher.generate)(password);
 
     assert_eq!(hash.is_ok(), true);
@@ -57,10 +99,22 @@ mod tests {
     let salt = "somesalt";
     let password = "password";
 
-    let hasher = Hasher::new(salt);
+    let hasher = Hasher::new(salt).unwrap();
     let hash = &(hasher.generate)(password).unwrap();
     let verified_password = (hasher.verify)(hash, password);
 
     assert_eq!(verified_password.is_ok(), true);
   }
+
+  #[test]
+  fn test_verify_dummy_hash() {
+    let hasher = Hasher::new("somesalt").unwrap();
+
+    assert_eq!((hasher.verify)(hasher.dummy(), "password").unwrap(), false);
+  }
+
+  #[test]
+  fn test_invalid_salt() {
+    assert!(Hasher::new("").is_err());
+  }
 }
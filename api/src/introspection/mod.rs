@@ -0,0 +1,357 @@
+use crate::context::Context;
+use crate::cookie::{Cookies, Csrf};
+use crate::db::Db;
+use crate::error::Error;
+use crate::exporter::Exporter;
+use crate::hasher::Hasher;
+use crate::limiter::Limiter;
+use crate::oidc::Oidc;
+use crate::routes::graphql::schema;
+use crate::tokeniser::Tokeniser;
+use crate::trace::SpanContext;
+use crate::webauthn::Webauthn;
+use serde_json::Value;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Standard introspection query used by GraphQL
+/// tooling to fetch the full schema.
+pub const QUERY: &str = r#"
+query IntrospectionQuery {
+  __schema {
+    queryType { name }
+    mutationType { name }
+    subscriptionType { name }
+    types { ...FullType }
+    directives {
+      name
+      description
+      locations
+      args { ...InputValue }
+    }
+  }
+}
+
+fragment FullType on __Type {
+  kind
+  name
+  description
+  fields(includeDeprecated: true) {
+    name
+    description
+    args { ...InputValue }
+    type { ...TypeRef }
+    isDeprecated
+    deprecationReason
+  }
+  inputFields { ...InputValue }
+  interfaces { ...TypeRef }
+  enumValues(includeDeprecated: true) {
+    name
+    description
+    isDeprecated
+    deprecationReason
+  }
+  possibleTypes { ...TypeRef }
+}
+
+fragment InputValue on __InputValue {
+  name
+  description
+  type { ...TypeRef }
+  defaultValue
+}
+
+fragment TypeRef on __Type {
+  kind
+  name
+  ofType {
+    kind
+    name
+    ofType {
+      kind
+      name
+      ofType {
+        kind
+        name
+        ofType {
+          kind
+          name
+          ofType {
+            kind
+            name
+            ofType {
+              kind
+              name
+              ofType {
+                kind
+                name
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+}
+"#;
+
+const BUILT_IN_SCALARS: [&str; 5] = ["Boolean", "Float", "ID", "Int", "String"];
+const BUILT_IN_DIRECTIVES: [&str; 3] = ["deprecated", "include", "skip"];
+
+/// Format to export the schema in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SchemaFormat {
+  Json,
+  Sdl,
+}
+
+impl FromStr for SchemaFormat {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<SchemaFormat, Error> {
+    match s {
+      "json" => Ok(SchemaFormat::Json),
+      "sdl" => Ok(SchemaFormat::Sdl),
+      _ => Err(Error::Str("Invalid schema format")),
+    }
+  }
+}
+
+/// Exports the GraphQL schema in the given `format`.
+/// Doesn't require a database connection.
+pub fn export(format: SchemaFormat) -> Result<String, Error> {
+  let introspection = introspect()?;
+
+  Ok(match format {
+    SchemaFormat::Json => format!(
+      "{}\n",
+      serde_json::to_string_pretty(&json!({ "data": introspection }))?
+    ),
+    SchemaFormat::Sdl => to_sdl(&introspection),
+  })
+}
+
+/// Runs the introspection `QUERY` against the schema
+/// with a context that never connects to the database.
+pub fn introspect() -> Result<Value, Error> {
+  let context = Context {
+    actor: None,
+    cookies: Arc::new(Cookies::disabled()),
+    csrf: Csrf::NotRequired,
+    db: Arc::new(Db::offline()),
+    deletion_grace_period: Duration::from_secs(0),
+    exporter: Arc::new(Exporter::disabled()),
+    hasher: Arc::new(Hasher::disabled()),
+    ip: None,
+    limiter: Arc::new(Limiter::disabled()),
+    mfa_issuer: String::new(),
+    oidc: Arc::new(Oidc::new(Vec::new())),
+    request_id: String::new(),
+    scopes: Vec::new(),
+    session: None,
+    set_cookies: Mutex::new(Vec::new()),
+    tokeniser: Arc::new(Tokeniser::new("")),
+    trace: SpanContext {
+      sampled: false,
+      ..SpanContext::root()
+    },
+    user: None,
+    user_agent: None,
+    webauthn: Arc::new(Webauthn {
+      rp_id: String::new(),
+      rp_name: String::new(),
+      origin: String::new(),
+    }),
+  };
+  let (data, errors) = juniper::execute(
+    QUERY,
+    None,
+    &schema::new(),
+    &juniper::Variables::new(),
+    &context,
+  )
+  .map_err(|_| Error::Str("Failed to introspect schema"))?;
+
+  if !errors.is_empty() {
+    return Err(Error::Str("Failed to introspect schema"));
+  }
+
+  Ok(serde_json::to_value(&data)?)
+}
+
+/// Prints an introspection result as GraphQL schema
+/// definition language (SDL). Types are sorted by
+/// name so the output is stable.
+pub fn to_sdl(introspection: &Value) -> String {
+  let schema = &introspection["__schema"];
+  let mut sdl = String::new();
+
+  sdl.push_str("schema {\n");
+  for &(operation, key) in &[
+    ("query", "queryType"),
+    ("mutation", "mutationType"),
+    ("subscription", "subscriptionType"),
+  ] {
+    if let Some(name) = schema[key]["name"].as_str() {
+      let _ = writeln!(sdl, "  {}: {}", operation, name);
+    }
+  }
+  sdl.push_str("}\n");
+
+  let mut directives = list(&schema["directives"])
+    .iter()
+    .filter(|directive| !BUILT_IN_DIRECTIVES.contains(&name(directive)))
+    .collect::<Vec<_>>();
+  directives.sort_by(|a, b| name(a).cmp(name(b)));
+
+  for directive in directives {
+    sdl.push('\n');
+    description(&mut sdl, directive, "");
+    let locations = list(&directive["locations"])
+      .iter()
+      .filter_map(Value::as_str)
+      .collect::<Vec<_>>();
+    let _ = writeln!(
+      sdl,
+      "directive @{}{} on {}",
+      name(directive),
+      arguments(&directive["args"]),
+      locations.join(" | ")
+    );
+  }
+
+  let mut types = list(&schema["types"])
+    .iter()
+    .filter(|kind| !name(kind).starts_with("__") && !BUILT_IN_SCALARS.contains(&name(kind)))
+    .collect::<Vec<_>>();
+  types.sort_by(|a, b| name(a).cmp(name(b)));
+
+  for kind in types {
+    sdl.push('\n');
+    description(&mut sdl, kind, "");
+
+    match kind["kind"].as_str() {
+      Some("SCALAR") => {
+        let _ = writeln!(sdl, "scalar {}", name(kind));
+      }
+      Some("OBJECT") | Some("INTERFACE") => {
+        let keyword = if kind["kind"] == "OBJECT" {
+          "type"
+        } else {
+          "interface"
+        };
+        let interfaces = list(&kind["interfaces"])
+          .iter()
+          .map(name)
+          .collect::<Vec<_>>();
+        let implements = if interfaces.is_empty() {
+          String::new()
+        } else {
+          format!(" implements {}", interfaces.join(" & "))
+        };
+
+        let _ = writeln!(sdl, "{} {}{} {{", keyword, name(kind), implements);
+        for field in list(&kind["fields"]) {
+          description(&mut sdl, field, "  ");
+          let _ = writeln!(
+            sdl,
+            "  {}{}: {}{}",
+            name(field),
+            arguments(&field["args"]),
+            type_ref(&field["type"]),
+            deprecated(field)
+          );
+        }
+        sdl.push_str("}\n");
+      }
+      Some("UNION") => {
+        let members = list(&kind["possibleTypes"])
+          .iter()
+          .map(name)
+          .collect::<Vec<_>>();
+        let _ = writeln!(sdl, "union {} = {}", name(kind), members.join(" | "));
+      }
+      Some("ENUM") => {
+        let _ = writeln!(sdl, "enum {} {{", name(kind));
+        for value in list(&kind["enumValues"]) {
+          description(&mut sdl, value, "  ");
+          let _ = writeln!(sdl, "  {}{}", name(value), deprecated(value));
+        }
+        sdl.push_str("}\n");
+      }
+      Some("INPUT_OBJECT") => {
+        let _ = writeln!(sdl, "input {} {{", name(kind));
+        for field in list(&kind["inputFields"]) {
+          description(&mut sdl, field, "  ");
+          let _ = writeln!(sdl, "  {}", input_value(field));
+        }
+        sdl.push_str("}\n");
+      }
+      _ => (),
+    }
+  }
+
+  sdl
+}
+
+fn list(value: &Value) -> &[Value] {
+  value.as_array().map(Vec::as_slice).unwrap_or(&[])
+}
+
+fn name(value: &Value) -> &str {
+  value["name"].as_str().unwrap_or("")
+}
+
+fn type_ref(value: &Value) -> String {
+  match value["kind"].as_str() {
+    Some("NON_NULL") => format!("{}!", type_ref(&value["ofType"])),
+    Some("LIST") => format!("[{}]", type_ref(&value["ofType"])),
+    _ => name(value).to_string(),
+  }
+}
+
+fn input_value(value: &Value) -> String {
+  match value["defaultValue"].as_str() {
+    Some(default) => format!(
+      "{}: {} = {}",
+      name(value),
+      type_ref(&value["type"]),
+      default
+    ),
+    None => format!("{}: {}", name(value), type_ref(&value["type"])),
+  }
+}
+
+fn arguments(args: &Value) -> String {
+  let args = list(args).iter().map(input_value).collect::<Vec<_>>();
+
+  if args.is_empty() {
+    String::new()
+  } else {
+    format!("({})", args.join(", "))
+  }
+}
+
+fn deprecated(value: &Value) -> String {
+  if value["isDeprecated"] != true {
+    return String::new();
+  }
+
+  match value["deprecationReason"].as_str() {
+    Some(reason) => format!(" @deprecated(reason: {})", Value::from(reason)),
+    None => " @deprecated".to_string(),
+  }
+}
+
+fn description(sdl: &mut String, value: &Value, indent: &str) {
+  if let Some(description) = value["description"].as_str() {
+    let _ = writeln!(sdl, "{}\"\"\"", indent);
+    for line in description.lines() {
+      let _ = writeln!(sdl, "{}{}", indent, line.replace("\"\"\"", "\\\"\"\""));
+    }
+    let _ = writeln!(sdl, "{}\"\"\"", indent);
+  }
+}
@@ -1,63 +1,231 @@
 extern crate argon2;
+extern crate base64;
 extern crate chrono;
 #[macro_use]
 extern crate diesel;
 #[macro_use]
 extern crate diesel_migrations;
+extern crate futures;
+extern crate hyper;
 extern crate jsonwebtoken as jwt;
 #[macro_use]
 extern crate juniper;
 extern crate clap;
 extern crate juniper_warp;
+#[macro_use]
+extern crate lazy_static;
+extern crate log;
+extern crate native_tls;
+#[macro_use]
+extern crate prometheus;
 extern crate r2d2;
+extern crate ring;
 #[macro_use]
 extern crate serde;
+#[macro_use]
+extern crate serde_json;
+extern crate tokio;
+extern crate tokio_signal;
+extern crate tokio_threadpool;
+extern crate tokio_tls;
+extern crate untrusted;
+extern crate url;
 extern crate uuid;
 extern crate warp;
 
 pub mod config;
 mod context;
+pub mod cookie;
 pub mod db;
 pub mod error;
+pub mod exporter;
 pub mod hasher;
+pub mod introspection;
+pub mod limiter;
+pub mod logger;
+pub mod metrics;
 pub mod models;
+pub mod oidc;
+pub mod policy;
+pub mod retention;
 mod routes;
+pub mod scope;
+pub mod shutdown;
+pub mod tls;
 pub mod tokeniser;
+pub mod totp;
+pub mod trace;
+pub mod webauthn;
 
 use config::Config;
 use db::Db;
 use error::Error;
+use exporter::Exporter;
+use futures::sync::oneshot;
+use futures::{Future, Stream};
 use hasher::Hasher;
+use limiter::Limiter;
+use log::Level;
+use models::user::User;
+use oidc::Oidc;
+use routes::access::access;
+use routes::cors::cors;
+use routes::export::export;
 use routes::graphql::{context, graphql};
+use routes::health::health;
+use routes::ide::ide;
+use routes::metrics::metrics;
 use std::sync::Arc;
+use std::time::Duration;
+use tls::Tls;
 use tokeniser::Tokeniser;
+use tokio::net::TcpListener;
+use tokio::reactor::Handle;
+use tokio::runtime::Runtime;
 use warp::Filter;
+use webauthn::Webauthn;
 
+/// How often soft deleted rows past their grace
+/// period and expired data exports are purged.
+const PURGE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Runs the API server until `SIGINT` or `SIGTERM`,
+/// then stops accepting connections, waits up to
+/// `Config::shutdown_timeout` for in-flight requests
+/// and closes the database connections.
 pub fn run(config: &Config) -> Result<(), Error> {
-  let address = config.address;
-  warp::serve(server(config)?).run((address, 8000));
+  logger::init(config.log_level)?;
+  trace::init(&config.trace_exporter)?;
+
+  let db = Arc::new(db(config)?);
+  let server = warp::serve(routes(config, db.clone())?);
+  let (stop, stopped) = oneshot::channel::<()>();
+  let stopped = stopped.map_err(|_| ());
+  let (stop_purge, purge_stopped) = oneshot::channel::<()>();
+  let mut runtime = Runtime::new()?;
+
+  runtime.spawn(
+    shutdown::until(
+      retention::purge_every(
+        db.clone(),
+        Arc::new(Exporter::new(config)),
+        Arc::new(Limiter::new(config)),
+        config.deletion_grace_period,
+        PURGE_PERIOD,
+      ),
+      purge_stopped,
+    )
+    .for_each(|_| Ok(())),
+  );
+
+  match (&config.tls_cert, &config.tls_key) {
+    (Some(cert), Some(key)) => {
+      let tls = Arc::new(Tls::new(cert, key)?);
+      let listener = TcpListener::from_std(
+        std::net::TcpListener::bind(config.listen)?,
+        &Handle::default(),
+      )?;
+
+      runtime.spawn(server.serve_incoming(shutdown::until(tls.incoming(listener), stopped)));
+    }
+    _ => {
+      let (_, serving) = server.bind_with_graceful_shutdown(config.listen, stopped);
+
+      runtime.spawn(serving);
+    }
+  }
+
+  runtime.block_on(shutdown::signal())?;
+  let _ = stop.send(());
+  let _ = stop_purge.send(());
+  // Idle keep-alive connections on the TLS listener
+  // aren't told to close, so may hold the drain
+  // open until the timeout.
+  if !shutdown::drain(runtime, config.shutdown_timeout) {
+    logger::event(
+      Level::Warn,
+      "Shutdown timed out",
+      json!({ "timeout_secs": config.shutdown_timeout.as_secs() }),
+    );
+  }
+
+  // Dropping the last reference closes the pooled
+  // connections, unless tasks abandoned by a timed
+  // out drain still hold one, in which case they're
+  // closed as the process exits.
+  drop(db);
+  trace::shutdown();
 
   Ok(())
 }
 
+/// Gives the user with `email` `role`, i.e. to set
+/// up the first superadmin.
+pub fn assign_role(config: &Config, email: &str, role: &str) -> Result<(), Error> {
+  let connection = db(config)?.connect()?;
+
+  if User::assign_role_by_email(&connection, email, role)? {
+    Ok(())
+  } else {
+    Err(Error::Str("User not found"))
+  }
+}
+
 pub fn server(
   config: &Config,
 ) -> Result<impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection>, Error> {
-  let db = Arc::new(Db::new(
+  routes(config, Arc::new(db(config)?))
+}
+
+fn db(config: &Config) -> Result<Db, Error> {
+  Db::new(
     &config.db_user,
     &config.db_password,
     &config.db_name,
     &config.db_server,
     config.testing,
-  )?);
-  let hasher = Arc::new(Hasher::new(&config.hash_salt));
+  )
+}
+
+fn routes(
+  config: &Config,
+  db: Arc<Db>,
+) -> Result<impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection>, Error> {
+  let hasher = Arc::new(Hasher::new(&config.hash_salt)?);
+  let limiter = Arc::new(Limiter::new(config));
   let tokeniser = Arc::new(Tokeniser::new(&config.token_secret));
-  let log = warp::log("warp_server");
+  let webauthn = Arc::new(Webauthn::new(config));
+  let oidc = Arc::new(Oidc::new(config.oidc_providers.clone()));
 
   Ok(
-    warp::get2()
-      .and(warp::path::end().and(juniper_warp::graphiql_filter("/graphql")))
-      .or(warp::path("graphql").and(graphql(context(db, hasher, tokeniser))))
-      .with(log),
+    access(
+      warp::path("graphql")
+        .and(warp::path::end())
+        .and(cors(
+          config,
+          graphql(
+            context(
+              db.clone(),
+              hasher,
+              limiter,
+              tokeniser.clone(),
+              webauthn,
+              oidc,
+              config,
+            ),
+            config.introspection,
+          ),
+        ))
+        .or(health(db.clone(), tokeniser))
+        .unify()
+        .or(export(Arc::new(Exporter::new(config))))
+        .unify()
+        .or(metrics(db))
+        .unify()
+        .or(ide(config.ide, &config.ide_path, "/graphql"))
+        .unify()
+        .boxed(),
+    )
+    .with(warp::log::custom(metrics::observe)),
   )
 }
@@ -0,0 +1,313 @@
+use crate::config::Config;
+use crate::db::Connection;
+use crate::error::Error;
+use crate::logger;
+use crate::metrics;
+use crate::models::schema::{login_failures, rate_limits};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_query;
+use diesel::sql_types::{Integer, Timestamptz, Varchar};
+use log::Level;
+use ring::digest;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Longest an account is locked for, however many
+/// times logging in has failed.
+const MAX_LOCKOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Rate limited mutations, counted separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+  Login,
+  CreateUser,
+  VerifyMfa,
+  PasskeyLogin,
+  OidcLogin,
+}
+
+impl Action {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Action::Login => "login",
+      Action::CreateUser => "create_user",
+      Action::VerifyMfa => "verify_mfa",
+      Action::PasskeyLogin => "passkey_login",
+      Action::OidcLogin => "oidc_login",
+    }
+  }
+}
+
+/// Limits attempts per IP address and per account
+/// within a fixed window, and locks accounts out
+/// for longer after each repeated login failure.
+/// Counts are kept in the database, so they're
+/// shared between replicas.
+pub struct Limiter {
+  /// Attempts per IP address per window, `0` for no limit.
+  pub ip: u32,
+  /// Attempts per account per window, `0` for no limit.
+  pub account: u32,
+  pub window: Duration,
+  /// Login failures before locking, `0` for no lockout.
+  pub lockout_threshold: u32,
+  /// First lockout, doubled for each further failure.
+  pub lockout_duration: Duration,
+}
+
+#[derive(QueryableByName)]
+struct Window {
+  #[sql_type = "Integer"]
+  attempts: i32,
+  #[sql_type = "Timestamptz"]
+  window_start: DateTime<Utc>,
+}
+
+impl Limiter {
+  /// Creates a new `Limiter` from the `rate_limit`
+  /// and `lockout` settings in `config`.
+  pub fn new(config: &Config) -> Limiter {
+    Limiter {
+      ip: config.rate_limit_ip,
+      account: config.rate_limit_account,
+      window: config.rate_limit_window,
+      lockout_threshold: config.lockout_threshold,
+      lockout_duration: config.lockout_duration,
+    }
+  }
+
+  /// Creates a `Limiter` that never refuses, for
+  /// contexts without clients (i.e. exporting the
+  /// schema).
+  pub fn disabled() -> Limiter {
+    Limiter {
+      ip: 0,
+      account: 0,
+      window: Duration::from_secs(0),
+      lockout_threshold: 0,
+      lockout_duration: Duration::from_secs(0),
+    }
+  }
+
+  /// Counts an attempt at `action`, refusing it once
+  /// `ip` or `account` has exceeded its limit for
+  /// the current window.
+  pub fn attempt(
+    &self,
+    connection: &Connection,
+    action: Action,
+    ip: Option<IpAddr>,
+    account: Option<&str>,
+  ) -> Result<(), Error> {
+    if let Some(ip) = ip {
+      self.count(connection, action, &format!("ip:{}", ip), self.ip)?;
+    }
+
+    if let Some(account) = account {
+      let key = format!("account:{}", normalise(account));
+      self.count(connection, action, &key, self.account)?;
+    }
+
+    Ok(())
+  }
+
+  /// Refuses logging in to `account` while it's locked.
+  pub fn unlocked(&self, connection: &Connection, account: &str) -> Result<(), Error> {
+    let locked_until = login_failures::table
+      .find(hash(&normalise(account)))
+      .select(login_failures::locked_until)
+      .first::<Option<DateTime<Utc>>>(connection)
+      .optional()?
+      .and_then(|locked_until| locked_until);
+
+    match locked_until {
+      Some(locked_until) if locked_until > Utc::now() => {
+        Err(Error::Locked(retry_after(locked_until)))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Records a failed login to `account`, locking it
+  /// once failures reach `lockout_threshold`.
+  pub fn failed(&self, connection: &Connection, account: &str) -> Result<(), Error> {
+    let account = hash(&normalise(account));
+    let now = Utc::now();
+    let failures = diesel::insert_into(login_failures::table)
+      .values((
+        login_failures::key_hash.eq(&account),
+        login_failures::failures.eq(1),
+        login_failures::last_failed_at.eq(now),
+      ))
+      .on_conflict(login_failures::key_hash)
+      .do_update()
+      .set((
+        login_failures::failures.eq(login_failures::failures + 1),
+        login_failures::last_failed_at.eq(now),
+      ))
+      .returning(login_failures::failures)
+      .get_result::<i32>(connection)?;
+
+    if self.lockout_threshold == 0 || (failures as u32) < self.lockout_threshold {
+      return Ok(());
+    }
+
+    let lockout = lockout(
+      self.lockout_duration,
+      failures as u32 - self.lockout_threshold,
+    );
+    let locked_until =
+      now + chrono::Duration::from_std(lockout).map_err(|_| Error::Str("Invalid lockout"))?;
+
+    diesel::update(login_failures::table.find(&account))
+      .set(login_failures::locked_until.eq(locked_until))
+      .execute(connection)?;
+
+    logger::event(
+      Level::Warn,
+      "Account locked",
+      json!({ "failures": failures, "seconds": lockout.as_secs() }),
+    );
+
+    Ok(())
+  }
+
+  /// Clears login failures for `account`.
+  pub fn succeeded(&self, connection: &Connection, account: &str) -> Result<(), Error> {
+    diesel::delete(login_failures::table.find(hash(&normalise(account)))).execute(connection)?;
+
+    Ok(())
+  }
+
+  /// Deletes rate limit windows that have ended and
+  /// login failures too old to count towards a
+  /// lockout, which can't still be locked either,
+  /// returning how many. Both are keyed on whatever
+  /// clients send, so would otherwise grow without
+  /// bound.
+  pub fn purge(&self, connection: &Connection) -> Result<usize, Error> {
+    let now = Utc::now();
+    let window =
+      chrono::Duration::from_std(self.window).map_err(|_| Error::Str("Invalid window"))?;
+    let lockout =
+      chrono::Duration::from_std(MAX_LOCKOUT).map_err(|_| Error::Str("Invalid lockout"))?;
+
+    Ok(
+      diesel::delete(rate_limits::table.filter(rate_limits::window_start.le(now - window)))
+        .execute(connection)?
+        + diesel::delete(
+          login_failures::table.filter(login_failures::last_failed_at.le(now - lockout)),
+        )
+        .execute(connection)?,
+    )
+  }
+
+  fn count(
+    &self,
+    connection: &Connection,
+    action: Action,
+    key: &str,
+    limit: u32,
+  ) -> Result<(), Error> {
+    if limit == 0 {
+      return Ok(());
+    }
+
+    let length =
+      chrono::Duration::from_std(self.window).map_err(|_| Error::Str("Invalid window"))?;
+    let now = Utc::now();
+
+    // Upserts so concurrent attempts are all counted.
+    let window = sql_query(
+      "INSERT INTO rate_limits (key, attempts, window_start) VALUES ($1, 1, $2) \
+       ON CONFLICT (key) DO UPDATE SET \
+       attempts = CASE WHEN rate_limits.window_start > $3 \
+       THEN rate_limits.attempts + 1 ELSE 1 END, \
+       window_start = CASE WHEN rate_limits.window_start > $3 \
+       THEN rate_limits.window_start ELSE $2 END \
+       RETURNING attempts, window_start",
+    )
+    .bind::<Varchar, _>(format!("{}:{}", action.as_str(), hash(key)))
+    .bind::<Timestamptz, _>(now)
+    .bind::<Timestamptz, _>(now - length)
+    .get_result::<Window>(connection)?;
+
+    if window.attempts as u32 <= limit {
+      return Ok(());
+    }
+
+    metrics::RATE_LIMITED
+      .with_label_values(&[action.as_str()])
+      .inc();
+
+    let reset = window.window_start + length;
+
+    Err(Error::RateLimited(retry_after(reset)))
+  }
+}
+
+/// Emails are compared case-insensitively, so
+/// `A@b.c` and `a@b.c` share their limits.
+fn normalise(account: &str) -> String {
+  account.trim().to_lowercase()
+}
+
+/// Fixed length key for `value`, which clients
+/// choose, so it fits the key columns and emails
+/// aren't stored.
+fn hash(value: &str) -> String {
+  base64::encode_config(
+    digest::digest(&digest::SHA256, value.as_bytes()).as_ref(),
+    base64::URL_SAFE_NO_PAD,
+  )
+}
+
+/// Seconds from now until `time`, at least one.
+fn retry_after(time: DateTime<Utc>) -> u64 {
+  let millis = (time - Utc::now()).num_milliseconds().max(1) as u64;
+
+  millis.div_ceil(1000)
+}
+
+/// Lockout after `failures` beyond the threshold,
+/// doubling from `duration` up to `MAX_LOCKOUT`.
+fn lockout(duration: Duration, failures: u32) -> Duration {
+  duration
+    .checked_mul(2u32.saturating_pow(failures))
+    .map_or(MAX_LOCKOUT, |lockout| lockout.min(MAX_LOCKOUT))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_lockout_doubles() {
+    let duration = Duration::from_secs(60);
+
+    assert_eq!(lockout(duration, 0), duration);
+    assert_eq!(lockout(duration, 1), Duration::from_secs(120));
+    assert_eq!(lockout(duration, 3), Duration::from_secs(480));
+  }
+
+  #[test]
+  fn test_lockout_capped() {
+    assert_eq!(lockout(Duration::from_secs(60), 20), MAX_LOCKOUT);
+    assert_eq!(lockout(Duration::from_secs(60), 64), MAX_LOCKOUT);
+  }
+
+  #[test]
+  fn test_hash() {
+    let long = format!("{}@example.com", "a".repeat(300));
+
+    assert_eq!(hash(&long).len(), 43);
+    assert_eq!(hash("a@b.c"), hash("a@b.c"));
+    assert_ne!(hash("a@b.c"), hash("b@b.c"));
+  }
+
+  #[test]
+  fn test_normalise() {
+    assert_eq!(normalise(" Test@Example.com "), "test@example.com");
+  }
+}
@@ -0,0 +1,140 @@
+use crate::error::Error;
+use chrono::{SecondsFormat, Utc};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde_json::{Map, Value};
+use std::io::{self, Write};
+
+/// Keys whose values are never logged, matched
+/// case-insensitively anywhere in the key.
+const REDACTED_KEYS: [&str; 5] = ["authorization", "cookie", "password", "secret", "token"];
+const REDACTED: &str = "[REDACTED]";
+
+/// Writes log records to stderr as JSON lines.
+pub struct Logger {
+  level: LevelFilter,
+}
+
+impl Log for Logger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= self.level
+  }
+
+  fn log(&self, record: &Record) {
+    if self.enabled(record.metadata()) {
+      write(
+        record.level(),
+        record.target(),
+        &record.args().to_string(),
+        Map::new(),
+      );
+    }
+  }
+
+  fn flush(&self) {
+    let _ = io::stderr().flush();
+  }
+}
+
+/// Installs the JSON logger for records up to
+/// `level`, including those from dependencies.
+pub fn init(level: LevelFilter) -> Result<(), Error> {
+  log::set_boxed_logger(Box::new(Logger { level }))
+    .map_err(|_| Error::Str("Logger already initialised"))?;
+  log::set_max_level(level);
+
+  Ok(())
+}
+
+/// Logs `message` with structured `fields`, i.e.
+/// `json!({ "request_id": id })`. Secrets within
+/// `fields` are redacted.
+pub fn event(level: Level, message: &str, fields: Value) {
+  if level > log::max_level() {
+    return;
+  }
+
+  let fields = match fields {
+    Value::Object(fields) => fields,
+    _ => Map::new(),
+  };
+
+  write(level, env!("CARGO_PKG_NAME"), message, fields);
+}
+
+fn write(level: Level, target: &str, message: &str, fields: Map<String, Value>) {
+  let mut line = Map::new();
+  line.insert(
+    "time".to_string(),
+    Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+  );
+  line.insert("level".to_string(), Value::from(level.to_string()));
+  line.insert("target".to_string(), Value::from(target));
+  line.insert("message".to_string(), Value::from(message));
+  line.extend(fields);
+
+  let mut line = Value::Object(line);
+  redact(&mut line);
+
+  let stderr = io::stderr();
+  let _ = writeln!(stderr.lock(), "{}", line);
+}
+
+/// Replaces the values of secret keys (passwords,
+/// tokens, etc) throughout `value`.
+pub fn redact(value: &mut Value) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map.iter_mut() {
+        let key = key.to_lowercase();
+
+        if REDACTED_KEYS.iter().any(|redacted| key.contains(redacted)) {
+          *value = Value::from(REDACTED);
+        } else {
+          redact(value);
+        }
+      }
+    }
+    Value::Array(values) => values.iter_mut().for_each(redact),
+    _ => (),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_redact() {
+    let mut value = json!({
+      "request_id": "abc",
+      "Authorization": "Bearer abc",
+      "variables": {
+        "user": { "email": "a@b.c", "password": "secret" },
+        "tokens": [{ "refreshToken": "abc" }],
+      },
+    });
+
+    redact(&mut value);
+
+    assert_eq!(
+      value,
+      json!({
+        "request_id": "abc",
+        "Authorization": REDACTED,
+        "variables": {
+          "user": { "email": "a@b.c", "password": REDACTED },
+          "tokens": REDACTED,
+        },
+      })
+    );
+  }
+
+  #[test]
+  fn test_redact_nested_arrays() {
+    let mut value = json!([{ "hash_secret": "abc" }, "password"]);
+
+    redact(&mut value);
+
+    assert_eq!(value, json!([{ "hash_secret": REDACTED }, "password"]));
+  }
+}
@@ -1,10 +1,20 @@
 extern crate api;
 
-use api::{config::Config, error::Error, run};
+use api::{assign_role, config::Command, error::Error, introspection, run};
 
 /// Entry point for binary only.
 /// Use `lib.rs` for testing.
 fn main() -> Result<(), Error> {
-  let config = Config::from_args()?;
-  run(&config)
+  match Command::from_args()? {
+    Command::Serve(config) => run(&config),
+    Command::Schema(format) => {
+      print!("{}", introspection::export(format)?);
+      Ok(())
+    }
+    Command::AssignRole {
+      config,
+      email,
+      role,
+    } => assign_role(&config, &email, &role),
+  }
 }
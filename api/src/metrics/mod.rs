@@ -0,0 +1,89 @@
+use crate::db::Db;
+use crate::error::Error;
+use prometheus::{
+  exponential_buckets, Encoder, Histogram, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use warp::log::Info;
+
+lazy_static! {
+  pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+    "http_requests_total",
+    "HTTP requests by response status",
+    &["status"]
+  )
+  .unwrap();
+  pub static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+    "http_request_duration_seconds",
+    "HTTP request latency by response status",
+    &["status"]
+  )
+  .unwrap();
+  pub static ref GRAPHQL_OPERATIONS: IntCounterVec = register_int_counter_vec!(
+    "graphql_operations_total",
+    "GraphQL operations by type",
+    &["type"]
+  )
+  .unwrap();
+  pub static ref RESOLVER_ERRORS: IntCounterVec = register_int_counter_vec!(
+    "graphql_resolver_errors_total",
+    "GraphQL resolver errors by code",
+    &["code"]
+  )
+  .unwrap();
+  pub static ref DB_POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+    "db_pool_connections",
+    "Database pool connections by state",
+    &["state"]
+  )
+  .unwrap();
+  pub static ref DB_POOL_WAIT: Histogram = register_histogram!(
+    "db_pool_wait_seconds",
+    "Time spent waiting for a database pool connection",
+    exponential_buckets(0.0001, 4.0, 10).unwrap()
+  )
+  .unwrap();
+  pub static ref HASH_DURATION: HistogramVec = register_histogram_vec!(
+    "argon2_duration_seconds",
+    "Argon2 password hashing time by operation",
+    &["operation"]
+  )
+  .unwrap();
+  pub static ref LOGINS: IntCounterVec =
+    register_int_counter_vec!("logins_total", "Login attempts by result", &["result"]).unwrap();
+  pub static ref RATE_LIMITED: IntCounterVec = register_int_counter_vec!(
+    "rate_limited_total",
+    "Attempts refused by rate limits by action",
+    &["action"]
+  )
+  .unwrap();
+}
+
+/// Records a finished HTTP request, for use
+/// with `warp::log::custom`.
+pub fn observe(info: Info) {
+  let status = info.status().as_u16().to_string();
+
+  HTTP_REQUESTS.with_label_values(&[&status]).inc();
+  HTTP_REQUEST_DURATION
+    .with_label_values(&[&status])
+    .observe(info.elapsed().as_secs_f64());
+}
+
+/// Gathers all metrics in the Prometheus text
+/// format, sampling the `db` pool first.
+pub fn export(db: &Db) -> Result<String, Error> {
+  let state = db.state();
+  let idle = i64::from(state.idle_connections);
+
+  DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+  DB_POOL_CONNECTIONS
+    .with_label_values(&["in_use"])
+    .set(i64::from(state.connections) - idle);
+
+  let mut buffer = Vec::new();
+  TextEncoder::new()
+    .encode(&prometheus::gather(), &mut buffer)
+    .map_err(|_| Error::Str("Failed to encode metrics"))?;
+
+  String::from_utf8(buffer).map_err(|_| Error::Str("Failed to encode metrics"))
+}
@@ -0,0 +1,169 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::models::schema::api_tokens;
+use crate::scope::Scope;
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use uuid::Uuid;
+
+/// Starts every API token, so they can be told
+/// apart from JWTs and spotted by secret scanners.
+pub const PREFIX: &str = "gph_";
+
+/// A long-lived token for scripts and CI jobs to
+/// act as a user, revocable at any time. Only its
+/// hash is stored, so it's shown once on creation.
+#[derive(GraphQLObject, Queryable)]
+pub struct ApiToken {
+  pub id: Uuid,
+  pub name: String,
+  pub scopes: Vec<String>,
+  /// Never expires when null.
+  pub expires_at: Option<DateTime<Utc>>,
+  pub last_used_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(GraphQLInputObject)]
+pub struct ApiTokenCreate {
+  pub name: String,
+  pub scopes: Vec<String>,
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A newly created API token, with the only
+/// copy of its secret.
+#[derive(GraphQLObject)]
+pub struct ApiTokenCreated {
+  pub token: String,
+  pub api_token: ApiToken,
+}
+
+type Columns = (
+  api_tokens::id,
+  api_tokens::name,
+  api_tokens::scopes,
+  api_tokens::expires_at,
+  api_tokens::last_used_at,
+  api_tokens::created_at,
+);
+
+const COLUMNS: Columns = (
+  api_tokens::id,
+  api_tokens::name,
+  api_tokens::scopes,
+  api_tokens::expires_at,
+  api_tokens::last_used_at,
+  api_tokens::created_at,
+);
+
+impl ApiToken {
+  /// Creates a token granted `scopes`, parsed
+  /// from `api_token`.
+  pub fn create(
+    connection: &Connection,
+    user_id: &Uuid,
+    api_token: &ApiTokenCreate,
+    scopes: &[Scope],
+  ) -> Result<ApiTokenCreated, Error> {
+    if api_token
+      .expires_at
+      .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+      return Err(Error::Str("API token expiry must be in the future"));
+    }
+
+    let mut secret = [0; 32];
+    SystemRandom::new()
+      .fill(&mut secret)
+      .map_err(|_| Error::Str("Failed to generate random bytes"))?;
+    let token = format!(
+      "{}{}",
+      PREFIX,
+      base64::encode_config(&secret, base64::URL_SAFE_NO_PAD)
+    );
+
+    let created = diesel::insert_into(api_tokens::table)
+      .values((
+        api_tokens::id.eq(Uuid::new_v4()),
+        api_tokens::user_id.eq(user_id),
+        api_tokens::name.eq(&api_token.name),
+        api_tokens::scopes.eq(
+          scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>(),
+        ),
+        api_tokens::token_hash.eq(hash(&token)),
+        api_tokens::expires_at.eq(api_token.expires_at),
+      ))
+      .returning(COLUMNS)
+      .get_result::<ApiToken>(connection)?;
+
+    Ok(ApiTokenCreated {
+      token,
+      api_token: created,
+    })
+  }
+
+  pub fn list(connection: &Connection, user_id: &Uuid) -> Result<Vec<ApiToken>, Error> {
+    Ok(
+      api_tokens::table
+        .filter(api_tokens::user_id.eq(user_id))
+        .order(api_tokens::created_at)
+        .select(COLUMNS)
+        .load::<ApiToken>(connection)?,
+    )
+  }
+
+  pub fn revoke(connection: &Connection, user_id: &Uuid, id: &Uuid) -> Result<bool, Error> {
+    Ok(
+      diesel::delete(
+        api_tokens::table
+          .find(id)
+          .filter(api_tokens::user_id.eq(user_id)),
+      )
+      .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// User an unexpired `token` belongs to and its
+  /// scopes, recording that it was used.
+  pub fn authenticate(connection: &Connection, token: &str) -> Result<(Uuid, Vec<Scope>), Error> {
+    let now = Utc::now();
+
+    diesel::update(
+      api_tokens::table
+        .filter(api_tokens::token_hash.eq(hash(token)))
+        .filter(
+          api_tokens::expires_at
+            .is_null()
+            .or(api_tokens::expires_at.gt(now)),
+        ),
+    )
+    .set(api_tokens::last_used_at.eq(now))
+    .returning((api_tokens::user_id, api_tokens::scopes))
+    .get_result::<(Uuid, Vec<String>)>(connection)
+    .optional()?
+    .map(|(user_id, scopes)| {
+      let scopes = scopes
+        .iter()
+        .filter_map(|scope| Scope::parse(scope))
+        .collect();
+
+      (user_id, scopes)
+    })
+    .ok_or(Error::Str("Unknown or expired API token"))
+  }
+}
+
+/// Tokens are random, so a fast hash is enough
+/// and can be looked up directly.
+fn hash(token: &str) -> Vec<u8> {
+  digest::digest(&digest::SHA256, token.as_bytes())
+    .as_ref()
+    .to_vec()
+}
@@ -0,0 +1,228 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::logger;
+use crate::models::schema::audit_events;
+use crate::policy::{self, Action, Resource};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+
+/// A record of who did what to whom, kept after
+/// either is deleted. Events can't be changed or
+/// removed once recorded.
+#[derive(Insertable)]
+#[table_name = "audit_events"]
+pub struct AuditEventCreate<'a> {
+  pub id: Uuid,
+  pub actor_id: Option<Uuid>,
+  /// Admin acting as `actor_id`, when impersonating.
+  pub impersonator_id: Option<Uuid>,
+  pub action: &'a str,
+  pub target_id: Option<Uuid>,
+  pub ip: Option<String>,
+  pub user_agent: Option<&'a str>,
+  pub request_id: &'a str,
+  /// Anything else worth knowing, i.e. a reason.
+  pub details: Value,
+  /// Fields changed, see `diff`.
+  pub diff: Value,
+}
+
+#[derive(GraphQLObject)]
+pub struct AuditEvent {
+  pub id: Uuid,
+  pub actor_id: Option<Uuid>,
+  /// Admin acting as the actor, when impersonating.
+  pub impersonator_id: Option<Uuid>,
+  pub action: String,
+  pub target_id: Option<Uuid>,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  pub request_id: String,
+  /// JSON object.
+  pub details: String,
+  /// JSON object of fields changed, each with
+  /// `from` and `to`.
+  pub diff: String,
+  pub created_at: DateTime<Utc>,
+}
+
+/// Narrows `AuditEvent::list`, every field is optional.
+#[derive(Default, GraphQLInputObject)]
+pub struct AuditEventFilter {
+  pub actor_id: Option<Uuid>,
+  pub impersonator_id: Option<Uuid>,
+  pub target_id: Option<Uuid>,
+  pub action: Option<String>,
+  /// Events from this time.
+  pub since: Option<DateTime<Utc>>,
+  /// Events before this time.
+  pub until: Option<DateTime<Utc>>,
+}
+
+/// Most events listed at once.
+const MAX_LIST: i64 = 100;
+
+impl AuditEvent {
+  pub fn record(connection: &Connection, event: &AuditEventCreate) -> Result<(), Error> {
+    diesel::insert_into(audit_events::table)
+      .values(event)
+      .execute(connection)?;
+
+    Ok(())
+  }
+
+  /// Events matching `filter`, newest first, for admins.
+  pub fn list(
+    connection: &Connection,
+    admin_id: &Uuid,
+    filter: &AuditEventFilter,
+    limit: i64,
+    offset: i64,
+  ) -> Result<Vec<AuditEvent>, Error> {
+    policy::authorize(connection, admin_id, Action::List, Resource::AuditEvents)?;
+
+    let mut query = audit_events::table
+      .select((
+        audit_events::id,
+        audit_events::actor_id,
+        audit_events::impersonator_id,
+        audit_events::action,
+        audit_events::target_id,
+        audit_events::ip,
+        audit_events::user_agent,
+        audit_events::request_id,
+        audit_events::details,
+        audit_events::diff,
+        audit_events::created_at,
+      ))
+      .order((audit_events::created_at.desc(), audit_events::id))
+      .limit(limit.clamp(0, MAX_LIST))
+      .offset(offset.max(0))
+      .into_boxed();
+
+    if let Some(actor_id) = filter.actor_id {
+      query = query.filter(audit_events::actor_id.eq(actor_id));
+    }
+
+    if let Some(impersonator_id) = filter.impersonator_id {
+      query = query.filter(audit_events::impersonator_id.eq(impersonator_id));
+    }
+
+    if let Some(target_id) = filter.target_id {
+      query = query.filter(audit_events::target_id.eq(target_id));
+    }
+
+    if let Some(ref action) = filter.action {
+      query = query.filter(audit_events::action.eq(action));
+    }
+
+    if let Some(since) = filter.since {
+      query = query.filter(audit_events::created_at.ge(since));
+    }
+
+    if let Some(until) = filter.until {
+      query = query.filter(audit_events::created_at.lt(until));
+    }
+
+    Ok(
+      query
+        .load::<(
+          Uuid,
+          Option<Uuid>,
+          Option<Uuid>,
+          String,
+          Option<Uuid>,
+          Option<String>,
+          Option<String>,
+          String,
+          Value,
+          Value,
+          DateTime<Utc>,
+        )>(connection)?
+        .into_iter()
+        .map(
+          |(
+            id,
+            actor_id,
+            impersonator_id,
+            action,
+            target_id,
+            ip,
+            user_agent,
+            request_id,
+            details,
+            diff,
+            created_at,
+          )| {
+            AuditEvent {
+              id,
+              actor_id,
+              impersonator_id,
+              action,
+              target_id,
+              ip,
+              user_agent,
+              request_id,
+              details: details.to_string(),
+              diff: diff.to_string(),
+              created_at,
+            }
+          },
+        )
+        .collect(),
+    )
+  }
+}
+
+/// Fields of `after` that differ from `before`, as
+/// `{ "field": { "from": ..., "to": ... } }`. Fields
+/// missing from `after` are unchanged. Secrets (i.e.
+/// passwords) are redacted, but still show they
+/// changed.
+pub fn diff(before: &Value, after: &Value) -> Value {
+  let mut changes = Map::new();
+
+  if let Value::Object(after) = after {
+    for (field, to) in after {
+      let from = before.get(field).unwrap_or(&Value::Null);
+
+      if from != to {
+        changes.insert(field.clone(), json!({ "from": from, "to": to }));
+      }
+    }
+  }
+
+  let mut changes = Value::Object(changes);
+  logger::redact(&mut changes);
+
+  changes
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_diff() {
+    let before = json!({ "email": "a@b.c", "name": "A", "role": "user" });
+    let after = json!({ "email": "a@b.c", "name": "B", "password": "hunter2" });
+
+    assert_eq!(
+      diff(&before, &after),
+      json!({
+        "name": { "from": "A", "to": "B" },
+        "password": "[REDACTED]",
+      })
+    );
+  }
+
+  #[test]
+  fn test_diff_unchanged() {
+    let before = json!({ "name": "A" });
+
+    assert_eq!(diff(&before, &before), json!({}));
+    assert_eq!(diff(&before, &Value::Null), json!({}));
+  }
+}
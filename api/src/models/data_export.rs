@@ -0,0 +1,206 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::models::mfa::Mfa;
+use crate::models::schema::{
+  api_tokens, audit_events, groups, identities, sessions, users, users_groups, webauthn_credentials,
+};
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde_json::Value;
+use uuid::Uuid;
+
+/// Where to download a user's data export from,
+/// until `expires_at`.
+#[derive(GraphQLObject)]
+pub struct DataExport {
+  pub url: String,
+  pub expires_at: DateTime<Utc>,
+}
+
+impl DataExport {
+  /// Everything kept about `user_id`: their profile,
+  /// group memberships, credentials (without secrets),
+  /// sessions and audit events they're the actor or
+  /// target of.
+  pub fn archive(connection: &Connection, user_id: &Uuid) -> Result<Value, Error> {
+    let (id, email, name, role, status) = users::table
+      .find(user_id)
+      .filter(users::deleted_at.is_null())
+      .select((
+        users::id,
+        users::email,
+        users::name,
+        users::role,
+        users::status,
+      ))
+      .first::<(Uuid, String, Option<String>, String, String)>(connection)?;
+
+    let groups = users_groups::table
+      .inner_join(groups::table)
+      .filter(users_groups::user_id.eq(user_id))
+      .filter(groups::deleted_at.is_null())
+      .select((groups::id, groups::name, users_groups::added_at))
+      .load::<(Uuid, String, DateTime<Utc>)>(connection)?
+      .into_iter()
+      .map(|(id, name, added_at)| json!({ "id": id, "name": name, "added_at": added_at }))
+      .collect::<Vec<_>>();
+
+    let api_tokens = api_tokens::table
+      .filter(api_tokens::user_id.eq(user_id))
+      .select((
+        api_tokens::name,
+        api_tokens::scopes,
+        api_tokens::expires_at,
+        api_tokens::last_used_at,
+        api_tokens::created_at,
+      ))
+      .load::<(
+        String,
+        Vec<String>,
+        Option<DateTime<Utc>>,
+        Option<DateTime<Utc>>,
+        DateTime<Utc>,
+      )>(connection)?
+      .into_iter()
+      .map(|(name, scopes, expires_at, last_used_at, created_at)| {
+        json!({
+          "name": name,
+          "scopes": scopes,
+          "expires_at": expires_at,
+          "last_used_at": last_used_at,
+          "created_at": created_at,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let passkeys = webauthn_credentials::table
+      .filter(webauthn_credentials::user_id.eq(user_id))
+      .select((
+        webauthn_credentials::name,
+        webauthn_credentials::created_at,
+        webauthn_credentials::last_used_at,
+      ))
+      .load::<(Option<String>, DateTime<Utc>, Option<DateTime<Utc>>)>(connection)?
+      .into_iter()
+      .map(|(name, created_at, last_used_at)| {
+        json!({ "name": name, "created_at": created_at, "last_used_at": last_used_at })
+      })
+      .collect::<Vec<_>>();
+
+    let identities = identities::table
+      .filter(identities::user_id.eq(user_id))
+      .select((
+        identities::provider,
+        identities::subject,
+        identities::email,
+        identities::created_at,
+      ))
+      .load::<(String, String, Option<String>, DateTime<Utc>)>(connection)?
+      .into_iter()
+      .map(|(provider, subject, email, created_at)| {
+        json!({
+          "provider": provider,
+          "subject": subject,
+          "email": email,
+          "created_at": created_at,
+        })
+      })
+      .collect::<Vec<_>>();
+
+    let sessions = sessions::table
+      .filter(sessions::user_id.eq(user_id))
+      .order(sessions::created_at)
+      .select((
+        sessions::device,
+        sessions::user_agent,
+        sessions::ip,
+        sessions::created_at,
+        sessions::last_seen_at,
+        sessions::revoked_at,
+      ))
+      .load::<(
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        DateTime<Utc>,
+        DateTime<Utc>,
+        Option<DateTime<Utc>>,
+      )>(connection)?
+      .into_iter()
+      .map(
+        |(device, user_agent, ip, created_at, last_seen_at, revoked_at)| {
+          json!({
+            "device": device,
+            "user_agent": user_agent,
+            "ip": ip,
+            "created_at": created_at,
+            "last_seen_at": last_seen_at,
+            "revoked_at": revoked_at,
+          })
+        },
+      )
+      .collect::<Vec<_>>();
+
+    // Who else acted on the user, and from where, is
+    // their personal data, not the user's.
+    let audit_events = audit_events::table
+      .filter(
+        audit_events::actor_id
+          .eq(user_id)
+          .or(audit_events::target_id.eq(user_id)),
+      )
+      .order(audit_events::created_at)
+      .select((
+        audit_events::action,
+        audit_events::actor_id,
+        audit_events::impersonator_id,
+        audit_events::target_id,
+        audit_events::ip,
+        audit_events::details,
+        audit_events::created_at,
+      ))
+      .load::<(
+        String,
+        Option<Uuid>,
+        Option<Uuid>,
+        Option<Uuid>,
+        Option<String>,
+        Value,
+        DateTime<Utc>,
+      )>(connection)?
+      .into_iter()
+      .map(
+        |(action, actor_id, impersonator_id, target_id, ip, details, created_at)| {
+          let own = actor_id.as_ref() == Some(user_id) && impersonator_id.is_none();
+
+          json!({
+            "action": action,
+            "actor_id": actor_id.filter(|_| own),
+            "target_id": target_id,
+            "ip": ip.filter(|_| own),
+            "details": details,
+            "created_at": created_at,
+          })
+        },
+      )
+      .collect::<Vec<_>>();
+
+    Ok(json!({
+      "exported_at": Utc::now(),
+      "profile": {
+        "id": id,
+        "email": email,
+        "name": name,
+        "role": role,
+        "status": status,
+        "mfa_enabled": Mfa::enabled(connection, user_id)?,
+      },
+      "groups": groups,
+      "api_tokens": api_tokens,
+      "passkeys": passkeys,
+      "identities": identities,
+      "sessions": sessions,
+      "audit_events": audit_events,
+    }))
+  }
+}
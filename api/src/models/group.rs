@@ -1,10 +1,13 @@
 use crate::db::Connection;
 use crate::error::Error;
-use crate::models::schema::{groups, users_groups};
-use crate::models::user::User;
+use crate::models::schema::groups;
 use crate::models::user_group::UserGroup;
+use crate::policy::{self, Action, Resource};
+use crate::retention;
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
+use serde_json::{Map, Value};
+use std::time::Duration;
 use uuid::Uuid;
 
 #[derive(GraphQLObject, Identifiable, Queryable)]
@@ -30,6 +33,24 @@ pub struct GroupUpdate {
   pub name: Option<String>,
 }
 
+impl GroupUpdate {
+  /// Fields being set, for diffing against a
+  /// `Group::snapshot`.
+  pub fn changes(&self) -> Value {
+    let mut changes = Map::new();
+
+    if let Some(ref name) = self.name {
+      changes.insert("name".to_string(), json!(name));
+    }
+
+    Value::Object(changes)
+  }
+}
+
+pub type Columns = (groups::id, groups::name, groups::created_at);
+
+pub const COLUMNS: Columns = (groups::id, groups::name, groups::created_at);
+
 impl Group {
   pub fn create(
     connection: &Connection,
@@ -46,65 +67,110 @@ impl Group {
   }
 
   pub fn read(connection: &Connection, user_id: &Uuid, group_id: &Uuid) -> Result<Group, Error> {
-    use diesel::pg::expression::dsl::any;
-
-    let user = User::read(connection, user_id, user_id)?;
-    let group_ids = UserGroup::belonging_to(&user).select(users_groups::group_id);
+    policy::authorize(connection, user_id, Action::Read, Resource::Group(group_id))?;
 
     Ok(
       groups::table
-        .filter(groups::id.eq(any(group_ids)))
+        .select(COLUMNS)
         .find(group_id)
+        .filter(groups::deleted_at.is_null())
         .first::<Group>(connection)?,
     )
   }
 
-  pub fn read_all(connection: &Connection, user_id: &Uuid) -> Result<Vec<Group>, Error> {
-    use diesel::pg::expression::dsl::any;
-
-    let user = User::read(connection, user_id, user_id)?;
-    let group_ids = UserGroup::belonging_to(&user).select(users_groups::group_id);
-
+  /// Fields of `group_id` changes are audited for,
+  /// without checks, or null for unknown groups.
+  pub fn snapshot(connection: &Connection, group_id: &Uuid) -> Result<Value, Error> {
     Ok(
       groups::table
-        .filter(groups::id.eq(any(group_ids)))
-        .load::<Group>(connection)?,
+        .select(COLUMNS)
+        .find(group_id)
+        .filter(groups::deleted_at.is_null())
+        .first::<Group>(connection)
+        .optional()?
+        .map(|group| json!({ "name": group.name }))
+        .unwrap_or(Value::Null),
     )
   }
 
+  pub fn read_all(connection: &Connection, user_id: &Uuid) -> Result<Vec<Group>, Error> {
+    UserGroup::read_groups(connection, user_id)
+  }
+
   pub fn update(
     connection: &Connection,
     user_id: &Uuid,
     group: &GroupUpdate,
   ) -> Result<bool, Error> {
-    use diesel::pg::expression::dsl::any;
-
-    let user = User::read(connection, user_id, user_id)?;
-    let group_ids = UserGroup::belonging_to(&user).select(users_groups::group_id);
+    policy::authorize(
+      connection,
+      user_id,
+      Action::Update,
+      Resource::Group(&group.id),
+    )?;
 
     Ok(
-      diesel::update(group)
-        .set(group)
-        .filter(groups::id.eq(any(group_ids)))
-        .execute(connection)?
+      diesel::update(
+        groups::table
+          .find(&group.id)
+          .filter(groups::deleted_at.is_null()),
+      )
+      .set(group)
+      .execute(connection)?
         == 1,
     )
   }
 
   pub fn delete(connection: &Connection, user_id: &Uuid, group_id: &Uuid) -> Result<bool, Error> {
-    use diesel::pg::expression::dsl::any;
+    policy::authorize(
+      connection,
+      user_id,
+      Action::Delete,
+      Resource::Group(group_id),
+    )?;
+
+    Ok(
+      diesel::update(
+        groups::table
+          .find(group_id)
+          .filter(groups::deleted_at.is_null()),
+      )
+      .set(groups::deleted_at.eq(Utc::now()))
+      .execute(connection)?
+        == 1,
+    )
+  }
 
-    let user = User::read(connection, user_id, user_id)?;
-    let group_ids = UserGroup::belonging_to(&user).select(users_groups::group_id);
+  /// Undoes deleting `group_id` less than
+  /// `grace_period` ago, for its members.
+  pub fn restore(
+    connection: &Connection,
+    user_id: &Uuid,
+    group_id: &Uuid,
+    grace_period: Duration,
+  ) -> Result<bool, Error> {
+    policy::authorize(
+      connection,
+      user_id,
+      Action::Restore,
+      Resource::Group(group_id),
+    )?;
 
     Ok(
-      diesel::delete(
+      diesel::update(
         groups::table
-          .filter(groups::id.eq(any(group_ids)))
-          .find(group_id),
+          .find(group_id)
+          .filter(groups::deleted_at.gt(retention::cutoff(grace_period)?)),
       )
+      .set(groups::deleted_at.eq(None::<DateTime<Utc>>))
       .execute(connection)?
         == 1,
     )
   }
+
+  /// Hard deletes groups soft deleted `before`,
+  /// cascading to their members.
+  pub fn purge(connection: &Connection, before: &DateTime<Utc>) -> Result<usize, Error> {
+    Ok(diesel::delete(groups::table.filter(groups::deleted_at.lt(before))).execute(connection)?)
+  }
 }
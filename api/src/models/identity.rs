@@ -0,0 +1,173 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::hasher::HashGenerator;
+use crate::models::mfa::Mfa;
+use crate::models::schema::{identities, oidc_states, users};
+use crate::models::user::{User, UserCreate};
+use crate::oidc::Oidc;
+use crate::tokeniser::TokenGenerator;
+use chrono::{DateTime, Duration, Utc};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel::Connection as _;
+use uuid::Uuid;
+
+/// How long users have to log in with the provider.
+const STATE_TTL: i64 = 10;
+
+/// An external account, linked to a user so they
+/// can log in with its provider.
+#[derive(Identifiable, Queryable)]
+#[table_name = "identities"]
+pub struct Identity {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub provider: String,
+  pub subject: String,
+  pub email: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+impl Identity {
+  /// Starts logging in with `provider`, returning the
+  /// URL to send the user to. When `user_id` is given,
+  /// the identity is linked to them instead.
+  pub fn begin(
+    connection: &Connection,
+    oidc: &Oidc,
+    provider: &str,
+    user_id: Option<&Uuid>,
+  ) -> Result<String, Error> {
+    let authorization = oidc.authorize(provider)?;
+    let now = Utc::now();
+
+    diesel::delete(oidc_states::table.filter(oidc_states::expires_at.le(now)))
+      .execute(connection)?;
+    diesel::insert_into(oidc_states::table)
+      .values((
+        oidc_states::state.eq(&authorization.state),
+        oidc_states::provider.eq(provider),
+        oidc_states::code_verifier.eq(&authorization.code_verifier),
+        oidc_states::nonce.eq(&authorization.nonce),
+        oidc_states::user_id.eq(user_id),
+        oidc_states::expires_at.eq(now + Duration::minutes(STATE_TTL)),
+      ))
+      .execute(connection)?;
+
+    Ok(authorization.url)
+  }
+
+  /// Exchanges the `code` and `state` the provider
+  /// returned the user with for a token. Unknown
+  /// identities are linked to the user who started
+  /// logging in, or a new user with their verified
+  /// email, who can't log in with a password. Users
+  /// who can't log in are refused, as with any other
+  /// login. Only the user who started linking, as
+  /// `user_id`, can finish, so others can't be
+  /// tricked into linking their identity to someone
+  /// else's account.
+  #[allow(clippy::too_many_arguments)]
+  pub fn login(
+    connection: &Connection,
+    oidc: &Oidc,
+    hash: &HashGenerator,
+    tokenise: &TokenGenerator,
+    challenge: &TokenGenerator,
+    user_id: Option<&Uuid>,
+    state: &str,
+    code: &str,
+  ) -> Result<String, Error> {
+    let (provider, code_verifier, nonce, link) = diesel::delete(
+      oidc_states::table
+        .find(state)
+        .filter(oidc_states::expires_at.gt(Utc::now())),
+    )
+    .returning((
+      oidc_states::provider,
+      oidc_states::code_verifier,
+      oidc_states::nonce,
+      oidc_states::user_id,
+    ))
+    .get_result::<(String, String, String, Option<Uuid>)>(connection)
+    .optional()?
+    .ok_or(Error::InvalidOidcLogin(
+      "Invalid OIDC login - Unknown or expired state",
+    ))?;
+
+    if link.is_some() && link.as_ref() != user_id {
+      return Err(Error::Unauthorised(
+        "Unauthorised - Only the user who started linking can finish it",
+      ));
+    }
+
+    let claims = oidc.exchange(&provider, code, &code_verifier, &nonce)?;
+
+    let linked = identities::table
+      .filter(identities::provider.eq(&provider))
+      .filter(identities::subject.eq(&claims.sub))
+      .select(identities::user_id)
+      .first::<Uuid>(connection)
+      .optional()?;
+
+    let user_id = match (linked, link) {
+      (Some(linked), Some(link)) if linked != link => {
+        return Err(Error::Str("Identity is linked to another user"));
+      }
+      (Some(linked), _) => linked,
+      (None, link) => connection.transaction::<_, Error, _>(|| {
+        let user_id = match link {
+          Some(link) => link,
+          None => {
+            let email = match claims.email {
+              Some(ref email) if claims.email_verified => email,
+              _ => return Err(Error::Str("Identity has no verified email")),
+            };
+            let taken = diesel::select(exists(users::table.filter(users::email.eq(email))))
+              .get_result::<bool>(connection)?;
+
+            if taken {
+              return Err(Error::EmailTaken);
+            }
+
+            let user_id = Uuid::new_v4();
+            let password = Uuid::new_v4().to_string();
+            User::create(
+              connection,
+              hash,
+              tokenise,
+              &UserCreate {
+                id: user_id,
+                email: email.clone(),
+                password,
+                name: claims.name.clone(),
+              },
+            )?;
+
+            user_id
+          }
+        };
+
+        diesel::insert_into(identities::table)
+          .values((
+            identities::id.eq(Uuid::new_v4()),
+            identities::user_id.eq(user_id),
+            identities::provider.eq(&provider),
+            identities::subject.eq(&claims.sub),
+            identities::email.eq(&claims.email),
+          ))
+          .execute(connection)?;
+
+        Ok(user_id)
+      })?,
+    };
+
+    User::check_status(connection, &user_id)?;
+
+    if Mfa::enabled(connection, &user_id)? {
+      return Err(Error::MfaRequired(challenge(user_id)?));
+    }
+
+    tokenise(user_id)
+  }
+}
@@ -0,0 +1,224 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::hasher::{HashGenerator, HashVerifier};
+use crate::models::schema::{mfa, mfa_recovery_codes};
+use crate::models::user::User;
+use crate::totp;
+use chrono::{DateTime, Utc};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use diesel::Connection as _;
+use uuid::Uuid;
+
+/// Recovery codes issued when enrolment is confirmed.
+const RECOVERY_CODES: usize = 10;
+/// Base32 characters in each recovery code.
+const RECOVERY_CODE_LENGTH: usize = 16;
+
+/// TOTP multi-factor authentication for a user,
+/// enabled once `confirmed_at` is set.
+#[derive(Identifiable, Queryable)]
+#[primary_key(user_id)]
+#[table_name = "mfa"]
+pub struct Mfa {
+  pub user_id: Uuid,
+  pub secret: String,
+  pub confirmed_at: Option<DateTime<Utc>>,
+  pub last_step: Option<i64>,
+}
+
+#[derive(Insertable)]
+#[table_name = "mfa_recovery_codes"]
+struct RecoveryCodeCreate {
+  id: Uuid,
+  user_id: Uuid,
+  code_hash: String,
+}
+
+impl Mfa {
+  /// Starts enrolling `user_id` with a new secret,
+  /// replacing any unconfirmed one, and returns its
+  /// `otpauth://` URI.
+  pub fn enrol(connection: &Connection, issuer: &str, user_id: &Uuid) -> Result<String, Error> {
+    if Mfa::enabled(connection, user_id)? {
+      return Err(Error::Str("MFA is already enabled"));
+    }
+
+    let secret = totp::secret()?;
+    diesel::insert_into(mfa::table)
+      .values((mfa::user_id.eq(user_id), mfa::secret.eq(&secret)))
+      .on_conflict(mfa::user_id)
+      .do_update()
+      .set(mfa::secret.eq(&secret))
+      .execute(connection)?;
+
+    Ok(totp::uri(
+      &secret,
+      issuer,
+      &User::email(connection, user_id)?,
+    ))
+  }
+
+  /// Enables MFA for `user_id` given a `code` from the
+  /// newly enrolled authenticator, returning one-time
+  /// recovery codes. These are only stored hashed, so
+  /// can't be shown again.
+  pub fn confirm(
+    connection: &Connection,
+    hash: &HashGenerator,
+    user_id: &Uuid,
+    code: &str,
+  ) -> Result<Vec<String>, Error> {
+    let pending = mfa::table
+      .find(user_id)
+      .filter(mfa::confirmed_at.is_null())
+      .first::<Mfa>(connection)
+      .optional()?
+      .ok_or(Error::Str("MFA enrolment not started"))?;
+    let step =
+      totp::verify(&pending.secret, code, Utc::now().timestamp()).ok_or(Error::InvalidMfaCode)?;
+
+    let codes = (0..RECOVERY_CODES)
+      .map(|_| recovery_code())
+      .collect::<Result<Vec<_>, Error>>()?;
+    let code_hashes = codes
+      .iter()
+      .map(|code| {
+        Ok(RecoveryCodeCreate {
+          id: Uuid::new_v4(),
+          user_id: *user_id,
+          code_hash: hash(&normalise(code))?,
+        })
+      })
+      .collect::<Result<Vec<_>, Error>>()?;
+
+    connection.transaction::<_, Error, _>(|| {
+      diesel::update(mfa::table.find(user_id))
+        .set((mfa::confirmed_at.eq(Utc::now()), mfa::last_step.eq(step)))
+        .execute(connection)?;
+      diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+        .execute(connection)?;
+      diesel::insert_into(mfa_recovery_codes::table)
+        .values(&code_hashes)
+        .execute(connection)?;
+
+      Ok(())
+    })?;
+
+    Ok(codes)
+  }
+
+  /// Disables MFA for `user_id`, given a current
+  /// code or an unused recovery code.
+  pub fn disable(
+    connection: &Connection,
+    verify: &HashVerifier,
+    user_id: &Uuid,
+    code: &str,
+  ) -> Result<bool, Error> {
+    Mfa::verify(connection, verify, user_id, code)?;
+
+    connection.transaction::<_, Error, _>(|| {
+      diesel::delete(mfa_recovery_codes::table.filter(mfa_recovery_codes::user_id.eq(user_id)))
+        .execute(connection)?;
+
+      Ok(diesel::delete(mfa::table.find(user_id)).execute(connection)? > 0)
+    })
+  }
+
+  /// Whether `user_id` has confirmed MFA enrolment.
+  pub fn enabled(connection: &Connection, user_id: &Uuid) -> Result<bool, Error> {
+    Ok(
+      diesel::select(exists(
+        mfa::table
+          .find(user_id)
+          .filter(mfa::confirmed_at.is_not_null()),
+      ))
+      .get_result(connection)?,
+    )
+  }
+
+  /// Checks `code` from the authenticator, or an unused
+  /// recovery code, for `user_id`. Each code is only
+  /// accepted once.
+  pub fn verify(
+    connection: &Connection,
+    verify: &HashVerifier,
+    user_id: &Uuid,
+    code: &str,
+  ) -> Result<(), Error> {
+    let enabled = mfa::table
+      .find(user_id)
+      .filter(mfa::confirmed_at.is_not_null())
+      .first::<Mfa>(connection)
+      .optional()?
+      .ok_or(Error::Str("MFA is not enabled"))?;
+
+    if let Some(step) = totp::verify(&enabled.secret, code, Utc::now().timestamp()) {
+      // Only moves forward, so codes can't be replayed.
+      let accepted = diesel::update(
+        mfa::table
+          .find(user_id)
+          .filter(mfa::last_step.is_null().or(mfa::last_step.lt(step))),
+      )
+      .set(mfa::last_step.eq(step))
+      .execute(connection)?
+        == 1;
+
+      return if accepted {
+        Ok(())
+      } else {
+        Err(Error::InvalidMfaCode)
+      };
+    }
+
+    let code = normalise(code);
+
+    if code.len() == RECOVERY_CODE_LENGTH {
+      let unused = mfa_recovery_codes::table
+        .filter(mfa_recovery_codes::user_id.eq(user_id))
+        .filter(mfa_recovery_codes::used_at.is_null())
+        .select((mfa_recovery_codes::id, mfa_recovery_codes::code_hash))
+        .load::<(Uuid, String)>(connection)?;
+
+      for (id, code_hash) in unused {
+        if verify(&code_hash, &code)? {
+          let used = diesel::update(
+            mfa_recovery_codes::table
+              .find(id)
+              .filter(mfa_recovery_codes::used_at.is_null()),
+          )
+          .set(mfa_recovery_codes::used_at.eq(Utc::now()))
+          .execute(connection)?;
+
+          if used == 1 {
+            return Ok(());
+          }
+        }
+      }
+    }
+
+    Err(Error::InvalidMfaCode)
+  }
+}
+
+/// Random recovery code, i.e. `abcd-efgh-ijkl-mnop`.
+fn recovery_code() -> Result<String, Error> {
+  let encoded = totp::encode(&totp::random(10)?).to_lowercase();
+  let groups = encoded
+    .as_bytes()
+    .chunks(4)
+    .map(|group| String::from_utf8_lossy(group).into_owned())
+    .collect::<Vec<_>>();
+
+  Ok(groups.join("-"))
+}
+
+/// Recovery codes ignore case, spaces and dashes.
+fn normalise(code: &str) -> String {
+  code
+    .chars()
+    .filter(|char| char.is_ascii_alphanumeric())
+    .collect::<String>()
+    .to_lowercase()
+}
@@ -1,4 +1,11 @@
+pub mod api_token;
+pub mod audit_event;
+pub mod data_export;
 pub mod group;
+pub mod identity;
+pub mod mfa;
+pub mod passkey;
 pub mod schema;
+pub mod session;
 pub mod user;
 mod user_group;
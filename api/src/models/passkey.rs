@@ -0,0 +1,241 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::logger;
+use crate::models::schema::{webauthn_challenges, webauthn_credentials};
+use crate::models::user::User;
+use crate::tokeniser::TokenGenerator;
+use crate::webauthn::{self, Webauthn};
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use log::Level;
+use uuid::Uuid;
+
+const REGISTRATION: &str = "registration";
+const AUTHENTICATION: &str = "authentication";
+
+/// A WebAuthn credential (passkey) a user can log in
+/// with instead of their password.
+#[derive(Identifiable, Queryable)]
+#[table_name = "webauthn_credentials"]
+pub struct Passkey {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub credential_id: Vec<u8>,
+  pub public_key: Vec<u8>,
+  pub sign_count: i64,
+  pub name: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// `PublicKeyCredential` from `navigator.credentials.create()`,
+/// with its binary fields base64url encoded.
+#[derive(GraphQLInputObject)]
+pub struct PasskeyRegistration {
+  pub id: String,
+  pub client_data_json: String,
+  pub attestation_object: String,
+  /// Label to tell passkeys apart, i.e. the device.
+  pub name: Option<String>,
+}
+
+/// `PublicKeyCredential` from `navigator.credentials.get()`,
+/// with its binary fields base64url encoded.
+#[derive(GraphQLInputObject)]
+pub struct PasskeyAssertion {
+  pub id: String,
+  pub client_data_json: String,
+  pub authenticator_data: String,
+  pub signature: String,
+  pub user_handle: Option<String>,
+}
+
+impl Passkey {
+  /// Starts registering a passkey for `user_id`,
+  /// returning options for `navigator.credentials.create()`
+  /// as JSON.
+  pub fn begin_registration(
+    connection: &Connection,
+    webauthn: &Webauthn,
+    user_id: &Uuid,
+  ) -> Result<String, Error> {
+    let email = User::email(connection, user_id)?;
+    let existing = webauthn_credentials::table
+      .filter(webauthn_credentials::user_id.eq(user_id))
+      .select(webauthn_credentials::credential_id)
+      .load::<Vec<u8>>(connection)?;
+    let challenge = challenge(connection, REGISTRATION, Some(user_id))?;
+
+    Ok(
+      webauthn
+        .creation_options(&challenge, user_id, &email, &existing)
+        .to_string(),
+    )
+  }
+
+  /// Verifies a `credential` created for `user_id`
+  /// and saves its public key.
+  pub fn register(
+    connection: &Connection,
+    webauthn: &Webauthn,
+    user_id: &Uuid,
+    credential: &PasskeyRegistration,
+  ) -> Result<bool, Error> {
+    let client_data_json = webauthn::decode(&credential.client_data_json)?;
+    let challenge = webauthn.client_data(&client_data_json, "webauthn.create")?;
+
+    if consume(connection, &challenge, REGISTRATION)? != Some(Some(*user_id)) {
+      return Err(Error::InvalidPasskey(
+        "Invalid passkey - Unknown or expired challenge",
+      ));
+    }
+
+    let registered = webauthn.register(&webauthn::decode(&credential.attestation_object)?)?;
+
+    if registered.id != webauthn::decode(&credential.id)? {
+      return Err(Error::InvalidPasskey(
+        "Invalid passkey - Credential ID mismatch",
+      ));
+    }
+
+    Ok(
+      diesel::insert_into(webauthn_credentials::table)
+        .values((
+          webauthn_credentials::id.eq(Uuid::new_v4()),
+          webauthn_credentials::user_id.eq(user_id),
+          webauthn_credentials::credential_id.eq(&registered.id),
+          webauthn_credentials::public_key.eq(&registered.public_key),
+          webauthn_credentials::sign_count.eq(i64::from(registered.sign_count)),
+          webauthn_credentials::name.eq(&credential.name),
+        ))
+        .on_conflict_do_nothing()
+        .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Starts logging in with a passkey, returning
+  /// options for `navigator.credentials.get()` as JSON.
+  pub fn begin_login(connection: &Connection, webauthn: &Webauthn) -> Result<String, Error> {
+    let challenge = challenge(connection, AUTHENTICATION, None)?;
+
+    Ok(webauthn.request_options(&challenge).to_string())
+  }
+
+  /// Exchanges a signed `credential` for a token,
+  /// refusing users who can't log in.
+  pub fn login(
+    connection: &Connection,
+    webauthn: &Webauthn,
+    tokenise: &TokenGenerator,
+    credential: &PasskeyAssertion,
+  ) -> Result<String, Error> {
+    let client_data_json = webauthn::decode(&credential.client_data_json)?;
+    let challenge = webauthn.client_data(&client_data_json, "webauthn.get")?;
+
+    if consume(connection, &challenge, AUTHENTICATION)?.is_none() {
+      return Err(Error::InvalidPasskey(
+        "Invalid passkey - Unknown or expired challenge",
+      ));
+    }
+
+    let passkey = webauthn_credentials::table
+      .filter(webauthn_credentials::credential_id.eq(webauthn::decode(&credential.id)?))
+      .first::<Passkey>(connection)
+      .optional()?
+      .ok_or(Error::InvalidPasskey(
+        "Invalid passkey - Unknown credential",
+      ))?;
+
+    if let Some(ref user_handle) = credential.user_handle {
+      if webauthn::decode(user_handle)? != passkey.user_id.as_bytes() {
+        return Err(Error::InvalidPasskey(
+          "Invalid passkey - User handle mismatch",
+        ));
+      }
+    }
+
+    let sign_count = i64::from(webauthn.authenticate(
+      &webauthn::decode(&credential.authenticator_data)?,
+      &client_data_json,
+      &webauthn::decode(&credential.signature)?,
+      &passkey.public_key,
+    )?);
+    let used = webauthn_credentials::table.find(passkey.id);
+
+    // Counts only go up, unless the authenticator doesn't
+    // keep one, so a repeat suggests it has been cloned.
+    let counted = if sign_count == 0 && passkey.sign_count == 0 {
+      diesel::update(used)
+        .set(webauthn_credentials::last_used_at.eq(Utc::now()))
+        .execute(connection)?
+    } else {
+      diesel::update(used.filter(webauthn_credentials::sign_count.lt(sign_count)))
+        .set((
+          webauthn_credentials::sign_count.eq(sign_count),
+          webauthn_credentials::last_used_at.eq(Utc::now()),
+        ))
+        .execute(connection)?
+    };
+
+    if counted == 0 {
+      logger::event(
+        Level::Warn,
+        "Passkey sign count did not increase",
+        json!({ "user": passkey.user_id, "passkey": passkey.id }),
+      );
+
+      return Err(Error::InvalidPasskey(
+        "Invalid passkey - Sign count did not increase",
+      ));
+    }
+
+    User::check_status(connection, &passkey.user_id)?;
+
+    tokenise(passkey.user_id)
+  }
+}
+
+/// Stores a new challenge for a ceremony of `kind`,
+/// clearing out expired ones.
+fn challenge(
+  connection: &Connection,
+  kind: &str,
+  user_id: Option<&Uuid>,
+) -> Result<Vec<u8>, Error> {
+  let challenge = webauthn::challenge()?;
+  let now = Utc::now();
+
+  diesel::delete(webauthn_challenges::table.filter(webauthn_challenges::expires_at.le(now)))
+    .execute(connection)?;
+  diesel::insert_into(webauthn_challenges::table)
+    .values((
+      webauthn_challenges::challenge.eq(&challenge),
+      webauthn_challenges::kind.eq(kind),
+      webauthn_challenges::user_id.eq(user_id),
+      webauthn_challenges::expires_at.eq(now + Duration::milliseconds(webauthn::TIMEOUT)),
+    ))
+    .execute(connection)?;
+
+  Ok(challenge)
+}
+
+/// Removes an unexpired `challenge` of `kind`, so it
+/// can only be answered once, returning who it was for.
+fn consume(
+  connection: &Connection,
+  challenge: &[u8],
+  kind: &str,
+) -> Result<Option<Option<Uuid>>, Error> {
+  Ok(
+    diesel::delete(
+      webauthn_challenges::table
+        .find(challenge)
+        .filter(webauthn_challenges::kind.eq(kind))
+        .filter(webauthn_challenges::expires_at.gt(Utc::now())),
+    )
+    .returning(webauthn_challenges::user_id)
+    .get_result::<Option<Uuid>>(connection)
+    .optional()?,
+  )
+}
@@ -1,8 +1,122 @@
+table! {
+    api_tokens (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        name -> Varchar,
+        scopes -> Array<Text>,
+        token_hash -> Bytea,
+        expires_at -> Nullable<Timestamptz>,
+        last_used_at -> Nullable<Timestamptz>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    audit_events (id) {
+        id -> Uuid,
+        actor_id -> Nullable<Uuid>,
+        action -> Varchar,
+        target_id -> Nullable<Uuid>,
+        ip -> Nullable<Varchar>,
+        request_id -> Varchar,
+        details -> Jsonb,
+        created_at -> Timestamptz,
+        user_agent -> Nullable<Text>,
+        diff -> Jsonb,
+        impersonator_id -> Nullable<Uuid>,
+    }
+}
+
 table! {
     groups (id) {
         id -> Uuid,
         name -> Varchar,
         created_at -> Timestamptz,
+        deleted_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    identities (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        provider -> Varchar,
+        subject -> Varchar,
+        email -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    login_failures (key_hash) {
+        key_hash -> Varchar,
+        failures -> Int4,
+        locked_until -> Nullable<Timestamptz>,
+        last_failed_at -> Timestamptz,
+    }
+}
+
+table! {
+    mfa (user_id) {
+        user_id -> Uuid,
+        secret -> Varchar,
+        confirmed_at -> Nullable<Timestamptz>,
+        last_step -> Nullable<Int8>,
+    }
+}
+
+table! {
+    mfa_recovery_codes (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    oidc_states (state) {
+        state -> Varchar,
+        provider -> Varchar,
+        code_verifier -> Varchar,
+        nonce -> Varchar,
+        user_id -> Nullable<Uuid>,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    rate_limits (key) {
+        key -> Varchar,
+        attempts -> Int4,
+        window_start -> Timestamptz,
+    }
+}
+
+table! {
+    role_permissions (role, permission) {
+        role -> Varchar,
+        permission -> Varchar,
+    }
+}
+
+table! {
+    roles (name) {
+        name -> Varchar,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        device -> Nullable<Varchar>,
+        user_agent -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+        expires_at -> Timestamptz,
+        revoked_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -12,6 +126,11 @@ table! {
         email -> Varchar,
         password -> Varchar,
         name -> Nullable<Varchar>,
+        role -> Varchar,
+        status -> Varchar,
+        status_reason -> Nullable<Text>,
+        status_expires_at -> Nullable<Timestamptz>,
+        deleted_at -> Nullable<Timestamptz>,
     }
 }
 
@@ -24,11 +143,56 @@ table! {
     }
 }
 
+table! {
+    webauthn_challenges (challenge) {
+        challenge -> Bytea,
+        kind -> Varchar,
+        user_id -> Nullable<Uuid>,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    webauthn_credentials (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        credential_id -> Bytea,
+        public_key -> Bytea,
+        sign_count -> Int8,
+        name -> Nullable<Varchar>,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+joinable!(api_tokens -> users (user_id));
+joinable!(identities -> users (user_id));
+joinable!(mfa -> users (user_id));
+joinable!(mfa_recovery_codes -> users (user_id));
+joinable!(oidc_states -> users (user_id));
+joinable!(role_permissions -> roles (role));
+joinable!(sessions -> users (user_id));
+joinable!(users -> roles (role));
 joinable!(users_groups -> groups (group_id));
 joinable!(users_groups -> users (user_id));
+joinable!(webauthn_challenges -> users (user_id));
+joinable!(webauthn_credentials -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    audit_events,
     groups,
+    identities,
+    login_failures,
+    mfa,
+    mfa_recovery_codes,
+    oidc_states,
+    rate_limits,
+    role_permissions,
+    roles,
+    sessions,
     users,
     users_groups,
+    webauthn_challenges,
+    webauthn_credentials,
 );
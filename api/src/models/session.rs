@@ -0,0 +1,205 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::models::schema::sessions;
+use crate::tokeniser::Claims;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use diesel::prelude::*;
+use uuid::Uuid;
+
+/// Seconds between recording a session being used.
+const TOUCH_INTERVAL: i64 = 60;
+
+/// A login, identified by its token's `jti`, so
+/// users can see where they're logged in and
+/// revoke tokens they no longer trust.
+#[derive(GraphQLObject, Queryable)]
+pub struct Session {
+  pub id: Uuid,
+  /// Kind of device, i.e. "iPhone", guessed from
+  /// the user agent.
+  pub device: Option<String>,
+  pub user_agent: Option<String>,
+  pub ip: Option<String>,
+  pub created_at: DateTime<Utc>,
+  pub last_seen_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+  /// Whether this is the session making the request.
+  pub current: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+struct SessionCreate<'a> {
+  id: Uuid,
+  user_id: Uuid,
+  device: Option<&'a str>,
+  user_agent: Option<&'a str>,
+  ip: Option<String>,
+  expires_at: DateTime<Utc>,
+}
+
+impl Session {
+  /// Records logging in with the token `claims`
+  /// were issued for.
+  pub fn start(
+    connection: &Connection,
+    claims: &Claims,
+    ip: Option<String>,
+    user_agent: Option<&str>,
+  ) -> Result<(), Error> {
+    diesel::insert_into(sessions::table)
+      .values(SessionCreate {
+        id: claims.jti,
+        user_id: claims.sub,
+        device: user_agent.and_then(device),
+        user_agent,
+        ip,
+        expires_at: Utc.timestamp(claims.exp, 0),
+      })
+      .execute(connection)?;
+
+    Ok(())
+  }
+
+  /// `user_id`'s unexpired sessions, most recently
+  /// seen first, marking `current_id`.
+  pub fn list(
+    connection: &Connection,
+    user_id: &Uuid,
+    current_id: Option<&Uuid>,
+  ) -> Result<Vec<Session>, Error> {
+    Ok(
+      sessions::table
+        .filter(sessions::user_id.eq(user_id))
+        .filter(sessions::revoked_at.is_null())
+        .filter(sessions::expires_at.gt(Utc::now()))
+        .order(sessions::last_seen_at.desc())
+        .select((
+          sessions::id,
+          sessions::device,
+          sessions::user_agent,
+          sessions::ip,
+          sessions::created_at,
+          sessions::last_seen_at,
+          sessions::expires_at,
+          sessions::id.eq(current_id.cloned().unwrap_or_else(Uuid::nil)),
+        ))
+        .load::<Session>(connection)?,
+    )
+  }
+
+  /// Refuses the token for `user_id`'s session `id`.
+  pub fn revoke(connection: &Connection, user_id: &Uuid, id: &Uuid) -> Result<bool, Error> {
+    Ok(
+      diesel::update(
+        sessions::table
+          .find(id)
+          .filter(sessions::user_id.eq(user_id))
+          .filter(sessions::revoked_at.is_null()),
+      )
+      .set(sessions::revoked_at.eq(Utc::now()))
+      .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Revokes every one of `user_id`'s sessions but
+  /// `current_id`, returning how many.
+  pub fn revoke_others(
+    connection: &Connection,
+    user_id: &Uuid,
+    current_id: Option<&Uuid>,
+  ) -> Result<usize, Error> {
+    Ok(
+      diesel::update(
+        sessions::table
+          .filter(sessions::user_id.eq(user_id))
+          .filter(sessions::id.ne(current_id.cloned().unwrap_or_else(Uuid::nil)))
+          .filter(sessions::revoked_at.is_null())
+          .filter(sessions::expires_at.gt(Utc::now())),
+      )
+      .set(sessions::revoked_at.eq(Utc::now()))
+      .execute(connection)?,
+    )
+  }
+
+  /// Records session `id` being used, at most once
+  /// per `TOUCH_INTERVAL` so most requests only read,
+  /// refusing it once revoked. Tokens issued without
+  /// logging in (i.e. impersonation) have no session,
+  /// so are accepted, and can't be revoked this way.
+  pub fn touch(connection: &Connection, id: &Uuid) -> Result<(), Error> {
+    let found = sessions::table
+      .find(id)
+      .select((sessions::revoked_at, sessions::last_seen_at))
+      .first::<(Option<DateTime<Utc>>, DateTime<Utc>)>(connection)
+      .optional()?;
+    let now = Utc::now();
+    let stale = now - Duration::seconds(TOUCH_INTERVAL);
+
+    match found {
+      Some((Some(_), _)) => Err(Error::Str("Session revoked")),
+      Some((None, last_seen_at)) if last_seen_at <= stale => {
+        diesel::update(
+          sessions::table
+            .find(id)
+            .filter(sessions::last_seen_at.le(stale)),
+        )
+        .set(sessions::last_seen_at.eq(now))
+        .execute(connection)?;
+
+        Ok(())
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Deletes expired sessions, whose tokens are
+  /// refused anyway, returning how many.
+  pub fn purge(connection: &Connection) -> Result<usize, Error> {
+    Ok(
+      diesel::delete(sessions::table.filter(sessions::expires_at.le(Utc::now())))
+        .execute(connection)?,
+    )
+  }
+}
+
+/// Kind of device `user_agent` is from, if known.
+fn device(user_agent: &str) -> Option<&'static str> {
+  const DEVICES: [(&str, &str); 7] = [
+    ("iPad", "iPad"),
+    ("iPhone", "iPhone"),
+    ("Android", "Android"),
+    ("Windows", "Windows"),
+    ("Macintosh", "Mac"),
+    ("CrOS", "Chromebook"),
+    ("Linux", "Linux"),
+  ];
+
+  DEVICES
+    .iter()
+    .find(|(pattern, _)| user_agent.contains(pattern))
+    .map(|(_, device)| *device)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_device() {
+    assert_eq!(
+      device("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15"),
+      Some("iPhone")
+    );
+    assert_eq!(
+      device("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36"),
+      Some("Android")
+    );
+    assert_eq!(
+      device("Mozilla/5.0 (Macintosh; Intel Mac OS X 14_0) AppleWebKit/605.1.15"),
+      Some("Mac")
+    );
+    assert_eq!(device("curl/8.4.0"), None);
+  }
+}
@@ -1,9 +1,15 @@
 use crate::db::Connection;
 use crate::error::Error;
 use crate::hasher::{HashGenerator, HashVerifier};
-use crate::models::schema::users;
-use crate::tokeniser::TokenGenerator;
+use crate::models::mfa::Mfa;
+use crate::models::schema::{roles, users};
+use crate::policy::{self, Action, Resource};
+use crate::retention;
+use crate::tokeniser::{ImpersonationGenerator, TokenGenerator};
+use chrono::{DateTime, Utc};
 use diesel::prelude::*;
+use serde_json::{Map, Value};
+use std::time::Duration;
 use uuid::Uuid;
 
 // Todo: Add validator to create & update fields
@@ -15,6 +21,52 @@ pub struct User {
   pub id: Uuid,
   pub email: String,
   pub name: Option<String>,
+  /// System role, granting permissions over other users.
+  pub role: String,
+  /// `active`, `suspended` or `pending`.
+  pub status: String,
+}
+
+/// Whether a user can log in. Suspended and pending
+/// users are refused until their status expires or
+/// an admin reinstates them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+  Active,
+  Suspended,
+  /// Not yet allowed in, e.g. awaiting approval.
+  Pending,
+}
+
+impl Status {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Status::Active => "active",
+      Status::Suspended => "suspended",
+      Status::Pending => "pending",
+    }
+  }
+
+  pub fn parse(status: &str) -> Option<Status> {
+    [Status::Active, Status::Suspended, Status::Pending]
+      .iter()
+      .cloned()
+      .find(|known| known.as_str() == status)
+  }
+
+  /// Refuses users who can't log in, unless their
+  /// status has expired.
+  fn allow(status: &str, expires_at: Option<DateTime<Utc>>) -> Result<(), Error> {
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+      return Ok(());
+    }
+
+    match Status::parse(status) {
+      Some(Status::Suspended) => Err(Error::AccountSuspended),
+      Some(Status::Pending) => Err(Error::AccountPending),
+      _ => Ok(()),
+    }
+  }
 }
 
 #[derive(AsChangeset, Clone, GraphQLInputObject, Insertable)]
@@ -35,12 +87,51 @@ pub struct UserUpdate {
   pub name: Option<String>,
 }
 
+impl UserUpdate {
+  /// Fields being set, for diffing against a
+  /// `User::snapshot`.
+  pub fn changes(&self) -> Value {
+    let mut changes = Map::new();
+
+    if let Some(ref email) = self.email {
+      changes.insert("email".to_string(), json!(email));
+    }
+    if let Some(ref password) = self.password {
+      changes.insert("password".to_string(), json!(password));
+    }
+    if let Some(ref name) = self.name {
+      changes.insert("name".to_string(), json!(name));
+    }
+
+    Value::Object(changes)
+  }
+}
+
 #[derive(GraphQLInputObject, Queryable)]
 pub struct UserLogin {
   pub email: String,
   pub password: String,
 }
 
+/// Most users listed at once.
+const MAX_LIST: i64 = 100;
+
+type Columns = (
+  users::id,
+  users::email,
+  users::name,
+  users::role,
+  users::status,
+);
+
+const COLUMNS: Columns = (
+  users::id,
+  users::email,
+  users::name,
+  users::role,
+  users::status,
+);
+
 impl User {
   pub fn create(
     connection: &Connection,
@@ -58,31 +149,66 @@ impl User {
   }
 
   pub fn read(connection: &Connection, admin_id: &Uuid, user_id: &Uuid) -> Result<User, Error> {
-    if admin_id != user_id {
-      return Err(Error::Str(
-        "Unauthorised - Only the given user can view their account",
-      ));
-    }
+    policy::authorize(connection, admin_id, Action::Read, Resource::User(user_id))?;
 
     Ok(
       users::table
-        .select((users::id, users::email, users::name))
+        .select(COLUMNS)
         .find(user_id)
+        .filter(users::deleted_at.is_null())
         .first::<User>(connection)?,
     )
   }
 
+  /// Users with an email or name containing `search`,
+  /// for admins.
+  pub fn list(
+    connection: &Connection,
+    admin_id: &Uuid,
+    search: Option<&str>,
+    limit: i64,
+    offset: i64,
+  ) -> Result<Vec<User>, Error> {
+    policy::authorize(connection, admin_id, Action::List, Resource::Users)?;
+
+    let mut query = users::table
+      .select(COLUMNS)
+      .filter(users::deleted_at.is_null())
+      .order(users::email)
+      .limit(limit.clamp(0, MAX_LIST))
+      .offset(offset.max(0))
+      .into_boxed();
+
+    if let Some(search) = search {
+      let pattern = format!(
+        "%{}%",
+        search
+          .replace('\\', "\\\\")
+          .replace('%', "\\%")
+          .replace('_', "\\_")
+      );
+      query = query.filter(
+        users::email
+          .ilike(pattern.clone())
+          .or(users::name.ilike(pattern)),
+      );
+    }
+
+    Ok(query.load::<User>(connection)?)
+  }
+
   pub fn update(
     connection: &Connection,
     hash: &HashGenerator,
     admin_id: &Uuid,
     user: &UserUpdate,
   ) -> Result<bool, Error> {
-    if admin_id != &user.id {
-      return Err(Error::Str(
-        "Unauthorised - Only the given user can update their account",
-      ));
-    }
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::Update,
+      Resource::User(&user.id),
+    )?;
 
     let mut user_update = user.clone();
 
@@ -90,34 +216,298 @@ impl User {
       user_update.password = Some(hash(&user_update.password.unwrap())?)
     }
 
-    Ok(diesel::update(user).set(user_update).execute(connection)? > 0)
+    Ok(
+      diesel::update(
+        users::table
+          .find(&user.id)
+          .filter(users::deleted_at.is_null()),
+      )
+      .set(user_update)
+      .execute(connection)?
+        > 0,
+    )
   }
 
+  /// Soft deletes `user_id`, so an admin can
+  /// restore them within the grace period.
   pub fn delete(connection: &Connection, admin_id: &Uuid, user_id: &Uuid) -> Result<bool, Error> {
-    if admin_id != user_id {
-      return Err(Error::Str(
-        "Unauthorised - Only the given user can delete their account",
-      ));
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::Delete,
+      Resource::User(user_id),
+    )?;
+
+    Ok(
+      diesel::update(
+        users::table
+          .find(user_id)
+          .filter(users::deleted_at.is_null()),
+      )
+      .set(users::deleted_at.eq(Utc::now()))
+      .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Undoes deleting `user_id` less than
+  /// `grace_period` ago, for admins.
+  pub fn restore(
+    connection: &Connection,
+    admin_id: &Uuid,
+    user_id: &Uuid,
+    grace_period: Duration,
+  ) -> Result<bool, Error> {
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::Restore,
+      Resource::User(user_id),
+    )?;
+
+    Ok(
+      diesel::update(
+        users::table
+          .find(user_id)
+          .filter(users::deleted_at.gt(retention::cutoff(grace_period)?)),
+      )
+      .set(users::deleted_at.eq(None::<DateTime<Utc>>))
+      .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Hard deletes users soft deleted `before`,
+  /// cascading to their groups and credentials.
+  pub fn purge(connection: &Connection, before: &DateTime<Utc>) -> Result<usize, Error> {
+    Ok(diesel::delete(users::table.filter(users::deleted_at.lt(before))).execute(connection)?)
+  }
+
+  /// Stops `user_id` logging in, for admins, until
+  /// `expires_at` if given.
+  pub fn suspend(
+    connection: &Connection,
+    admin_id: &Uuid,
+    user_id: &Uuid,
+    reason: Option<&str>,
+    expires_at: Option<DateTime<Utc>>,
+  ) -> Result<bool, Error> {
+    if expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+      return Err(Error::Str("Suspension expiry must be in the future"));
+    }
+
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::Suspend,
+      Resource::User(user_id),
+    )?;
+
+    Ok(
+      diesel::update(users::table.find(user_id))
+        .set((
+          users::status.eq(Status::Suspended.as_str()),
+          users::status_reason.eq(reason),
+          users::status_expires_at.eq(expires_at),
+        ))
+        .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Lets a suspended or pending `user_id` log in
+  /// again, for admins.
+  pub fn reinstate(
+    connection: &Connection,
+    admin_id: &Uuid,
+    user_id: &Uuid,
+  ) -> Result<bool, Error> {
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::Reinstate,
+      Resource::User(user_id),
+    )?;
+
+    Ok(
+      diesel::update(users::table.find(user_id))
+        .set((
+          users::status.eq(Status::Active.as_str()),
+          users::status_reason.eq(None::<String>),
+          users::status_expires_at.eq(None::<DateTime<Utc>>),
+        ))
+        .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Refuses `user_id` unless their status lets them
+  /// log in, or if they're deleted or gone (i.e.
+  /// purged while their tokens are still valid).
+  pub fn check_status(connection: &Connection, user_id: &Uuid) -> Result<(), Error> {
+    let found = users::table
+      .find(user_id)
+      .select((users::status, users::status_expires_at, users::deleted_at))
+      .first::<(String, Option<DateTime<Utc>>, Option<DateTime<Utc>>)>(connection)
+      .optional()?;
+
+    match found {
+      Some((_, _, Some(_))) => Err(Error::Str("Account deleted")),
+      Some((status, expires_at, None)) => Status::allow(&status, expires_at),
+      None => Err(Error::InvalidCredentials),
     }
+  }
+
+  /// Token to act as `user_id`, for admins
+  /// helping them.
+  pub fn impersonate(
+    connection: &Connection,
+    impersonate: &ImpersonationGenerator,
+    admin_id: &Uuid,
+    user_id: &Uuid,
+  ) -> Result<String, Error> {
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::Impersonate,
+      Resource::User(user_id),
+    )?;
+
+    impersonate(*user_id, *admin_id)
+  }
+
+  /// Gives `user_id` `role`, for admins with every
+  /// permission it grants.
+  pub fn assign_role(
+    connection: &Connection,
+    admin_id: &Uuid,
+    user_id: &Uuid,
+    role: &str,
+  ) -> Result<bool, Error> {
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::AssignRole,
+      Resource::User(user_id),
+    )?;
+    policy::authorize(
+      connection,
+      admin_id,
+      Action::AssignRole,
+      Resource::Role(role),
+    )?;
+
+    Ok(
+      diesel::update(users::table.find(user_id))
+        .set(users::role.eq(role))
+        .execute(connection)?
+        > 0,
+    )
+  }
+
+  /// Gives the user with `email` `role`, without
+  /// checks, for setting up the first superadmin.
+  pub fn assign_role_by_email(
+    connection: &Connection,
+    email: &str,
+    role: &str,
+  ) -> Result<bool, Error> {
+    role_exists(connection, role)?;
+
+    Ok(
+      diesel::update(users::table.filter(users::email.eq(email)))
+        .set(users::role.eq(role))
+        .execute(connection)?
+        > 0,
+    )
+  }
 
-    Ok(diesel::delete(users::table.find(user_id)).execute(connection)? > 0)
+  /// Fields of `user_id` changes are audited for,
+  /// without checks, or null for unknown users.
+  pub fn snapshot(connection: &Connection, user_id: &Uuid) -> Result<Value, Error> {
+    Ok(
+      users::table
+        .select(COLUMNS)
+        .find(user_id)
+        .filter(users::deleted_at.is_null())
+        .first::<User>(connection)
+        .optional()?
+        .map(|user| {
+          json!({
+            "email": user.email,
+            "name": user.name,
+            "role": user.role,
+            "status": user.status,
+          })
+        })
+        .unwrap_or(Value::Null),
+    )
   }
 
+  /// Email for `user_id`.
+  pub fn email(connection: &Connection, user_id: &Uuid) -> Result<String, Error> {
+    Ok(
+      users::table
+        .select(users::email)
+        .find(user_id)
+        .filter(users::deleted_at.is_null())
+        .first::<String>(connection)?,
+    )
+  }
+
+  /// Exchanges an email and password for a token.
+  /// Unknown emails are verified against `dummy_hash`
+  /// so they take as long as wrong passwords, and both
+  /// return the same error. Users with MFA enabled
+  /// get a `challenge` token to verify their code
+  /// with instead.
   pub fn login(
     connection: &Connection,
     verify: &HashVerifier,
+    dummy_hash: &str,
     tokenise: &TokenGenerator,
+    challenge: &TokenGenerator,
     user: &UserLogin,
   ) -> Result<String, Error> {
-    let (id, password_hash) = users::table
+    let found = users::table
       .filter(users::email.eq(&user.email))
-      .select((users::id, users::password))
-      .first::<(Uuid, String)>(connection)?;
+      .filter(users::deleted_at.is_null())
+      .select((
+        users::id,
+        users::password,
+        users::status,
+        users::status_expires_at,
+      ))
+      .first::<(Uuid, String, String, Option<DateTime<Utc>>)>(connection)
+      .optional()?;
 
-    if verify(&password_hash, &user.password)? {
-      Ok(tokenise(id)?)
-    } else {
-      Err(Error::Str("Not found"))
+    match found {
+      Some((id, password_hash, status, expires_at)) => {
+        if !verify(&password_hash, &user.password)? {
+          Err(Error::InvalidCredentials)
+        } else if let Err(err) = Status::allow(&status, expires_at) {
+          Err(err)
+        } else if Mfa::enabled(connection, &id)? {
+          Err(Error::MfaRequired(challenge(id)?))
+        } else {
+          Ok(tokenise(id)?)
+        }
+      }
+      None => {
+        let _ = verify(dummy_hash, &user.password);
+
+        Err(Error::InvalidCredentials)
+      }
     }
   }
 }
+
+fn role_exists(connection: &Connection, role: &str) -> Result<(), Error> {
+  roles::table
+    .find(role)
+    .select(roles::name)
+    .first::<String>(connection)
+    .optional()?
+    .map(|_| ())
+    .ok_or(Error::Str("Unknown role"))
+}
@@ -1,6 +1,6 @@
 use crate::db::Connection;
 use crate::error::Error;
-use crate::models::group::Group;
+use crate::models::group::{self, Group};
 use crate::models::schema::{groups, users_groups};
 use crate::models::user::User;
 use chrono::{DateTime, Utc};
@@ -52,12 +52,15 @@ impl UserGroup {
   pub fn read_groups(connection: &Connection, user_id: &Uuid) -> Result<Vec<Group>, Error> {
     use diesel::pg::expression::dsl::any;
 
-    let user = User::read(connection, user_id, user_id)?;
-    let group_ids = UserGroup::belonging_to(&user).select(users_groups::group_id);
+    let group_ids = users_groups::table
+      .filter(users_groups::user_id.eq(user_id))
+      .select(users_groups::group_id);
 
     Ok(
       groups::table
+        .select(group::COLUMNS)
         .filter(groups::id.eq(any(group_ids)))
+        .filter(groups::deleted_at.is_null())
         .load::<Group>(connection)?,
     )
   }
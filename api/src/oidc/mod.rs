@@ -0,0 +1,480 @@
+use crate::config::OidcProvider;
+use crate::error::Error;
+use crate::logger;
+use crate::tls::Connector;
+use futures::{Future, Stream};
+use hyper::{header, Body, Client, Method, Request, StatusCode};
+use jwt::{decode, decode_header, Algorithm, Validation};
+use log::Level;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Timeout;
+use url::Url;
+
+/// Scopes requested, enough for the ID token to
+/// include an email and name.
+const SCOPES: &str = "openid email profile";
+/// How long discovery documents and keys are
+/// cached for before being fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+/// Clock skew allowed when validating ID tokens.
+const LEEWAY: i64 = 60;
+/// Longest a provider has to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest response read, discovery documents,
+/// key sets and tokens are all small.
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+/// OpenID Connect relying party, logging users in
+/// with the authorization code flow and PKCE.
+pub struct Oidc {
+  providers: Vec<OidcProvider>,
+  metadata: Mutex<HashMap<String, Arc<Metadata>>>,
+}
+
+/// A provider's endpoints and signing keys.
+struct Metadata {
+  fetched: Instant,
+  authorization_endpoint: String,
+  token_endpoint: String,
+  keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+  keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+  kty: String,
+  kid: Option<String>,
+  n: Option<String>,
+  e: Option<String>,
+}
+
+/// Where to send a user to log in, with the
+/// secrets to keep until they return.
+pub struct Authorization {
+  pub url: String,
+  pub state: String,
+  pub nonce: String,
+  pub code_verifier: String,
+}
+
+/// Verified claims from an ID token.
+#[derive(Deserialize)]
+pub struct IdClaims {
+  pub sub: String,
+  pub email: Option<String>,
+  #[serde(default)]
+  pub email_verified: bool,
+  pub name: Option<String>,
+  nonce: Option<String>,
+}
+
+impl Oidc {
+  pub fn new(providers: Vec<OidcProvider>) -> Oidc {
+    Oidc {
+      providers,
+      metadata: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// Starts logging in with `provider`.
+  pub fn authorize(&self, provider: &str) -> Result<Authorization, Error> {
+    let provider = self.provider(provider)?;
+    let metadata = self.metadata(provider, false)?;
+    let state = random()?;
+    let nonce = random()?;
+    let code_verifier = random()?;
+    let code_challenge = encode(digest::digest(&digest::SHA256, code_verifier.as_bytes()).as_ref());
+    let url = Url::parse_with_params(
+      &metadata.authorization_endpoint,
+      &[
+        ("response_type", "code"),
+        ("client_id", &provider.client_id),
+        ("redirect_uri", &provider.redirect_uri),
+        ("scope", SCOPES),
+        ("state", &state),
+        ("nonce", &nonce),
+        ("code_challenge", &code_challenge),
+        ("code_challenge_method", "S256"),
+      ],
+    )
+    .map_err(|_| Error::OidcProvider("OIDC provider URL is invalid"))?;
+
+    Ok(Authorization {
+      url: url.into_string(),
+      state,
+      nonce,
+      code_verifier,
+    })
+  }
+
+  /// Exchanges the `code` a user returned with for
+  /// their ID token, and verifies it.
+  pub fn exchange(
+    &self,
+    provider: &str,
+    code: &str,
+    code_verifier: &str,
+    nonce: &str,
+  ) -> Result<IdClaims, Error> {
+    let provider = self.provider(provider)?;
+    let mut form = vec![
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", provider.redirect_uri.as_str()),
+      ("client_id", provider.client_id.as_str()),
+      ("code_verifier", code_verifier),
+    ];
+
+    if let Some(ref client_secret) = provider.client_secret {
+      form.push(("client_secret", client_secret));
+    }
+
+    let tokens = post(&self.metadata(provider, false)?.token_endpoint, &form)?;
+    let id_token = tokens["id_token"]
+      .as_str()
+      .ok_or(Error::InvalidOidcLogin("Invalid OIDC login - No ID token"))?;
+    let kid = decode_header(id_token)?.kid;
+    let key = match self.metadata(provider, false)?.key(&kid) {
+      Some(key) => key,
+      // Keys may have rotated since they were cached.
+      None => self
+        .metadata(provider, true)?
+        .key(&kid)
+        .ok_or(Error::InvalidOidcLogin(
+          "Invalid OIDC login - Unknown signing key",
+        ))?,
+    };
+
+    let mut validation = Validation {
+      iss: Some(provider.issuer.clone()),
+      leeway: LEEWAY,
+      ..Validation::new(Algorithm::RS256)
+    };
+    validation.set_audience(&provider.client_id);
+
+    let claims = decode::<IdClaims>(id_token, &key, &validation)?.claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+      return Err(Error::InvalidOidcLogin(
+        "Invalid OIDC login - Nonce mismatch",
+      ));
+    }
+
+    Ok(claims)
+  }
+
+  fn provider(&self, name: &str) -> Result<&OidcProvider, Error> {
+    self
+      .providers
+      .iter()
+      .find(|provider| provider.name == name)
+      .ok_or(Error::Str("Unknown OIDC provider"))
+  }
+
+  /// Cached metadata for `provider`, fetched again
+  /// once stale or when `refresh` is set.
+  fn metadata(&self, provider: &OidcProvider, refresh: bool) -> Result<Arc<Metadata>, Error> {
+    if !refresh {
+      let cached = self.metadata.lock().expect("metadata lock is poisoned");
+
+      if let Some(metadata) = cached.get(&provider.name) {
+        if metadata.fetched.elapsed() < CACHE_TTL {
+          return Ok(metadata.clone());
+        }
+      }
+    }
+
+    let issuer = provider.issuer.trim_end_matches('/');
+    let discovery = get(&format!("{}/.well-known/openid-configuration", issuer))?;
+
+    if discovery["issuer"]
+      .as_str()
+      .map(|iss| iss.trim_end_matches('/'))
+      != Some(issuer)
+    {
+      return Err(Error::OidcProvider("OIDC provider issuer mismatch"));
+    }
+
+    let endpoint = |name: &str| {
+      discovery[name]
+        .as_str()
+        .map(str::to_string)
+        .ok_or(Error::OidcProvider("OIDC provider discovery is incomplete"))
+    };
+    let jwks: Jwks = serde_json::from_value(get(&endpoint("jwks_uri")?)?)?;
+    let metadata = Arc::new(Metadata {
+      fetched: Instant::now(),
+      authorization_endpoint: endpoint("authorization_endpoint")?,
+      token_endpoint: endpoint("token_endpoint")?,
+      keys: jwks.keys,
+    });
+
+    self
+      .metadata
+      .lock()
+      .expect("metadata lock is poisoned")
+      .insert(provider.name.clone(), metadata.clone());
+
+    Ok(metadata)
+  }
+}
+
+impl Metadata {
+  /// RSA key with `kid`, or the only one if the
+  /// token doesn't say, as DER for `jsonwebtoken`.
+  fn key(&self, kid: &Option<String>) -> Option<Vec<u8>> {
+    let mut keys = self.keys.iter().filter(|key| key.kty == "RSA");
+    let key = match kid {
+      Some(kid) => keys.find(|key| key.kid.as_ref() == Some(kid))?,
+      None => {
+        let key = keys.next()?;
+
+        if keys.next().is_some() {
+          return None;
+        }
+
+        key
+      }
+    };
+    let n = base64::decode_config(key.n.as_ref()?, base64::URL_SAFE_NO_PAD).ok()?;
+    let e = base64::decode_config(key.e.as_ref()?, base64::URL_SAFE_NO_PAD).ok()?;
+
+    Some(rsa_public_key(&n, &e))
+  }
+}
+
+/// Fetches JSON from `url`.
+fn get(url: &str) -> Result<Value, Error> {
+  request(url, None)
+}
+
+/// Posts `form` to `url`, returning its JSON response.
+fn post(url: &str, form: &[(&str, &str)]) -> Result<Value, Error> {
+  let body = url::form_urlencoded::Serializer::new(String::new())
+    .extend_pairs(form)
+    .finish();
+
+  request(url, Some(body))
+}
+
+/// Blocking request to `url`, on a thread of its own
+/// as resolvers already run on the server's runtime.
+fn request(url: &str, form: Option<String>) -> Result<Value, Error> {
+  let url = Url::parse(url).map_err(|_| Error::OidcProvider("OIDC provider URL is invalid"))?;
+  let mut builder = Request::builder();
+  builder
+    .uri(url.as_str())
+    .header(header::ACCEPT, "application/json");
+  let request = match form {
+    Some(form) => builder
+      .method(Method::POST)
+      .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+      .body(Body::from(form)),
+    None => builder.body(Body::empty()),
+  }
+  .map_err(|_| Error::OidcProvider("OIDC provider URL is invalid"))?;
+
+  let (status, body) = thread::spawn(move || send(request))
+    .join()
+    .map_err(|_| Error::Str("OIDC provider request panicked"))??;
+
+  parse(&url, status, &body)
+}
+
+fn send(request: Request<Body>) -> Result<(StatusCode, Vec<u8>), Error> {
+  let client = Client::builder().keep_alive(false).build(Connector::new()?);
+  let sent = client
+    .request(request)
+    .map_err(unreachable)
+    .and_then(|response| {
+      let status = response.status();
+
+      response
+        .into_body()
+        .map_err(unreachable)
+        .fold(Vec::new(), |mut body, chunk| {
+          if body.len() + chunk.len() > MAX_RESPONSE {
+            return Err(Error::OidcProvider("OIDC provider response is too large"));
+          }
+
+          body.extend_from_slice(&chunk);
+          Ok(body)
+        })
+        .map(move |body| (status, body))
+    });
+
+  Runtime::new()?
+    .block_on(Timeout::new(sent, TIMEOUT))
+    .map_err(|err| {
+      err
+        .into_inner()
+        .unwrap_or(Error::OidcProvider("OIDC provider timed out"))
+    })
+}
+
+fn unreachable(err: hyper::Error) -> Error {
+  logger::event(
+    Level::Warn,
+    "OIDC provider unreachable",
+    json!({ "error": err.to_string() }),
+  );
+
+  Error::OidcProvider("OIDC provider unreachable")
+}
+
+/// Parses a response's JSON body, refusing
+/// anything but success.
+fn parse(url: &Url, status: StatusCode, body: &[u8]) -> Result<Value, Error> {
+  let body = serde_json::from_slice::<Value>(body)
+    .map_err(|_| Error::OidcProvider("OIDC provider response is invalid"))?;
+
+  if !status.is_success() {
+    logger::event(
+      Level::Warn,
+      "OIDC provider request failed",
+      json!({
+        "url": format!("{}{}", url.origin().ascii_serialization(), url.path()),
+        "status": status.as_u16(),
+        "error": body["error"],
+      }),
+    );
+
+    return Err(Error::OidcProvider("OIDC provider request failed"));
+  }
+
+  Ok(body)
+}
+
+/// Random base64url string for states, nonces
+/// and PKCE verifiers.
+fn random() -> Result<String, Error> {
+  let mut bytes = [0; 32];
+  SystemRandom::new()
+    .fill(&mut bytes)
+    .map_err(|_| Error::Str("Failed to generate random bytes"))?;
+
+  Ok(encode(&bytes))
+}
+
+fn encode(bytes: &[u8]) -> String {
+  base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// DER `RSAPublicKey` (RFC 8017) from a modulus
+/// and exponent.
+fn rsa_public_key(n: &[u8], e: &[u8]) -> Vec<u8> {
+  let mut integers = der_integer(n);
+  integers.extend(der_integer(e));
+
+  der(0x30, &integers)
+}
+
+/// Unsigned big-endian integer, with a leading
+/// zero if it would otherwise read as negative.
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+  let bytes = &bytes[bytes.iter().take_while(|&&byte| byte == 0).count()..];
+  let mut value = Vec::with_capacity(bytes.len() + 1);
+
+  if bytes.first().is_none_or(|byte| byte & 0x80 != 0) {
+    value.push(0);
+  }
+
+  value.extend_from_slice(bytes);
+
+  der(0x02, &value)
+}
+
+fn der(tag: u8, value: &[u8]) -> Vec<u8> {
+  let mut encoded = vec![tag];
+  let length = (value.len() as u32).to_be_bytes();
+  let length = &length[length.iter().take_while(|&&byte| byte == 0).count()..];
+
+  if value.len() < 0x80 {
+    encoded.push(value.len() as u8);
+  } else {
+    encoded.push(0x80 | length.len() as u8);
+    encoded.extend_from_slice(length);
+  }
+
+  encoded.extend_from_slice(value);
+  encoded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn url() -> Url {
+    Url::parse("http://localhost/token").unwrap()
+  }
+
+  #[test]
+  fn test_parse() {
+    assert_eq!(
+      parse(&url(), StatusCode::OK, b"{\"a\":1}").unwrap(),
+      json!({ "a": 1 })
+    );
+  }
+
+  #[test]
+  fn test_parse_error_status() {
+    let body = b"{\"error\":\"invalid_grant\"}";
+
+    assert!(parse(&url(), StatusCode::BAD_REQUEST, body).is_err());
+  }
+
+  #[test]
+  fn test_parse_invalid() {
+    assert!(parse(&url(), StatusCode::OK, b"").is_err());
+    assert!(parse(&url(), StatusCode::OK, b"not json").is_err());
+  }
+
+  #[test]
+  fn test_der_integer() {
+    assert_eq!(
+      der_integer(&[0x01, 0x00, 0x01]),
+      vec![0x02, 0x03, 0x01, 0x00, 0x01]
+    );
+    assert_eq!(der_integer(&[0x00, 0x80]), vec![0x02, 0x02, 0x00, 0x80]);
+    assert_eq!(der_integer(&[]), vec![0x02, 0x01, 0x00]);
+  }
+
+  #[test]
+  fn test_der_long_length() {
+    let encoded = der(0x04, &[0; 300]);
+
+    assert_eq!(&encoded[..4], &[0x04, 0x82, 0x01, 0x2c]);
+    assert_eq!(encoded.len(), 304);
+  }
+
+  #[test]
+  fn test_key_by_kid() {
+    let jwk = |kid: &str| Jwk {
+      kty: "RSA".to_string(),
+      kid: Some(kid.to_string()),
+      n: Some("AQAB".to_string()),
+      e: Some("AQAB".to_string()),
+    };
+    let metadata = Metadata {
+      fetched: Instant::now(),
+      authorization_endpoint: String::new(),
+      token_endpoint: String::new(),
+      keys: vec![jwk("a"), jwk("b")],
+    };
+
+    assert!(metadata.key(&Some("b".to_string())).is_some());
+    assert!(metadata.key(&Some("c".to_string())).is_none());
+    assert!(metadata.key(&None).is_none());
+  }
+}
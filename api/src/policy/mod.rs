@@ -0,0 +1,503 @@
+use crate::db::Connection;
+use crate::error::Error;
+use crate::logger;
+use crate::models::schema::{role_permissions, roles, users, users_groups};
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use log::Level;
+use uuid::Uuid;
+
+/// Something a role allows on other users' accounts.
+/// Everyone can act on their own account without one.
+/// Roles and what they grant are kept in the
+/// `role_permissions` table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+  ListUsers,
+  ReadUsers,
+  UpdateUsers,
+  DeleteUsers,
+  SuspendUsers,
+  ImpersonateUsers,
+  AssignRoles,
+  ReadAuditEvents,
+}
+
+impl Permission {
+  pub const ALL: [Permission; 8] = [
+    Permission::ListUsers,
+    Permission::ReadUsers,
+    Permission::UpdateUsers,
+    Permission::DeleteUsers,
+    Permission::SuspendUsers,
+    Permission::ImpersonateUsers,
+    Permission::AssignRoles,
+    Permission::ReadAuditEvents,
+  ];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Permission::ListUsers => "list_users",
+      Permission::ReadUsers => "read_users",
+      Permission::UpdateUsers => "update_users",
+      Permission::DeleteUsers => "delete_users",
+      Permission::SuspendUsers => "suspend_users",
+      Permission::ImpersonateUsers => "impersonate_users",
+      Permission::AssignRoles => "assign_roles",
+      Permission::ReadAuditEvents => "read_audit_events",
+    }
+  }
+
+  pub fn parse(permission: &str) -> Option<Permission> {
+    Permission::ALL
+      .iter()
+      .cloned()
+      .find(|known| known.as_str() == permission)
+  }
+}
+
+/// What an actor wants to do to a `Resource`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+  Read,
+  Update,
+  Delete,
+  Restore,
+  List,
+  Suspend,
+  Reinstate,
+  Impersonate,
+  AssignRole,
+}
+
+impl Action {
+  pub const ALL: [Action; 9] = [
+    Action::Read,
+    Action::Update,
+    Action::Delete,
+    Action::Restore,
+    Action::List,
+    Action::Suspend,
+    Action::Reinstate,
+    Action::Impersonate,
+    Action::AssignRole,
+  ];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Action::Read => "read",
+      Action::Update => "update",
+      Action::Delete => "delete",
+      Action::Restore => "restore",
+      Action::List => "list",
+      Action::Suspend => "suspend",
+      Action::Reinstate => "reinstate",
+      Action::Impersonate => "impersonate",
+      Action::AssignRole => "assign_role",
+    }
+  }
+}
+
+/// What an `Action` is done to.
+pub enum Resource<'a> {
+  User(&'a Uuid),
+  /// Every user, e.g. to search them.
+  Users,
+  Group(&'a Uuid),
+  Role(&'a str),
+  /// The audit log.
+  AuditEvents,
+}
+
+impl<'a> Resource<'a> {
+  fn describe(&self) -> String {
+    match self {
+      Resource::User(id) => format!("user:{}", id),
+      Resource::Users => "users".to_string(),
+      Resource::Group(id) => format!("group:{}", id),
+      Resource::Role(name) => format!("role:{}", name),
+      Resource::AuditEvents => "audit_events".to_string(),
+    }
+  }
+}
+
+/// Who is acting, with what their role grants.
+struct Actor {
+  id: Uuid,
+  permissions: Vec<Permission>,
+}
+
+/// What decisions about a `Resource` depend on.
+enum Facts {
+  User {
+    id: Uuid,
+    permissions: Vec<Permission>,
+  },
+  Users,
+  Group {
+    member: bool,
+  },
+  Role {
+    permissions: Vec<Permission>,
+  },
+  AuditEvents,
+}
+
+#[derive(Debug, PartialEq)]
+enum Decision {
+  Allow,
+  /// Refused, with why.
+  Deny(&'static str),
+}
+
+/// Refuses `actor_id` doing `action` to `resource`
+/// unless a policy allows it, logging the decision.
+pub fn authorize(
+  connection: &Connection,
+  actor_id: &Uuid,
+  action: Action,
+  resource: Resource,
+) -> Result<(), Error> {
+  let actor = Actor {
+    id: *actor_id,
+    permissions: permissions(connection, actor_id)?,
+  };
+  let facts = facts(connection, &actor, &resource)?;
+  let decision = decide(&actor, action, &facts);
+  let (level, reason) = match decision {
+    Decision::Allow => (Level::Debug, None),
+    Decision::Deny(reason) => (Level::Info, Some(reason)),
+  };
+
+  logger::event(
+    level,
+    "Authorization decision",
+    json!({
+      "actor": actor.id,
+      "action": action.as_str(),
+      "resource": resource.describe(),
+      "decision": if reason.is_none() { "allow" } else { "deny" },
+      "reason": reason,
+    }),
+  );
+
+  match reason {
+    None => Ok(()),
+    Some(reason) => Err(Error::Unauthorised(reason)),
+  }
+}
+
+/// Policies as (actor, action, resource) → decision.
+/// Anything not allowed here is denied.
+fn decide(actor: &Actor, action: Action, facts: &Facts) -> Decision {
+  let granted = |permission: &Permission| actor.permissions.contains(permission);
+  // Admins can only act on users and roles with no
+  // permissions they lack, so support can't act
+  // on superadmins.
+  let outranks = |permissions: &[Permission]| permissions.iter().all(granted);
+
+  match facts {
+    Facts::User { id, .. } if *id == actor.id => match action {
+      Action::Read | Action::Update | Action::Delete => Decision::Allow,
+      Action::Suspend => Decision::Deny("Unauthorised - Users can't suspend themselves"),
+      Action::Reinstate => Decision::Deny("Unauthorised - Users can't reinstate themselves"),
+      Action::Restore => Decision::Deny("Unauthorised - Users can't restore themselves"),
+      Action::Impersonate => Decision::Deny("Unauthorised - Users can't impersonate themselves"),
+      Action::AssignRole => Decision::Deny("Unauthorised - Users can't change their own role"),
+      Action::List => Decision::Deny("Unauthorised - Not allowed"),
+    },
+    Facts::User { permissions, .. } => {
+      let (permission, denied) = match action {
+        Action::Read => (
+          Permission::ReadUsers,
+          "Unauthorised - Only the given user or an admin can view their account",
+        ),
+        Action::Update => (
+          Permission::UpdateUsers,
+          "Unauthorised - Only the given user or an admin can update their account",
+        ),
+        Action::Delete => (
+          Permission::DeleteUsers,
+          "Unauthorised - Only the given user or an admin can delete their account",
+        ),
+        Action::Restore => (
+          Permission::DeleteUsers,
+          "Unauthorised - Only admins can restore users",
+        ),
+        Action::Suspend => (
+          Permission::SuspendUsers,
+          "Unauthorised - Only admins can suspend users",
+        ),
+        Action::Reinstate => (
+          Permission::SuspendUsers,
+          "Unauthorised - Only admins can reinstate users",
+        ),
+        Action::Impersonate => (
+          Permission::ImpersonateUsers,
+          "Unauthorised - Only admins can impersonate users",
+        ),
+        Action::AssignRole => (
+          Permission::AssignRoles,
+          "Unauthorised - Only admins can assign roles",
+        ),
+        Action::List => return Decision::Deny("Unauthorised - Not allowed"),
+      };
+
+      if granted(&permission) && outranks(permissions) {
+        Decision::Allow
+      } else {
+        Decision::Deny(denied)
+      }
+    }
+    Facts::Users => match action {
+      Action::List if granted(&Permission::ListUsers) => Decision::Allow,
+      Action::List => Decision::Deny("Unauthorised - Only admins can list users"),
+      _ => Decision::Deny("Unauthorised - Not allowed"),
+    },
+    Facts::Group { member } => match action {
+      Action::Read | Action::Update | Action::Delete | Action::Restore if *member => {
+        Decision::Allow
+      }
+      Action::Read | Action::Update | Action::Delete | Action::Restore => {
+        Decision::Deny("Unauthorised - Only members can access the group")
+      }
+      _ => Decision::Deny("Unauthorised - Not allowed"),
+    },
+    Facts::Role { permissions } => match action {
+      Action::AssignRole if granted(&Permission::AssignRoles) && outranks(permissions) => {
+        Decision::Allow
+      }
+      Action::AssignRole => {
+        Decision::Deny("Unauthorised - Only admins with every permission of a role can assign it")
+      }
+      _ => Decision::Deny("Unauthorised - Not allowed"),
+    },
+    Facts::AuditEvents => match action {
+      Action::List if granted(&Permission::ReadAuditEvents) => Decision::Allow,
+      Action::List => Decision::Deny("Unauthorised - Only admins can view the audit log"),
+      _ => Decision::Deny("Unauthorised - Not allowed"),
+    },
+  }
+}
+
+/// Loads what deciding on `resource` depends on.
+fn facts(connection: &Connection, actor: &Actor, resource: &Resource) -> Result<Facts, Error> {
+  Ok(match *resource {
+    Resource::User(id) if *id == actor.id => Facts::User {
+      id: *id,
+      permissions: actor.permissions.clone(),
+    },
+    Resource::User(id) => Facts::User {
+      id: *id,
+      permissions: permissions(connection, id)?,
+    },
+    Resource::Users => Facts::Users,
+    Resource::Group(id) => Facts::Group {
+      member: diesel::select(exists(
+        users_groups::table
+          .filter(users_groups::user_id.eq(actor.id))
+          .filter(users_groups::group_id.eq(id)),
+      ))
+      .get_result(connection)?,
+    },
+    Resource::Role(name) => {
+      let known = diesel::select(exists(roles::table.find(name))).get_result::<bool>(connection)?;
+
+      if !known {
+        return Err(Error::Str("Unknown role"));
+      }
+
+      Facts::Role {
+        permissions: role_permissions(connection, name)?,
+      }
+    }
+    Resource::AuditEvents => Facts::AuditEvents,
+  })
+}
+
+/// Permissions granted to `role`.
+fn role_permissions(connection: &Connection, role: &str) -> Result<Vec<Permission>, Error> {
+  Ok(
+    role_permissions::table
+      .filter(role_permissions::role.eq(role))
+      .select(role_permissions::permission)
+      .load::<String>(connection)?
+      .iter()
+      .filter_map(|permission| Permission::parse(permission))
+      .collect(),
+  )
+}
+
+/// Permissions granted to `user_id` by their role,
+/// none for unknown users.
+fn permissions(connection: &Connection, user_id: &Uuid) -> Result<Vec<Permission>, Error> {
+  match users::table
+    .find(user_id)
+    .select(users::role)
+    .first::<String>(connection)
+    .optional()?
+  {
+    Some(role) => role_permissions(connection, &role),
+    None => Ok(Vec::new()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SUPPORT: [Permission; 3] = [
+    Permission::ListUsers,
+    Permission::ReadUsers,
+    Permission::SuspendUsers,
+  ];
+
+  fn id(n: u8) -> Uuid {
+    Uuid::from_slice(&[n; 16]).unwrap()
+  }
+
+  fn actor(role: &str) -> Actor {
+    Actor {
+      id: id(0),
+      permissions: match role {
+        "superadmin" => Permission::ALL.to_vec(),
+        "support" => SUPPORT.to_vec(),
+        _ => Vec::new(),
+      },
+    }
+  }
+
+  /// Every kind of resource, by name.
+  fn resources(actor: &Actor) -> Vec<(&'static str, Facts)> {
+    vec![
+      (
+        "self",
+        Facts::User {
+          id: actor.id,
+          permissions: actor.permissions.clone(),
+        },
+      ),
+      (
+        "user",
+        Facts::User {
+          id: id(1),
+          permissions: Vec::new(),
+        },
+      ),
+      (
+        "support",
+        Facts::User {
+          id: id(2),
+          permissions: SUPPORT.to_vec(),
+        },
+      ),
+      (
+        "superadmin",
+        Facts::User {
+          id: id(3),
+          permissions: Permission::ALL.to_vec(),
+        },
+      ),
+      ("users", Facts::Users),
+      ("member group", Facts::Group { member: true }),
+      ("other group", Facts::Group { member: false }),
+      (
+        "user role",
+        Facts::Role {
+          permissions: Vec::new(),
+        },
+      ),
+      (
+        "support role",
+        Facts::Role {
+          permissions: SUPPORT.to_vec(),
+        },
+      ),
+      (
+        "superadmin role",
+        Facts::Role {
+          permissions: Permission::ALL.to_vec(),
+        },
+      ),
+      ("audit events", Facts::AuditEvents),
+    ]
+  }
+
+  /// Actions each role is allowed on each resource,
+  /// everything else must be denied.
+  fn allowed(role: &str, resource: &str) -> Vec<Action> {
+    use self::Action::*;
+
+    match (role, resource) {
+      (_, "self") => vec![Read, Update, Delete],
+      (_, "member group") => vec![Read, Update, Delete, Restore],
+      ("superadmin", "user") | ("superadmin", "support") | ("superadmin", "superadmin") => {
+        vec![
+          Read,
+          Update,
+          Delete,
+          Restore,
+          Suspend,
+          Reinstate,
+          Impersonate,
+          AssignRole,
+        ]
+      }
+      ("support", "user") | ("support", "support") => vec![Read, Suspend, Reinstate],
+      ("superadmin", "users") | ("support", "users") => vec![List],
+      ("superadmin", "audit events") => vec![List],
+      ("superadmin", "user role")
+      | ("superadmin", "support role")
+      | ("superadmin", "superadmin role") => vec![AssignRole],
+      _ => Vec::new(),
+    }
+  }
+
+  #[test]
+  fn test_decide() {
+    for role in &["superadmin", "support", "user"] {
+      let actor = actor(role);
+
+      for (resource, facts) in resources(&actor) {
+        let allowed = allowed(role, resource);
+
+        for action in Action::ALL.iter() {
+          let decision = decide(&actor, *action, &facts);
+
+          assert_eq!(
+            decision == Decision::Allow,
+            allowed.contains(action),
+            "{} {} {}: {:?}",
+            role,
+            action.as_str(),
+            resource,
+            decision
+          );
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_decide_self_reason() {
+    let actor = actor("superadmin");
+    let facts = Facts::User {
+      id: actor.id,
+      permissions: Permission::ALL.to_vec(),
+    };
+
+    assert_eq!(
+      decide(&actor, Action::Suspend, &facts),
+      Decision::Deny("Unauthorised - Users can't suspend themselves")
+    );
+  }
+
+  #[test]
+  fn test_parse() {
+    for permission in Permission::ALL.iter() {
+      assert_eq!(Permission::parse(permission.as_str()), Some(*permission));
+    }
+
+    assert_eq!(Permission::parse("drop_tables"), None);
+  }
+}
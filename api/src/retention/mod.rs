@@ -0,0 +1,99 @@
+use crate::db::{Connection, Db};
+use crate::error::Error;
+use crate::exporter::Exporter;
+use crate::limiter::Limiter;
+use crate::logger;
+use crate::models::group::Group;
+use crate::models::session::Session;
+use crate::models::user::User;
+use chrono::{DateTime, Utc};
+use futures::{future::poll_fn, Future, Stream};
+use log::Level;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+use tokio_threadpool::blocking;
+
+/// Soft deleted rows older than this can't be
+/// restored, and are purged.
+pub fn cutoff(grace_period: Duration) -> Result<DateTime<Utc>, Error> {
+  Ok(
+    Utc::now()
+      - chrono::Duration::from_std(grace_period)
+        .map_err(|_| Error::Str("Invalid deletion grace period"))?,
+  )
+}
+
+/// Hard deletes users and groups soft deleted
+/// more than `grace_period` ago, returning how
+/// many of each.
+pub fn purge(connection: &Connection, grace_period: Duration) -> Result<(usize, usize), Error> {
+  let before = cutoff(grace_period)?;
+
+  Ok((
+    User::purge(connection, &before)?,
+    Group::purge(connection, &before)?,
+  ))
+}
+
+/// Purges every `period` on the blocking thread
+/// pool, along with expired sessions, data exports
+/// and rate limits, logging failures rather than
+/// stopping.
+pub fn purge_every(
+  db: Arc<Db>,
+  exporter: Arc<Exporter>,
+  limiter: Arc<Limiter>,
+  grace_period: Duration,
+  period: Duration,
+) -> impl Stream<Item = (), Error = ()> {
+  Interval::new(Instant::now(), period)
+    .map_err(|_| ())
+    .and_then(move |_| {
+      let db = db.clone();
+      let exporter = exporter.clone();
+      let limiter = limiter.clone();
+
+      poll_fn(move || {
+        blocking(|| {
+          let connection = db.connect()?;
+          let (users, groups) = purge(&connection, grace_period)?;
+
+          Ok((
+            users,
+            groups,
+            Session::purge(&connection)?,
+            exporter.purge()?,
+            limiter.purge(&connection)?,
+          ))
+        })
+      })
+      .then(|result: Result<Result<_, Error>, _>| {
+        match result {
+          Ok(Ok((users, groups, sessions, exports, rate_limits))) => logger::event(
+            Level::Info,
+            "Purged deleted rows",
+            json!({
+              "users": users,
+              "groups": groups,
+              "sessions": sessions,
+              "exports": exports,
+              "rate_limits": rate_limits,
+            }),
+          ),
+          Ok(Err(err)) => logger::event(
+            Level::Error,
+            "Purge failed",
+            json!({ "error": err.to_string() }),
+          ),
+          Err(_) => logger::event(
+            Level::Error,
+            "Purge failed",
+            json!({ "error": "Blocking thread pool unavailable" }),
+          ),
+        }
+
+        Ok(())
+      })
+    })
+}
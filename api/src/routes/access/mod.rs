@@ -0,0 +1,198 @@
+use crate::logger;
+use crate::trace::{Span, SpanContext, SpanKind};
+use log::Level;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Instant, SystemTime};
+use uuid::Uuid;
+use warp::http::header::{self, HeaderValue};
+use warp::http::{Method, Response};
+use warp::path::FullPath;
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+pub const REQUEST_ID: &str = "x-request-id";
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACERESPONSE: &str = "traceresponse";
+
+/// Extracts the `X-Request-Id` header, generating
+/// a new ID when it's missing or invalid.
+pub fn request_id() -> BoxedFilter<(String,)> {
+  warp::header::optional::<String>(REQUEST_ID)
+    .map(|request_id: Option<String>| match request_id {
+      Some(ref request_id) if is_valid(request_id) => request_id.clone(),
+      _ => Uuid::new_v4().to_string(),
+    })
+    .boxed()
+}
+
+/// Whether a propagated request ID is safe to log.
+fn is_valid(request_id: &str) -> bool {
+  !request_id.is_empty()
+    && request_id.len() <= 128
+    && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Extracts the client's IP address. When
+/// `trust_forwarded_for` (i.e. behind a gateway
+/// that appends to `X-Forwarded-For`) it's taken
+/// from the header's last entry, as earlier
+/// entries can be forged by the client.
+pub fn client_ip(trust_forwarded_for: bool) -> BoxedFilter<(Option<IpAddr>,)> {
+  warp::addr::remote()
+    .and(warp::header::optional::<String>("x-forwarded-for"))
+    .map(
+      move |remote: Option<SocketAddr>, forwarded_for: Option<String>| {
+        let forwarded = forwarded_for
+          .filter(|_| trust_forwarded_for)
+          .and_then(|forwarded_for| forwarded_for.rsplit(',').next()?.trim().parse().ok());
+
+        forwarded.or_else(|| remote.map(|remote| remote.ip()))
+      },
+    )
+    .boxed()
+}
+
+/// Extracts the span context propagated by a
+/// valid `traceparent` header.
+pub fn traceparent() -> BoxedFilter<(Option<SpanContext>,)> {
+  warp::header::optional::<String>(TRACEPARENT)
+    .map(|header: Option<String>| header.and_then(|header| SpanContext::from_traceparent(&header)))
+    .boxed()
+}
+
+/// Logs and traces each request handled by `filter`
+/// and sets the `X-Request-Id` and `traceresponse`
+/// response headers, unless the handler already set
+/// them. Rejections are turned into responses here
+/// so they're logged too.
+pub fn access(filter: BoxedFilter<(Response<Vec<u8>>,)>) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  warp::any()
+    .map(Instant::now)
+    .and(warp::method())
+    .and(warp::path::full())
+    .and(request_id())
+    .and(traceparent())
+    .and(filter.recover(rejection).unify())
+    .map(
+      |started: Instant,
+       method: Method,
+       path: FullPath,
+       request_id: String,
+       parent: Option<SpanContext>,
+       mut response: Response<Vec<u8>>| {
+        if !response.headers().contains_key(REQUEST_ID) {
+          response = with_request_id(response, &request_id);
+        }
+
+        // Handlers that start spans of their own respond
+        // with the request span's context to parent them.
+        let mut span = Span::new(
+          &format!("{} {}", method, path.as_str()),
+          SpanKind::Server,
+          parent.as_ref(),
+        )
+        .with_start(SystemTime::now() - started.elapsed());
+        let context = response
+          .headers()
+          .get(TRACERESPONSE)
+          .and_then(|header| header.to_str().ok())
+          .and_then(SpanContext::from_traceparent);
+
+        match context {
+          Some(context) => span = span.with_context(context),
+          None => response = with_traceresponse(response, span.context()),
+        }
+
+        let request_id = response
+          .headers()
+          .get(REQUEST_ID)
+          .and_then(|header| header.to_str().ok())
+          .unwrap_or(&request_id)
+          .to_string();
+        let status = response.status();
+
+        span.set_attribute("http.request.method", method.as_str());
+        span.set_attribute("url.path", path.as_str());
+        span.set_attribute("http.response.status_code", status.as_u16());
+        span.set_attribute("request_id", request_id.as_str());
+
+        if status.is_server_error() {
+          span.set_error(status.canonical_reason().unwrap_or("Server error"));
+        }
+
+        logger::event(
+          if status.is_server_error() {
+            Level::Error
+          } else {
+            Level::Info
+          },
+          "Request",
+          json!({
+            "request_id": request_id,
+            "method": method.as_str(),
+            "path": path.as_str(),
+            "status": status.as_u16(),
+            "duration_ms": started.elapsed().as_secs_f64() * 1000.0,
+          }),
+        );
+
+        response
+      },
+    )
+    .boxed()
+}
+
+/// Sets `X-Request-Id` on `response`.
+pub fn with_request_id(mut response: Response<Vec<u8>>, request_id: &str) -> Response<Vec<u8>> {
+  if let Ok(header) = HeaderValue::from_str(request_id) {
+    response.headers_mut().insert(REQUEST_ID, header);
+  }
+
+  response
+}
+
+/// Sets `traceresponse` on `response`, identifying
+/// the request's span to the caller.
+pub fn with_traceresponse(
+  mut response: Response<Vec<u8>>,
+  context: &SpanContext,
+) -> Response<Vec<u8>> {
+  if let Ok(header) = HeaderValue::from_str(&context.to_traceparent()) {
+    response.headers_mut().insert(TRACERESPONSE, header);
+  }
+
+  response
+}
+
+fn rejection(rejection: Rejection) -> Result<Response<Vec<u8>>, Rejection> {
+  let mut response = Response::builder();
+  response.status(rejection.status());
+
+  let body = match rejection.cause() {
+    Some(cause) => {
+      response.header(header::CONTENT_TYPE, "text/plain; charset=utf-8");
+      cause.to_string().into_bytes()
+    }
+    None => Vec::new(),
+  };
+
+  Ok(response.body(body).expect("response is valid"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_request_id_valid() {
+    assert!(is_valid("0f4c7a9e-5a4b-4f55-9d7e-1c6d2b7b4f3a"));
+    assert!(is_valid("gateway/1234"));
+  }
+
+  #[test]
+  fn test_request_id_invalid() {
+    assert!(!is_valid(""));
+    assert!(!is_valid("two words"));
+    assert!(!is_valid("line\nbreak"));
+    assert!(!is_valid(&"a".repeat(129)));
+  }
+}
@@ -0,0 +1,271 @@
+use crate::config::Config;
+use crate::routes::access::{REQUEST_ID, TRACERESPONSE};
+use std::sync::Arc;
+use warp::http::header::{self, HeaderValue};
+use warp::http::{Response, StatusCode};
+use warp::{filters::BoxedFilter, Filter};
+
+/// Who can call an endpoint from browsers on
+/// other origins, and how.
+struct Cors {
+  origins: Vec<String>,
+  methods: Vec<String>,
+  headers: Vec<String>,
+  credentials: bool,
+  max_age: u64,
+}
+
+/// Lets browsers on the configured origins call
+/// `filter`. Preflight `OPTIONS` requests are
+/// answered `204 No Content` with what's allowed,
+/// or `403 Forbidden` without CORS headers when the
+/// origin, method or a header isn't allowed. Other
+/// responses get CORS headers for allowed origins.
+/// `filter` is returned as is when no origins are
+/// configured.
+pub fn cors(
+  config: &Config,
+  filter: BoxedFilter<(Response<Vec<u8>>,)>,
+) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  if config.cors_origins.is_empty() {
+    return filter;
+  }
+
+  let cors = Arc::new(Cors {
+    origins: config.cors_origins.clone(),
+    methods: config.cors_methods.clone(),
+    headers: config.cors_headers.clone(),
+    credentials: config.cors_credentials,
+    max_age: config.cors_max_age.as_secs(),
+  });
+  let actual_cors = cors.clone();
+
+  let preflight = warp::options()
+    .and(warp::header::<String>("origin"))
+    .and(warp::header::<String>("access-control-request-method"))
+    .and(warp::header::optional::<String>(
+      "access-control-request-headers",
+    ))
+    .map(
+      move |origin: String, method: String, headers: Option<String>| {
+        cors.preflight(&origin, &method, headers.as_deref())
+      },
+    );
+
+  let actual = warp::header::optional::<String>("origin").and(filter).map(
+    move |origin: Option<String>, response: Response<Vec<u8>>| {
+      actual_cors.actual(origin.as_deref(), response)
+    },
+  );
+
+  preflight.or(actual).unify().boxed()
+}
+
+impl Cors {
+  fn allows_origin(&self, origin: &str) -> bool {
+    let origin = origin.to_lowercase();
+
+    self
+      .origins
+      .iter()
+      .any(|pattern| matches(pattern.as_bytes(), origin.as_bytes()))
+  }
+
+  fn preflight(&self, origin: &str, method: &str, headers: Option<&str>) -> Response<Vec<u8>> {
+    let allowed = self.allows_origin(origin)
+      && self.methods.iter().any(|allowed| allowed == method)
+      && headers
+        .unwrap_or("")
+        .split(',')
+        .map(|header| header.trim().to_lowercase())
+        .filter(|header| !header.is_empty())
+        .all(|header| self.headers.contains(&header));
+
+    let mut response = Response::builder();
+    response.header(header::VARY, "Origin");
+
+    if !allowed {
+      return response
+        .status(StatusCode::FORBIDDEN)
+        .body(Vec::new())
+        .expect("response is valid");
+    }
+
+    response
+      .status(StatusCode::NO_CONTENT)
+      .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+      .header(
+        header::ACCESS_CONTROL_ALLOW_METHODS,
+        self.methods.join(", ").as_str(),
+      )
+      .header(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        self.headers.join(", ").as_str(),
+      )
+      .header(header::ACCESS_CONTROL_MAX_AGE, self.max_age);
+
+    if self.credentials {
+      response.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+    }
+
+    response.body(Vec::new()).expect("response is valid")
+  }
+
+  fn actual(&self, origin: Option<&str>, mut response: Response<Vec<u8>>) -> Response<Vec<u8>> {
+    let headers = response.headers_mut();
+    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+
+    let origin = match origin
+      .filter(|origin| self.allows_origin(origin))
+      .and_then(|origin| HeaderValue::from_str(origin).ok())
+    {
+      Some(origin) => origin,
+      None => return response,
+    };
+
+    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    headers.insert(
+      header::ACCESS_CONTROL_EXPOSE_HEADERS,
+      HeaderValue::from_str(&format!("{}, {}", REQUEST_ID, TRACERESPONSE))
+        .expect("header is valid"),
+    );
+
+    if self.credentials {
+      headers.insert(
+        header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+        HeaderValue::from_static("true"),
+      );
+    }
+
+    response
+  }
+}
+
+/// Whether `origin` matches `pattern`, where `*`
+/// matches one or more host characters (letters,
+/// digits, `-` and `.`), so it can't reach into the
+/// scheme or port. `*` alone matches any origin.
+fn matches(pattern: &[u8], origin: &[u8]) -> bool {
+  if pattern == b"*" {
+    return true;
+  }
+
+  match pattern.iter().position(|&byte| byte == b'*') {
+    None => pattern == origin,
+    Some(star) => {
+      let (prefix, rest) = (&pattern[..star], &pattern[star + 1..]);
+
+      origin.starts_with(prefix) && {
+        let tail = &origin[prefix.len()..];
+
+        tail
+          .iter()
+          .take_while(|&&byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'.')
+          .enumerate()
+          .any(|(index, _)| matches(rest, &tail[index + 1..]))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cors(credentials: bool) -> Cors {
+    Cors {
+      origins: vec![
+        "https://app.example.com".to_string(),
+        "https://*.preview.example.com".to_string(),
+      ],
+      methods: vec!["GET".to_string(), "POST".to_string()],
+      headers: vec!["authorization".to_string(), "content-type".to_string()],
+      credentials,
+      max_age: 600,
+    }
+  }
+
+  #[test]
+  fn test_matches() {
+    let pattern = b"https://*.example.com";
+
+    assert!(matches(pattern, b"https://app.example.com"));
+    assert!(matches(pattern, b"https://a.b.example.com"));
+    assert!(!matches(pattern, b"https://example.com"));
+    assert!(!matches(pattern, b"https://.example.com.evil.com"));
+    assert!(!matches(pattern, b"https://evil.com/.example.com"));
+    assert!(!matches(pattern, b"https://app.example.com:8080"));
+    assert!(!matches(pattern, b"http://app.example.com"));
+    assert!(matches(b"*", b"https://anywhere.com"));
+    assert!(matches(b"http://localhost:*", b"http://localhost:3000"));
+  }
+
+  #[test]
+  fn test_allows_origin() {
+    let cors = cors(false);
+
+    assert!(cors.allows_origin("https://app.example.com"));
+    assert!(cors.allows_origin("HTTPS://PR-12.preview.example.com"));
+    assert!(!cors.allows_origin("https://evil.com"));
+  }
+
+  #[test]
+  fn test_preflight() {
+    let response = cors(true).preflight(
+      "https://app.example.com",
+      "POST",
+      Some("Content-Type, Authorization"),
+    );
+    let headers = response.headers();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+      headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+      "https://app.example.com"
+    );
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+    assert_eq!(
+      headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+      "authorization, content-type"
+    );
+    assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+  }
+
+  #[test]
+  fn test_preflight_forbidden() {
+    let cors = cors(false);
+
+    for (origin, method, headers) in &[
+      ("https://evil.com", "POST", None),
+      ("https://app.example.com", "DELETE", None),
+      ("https://app.example.com", "POST", Some("x-secret")),
+    ] {
+      let response = cors.preflight(origin, method, *headers);
+
+      assert_eq!(response.status(), StatusCode::FORBIDDEN);
+      assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+  }
+
+  #[test]
+  fn test_actual() {
+    let cors = cors(false);
+    let allowed = cors.actual(Some("https://app.example.com"), Response::new(Vec::new()));
+    let denied = cors.actual(Some("https://evil.com"), Response::new(Vec::new()));
+
+    assert_eq!(
+      allowed.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+      "https://app.example.com"
+    );
+    assert!(!allowed
+      .headers()
+      .contains_key(header::ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    assert!(!denied
+      .headers()
+      .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    assert_eq!(denied.headers()[header::VARY], "Origin");
+  }
+}
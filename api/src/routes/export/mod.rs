@@ -0,0 +1,55 @@
+use crate::exporter::Exporter;
+use futures::{future::poll_fn, Future};
+use std::sync::Arc;
+use tokio_threadpool::blocking;
+use uuid::Uuid;
+use warp::http::{header, Response, StatusCode};
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+/// Signed download link, as issued by
+/// `requestDataExport`.
+#[derive(Deserialize)]
+struct Link {
+  expires: i64,
+  signature: String,
+}
+
+/// Serves data exports at `/exports/{id}` to anyone
+/// with an unexpired signed link. Invalid links get
+/// `404 Not Found`, so they don't reveal which
+/// exports exist.
+pub fn export(exporter: Arc<Exporter>) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  warp::get2()
+    .and(warp::path("exports"))
+    .and(warp::path::param::<Uuid>())
+    .and(warp::path::end())
+    .and(warp::query::<Link>())
+    .and_then(move |id: Uuid, link: Link| {
+      let exporter = exporter.clone();
+
+      poll_fn(move || blocking(|| exporter.read(&id, link.expires, &link.signature))).then(
+        move |result| {
+          Ok::<_, Rejection>(match result {
+            Ok(Ok(archive)) => Response::builder()
+              .header(header::CONTENT_TYPE, "application/json")
+              .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"export-{}.json\"", id),
+              )
+              .header(header::CACHE_CONTROL, "no-store")
+              .body(archive)
+              .expect("response is valid"),
+            Ok(Err(_)) => Response::builder()
+              .status(StatusCode::NOT_FOUND)
+              .body(Vec::new())
+              .expect("response is valid"),
+            Err(_) => Response::builder()
+              .status(StatusCode::INTERNAL_SERVER_ERROR)
+              .body(Vec::new())
+              .expect("response is valid"),
+          })
+        },
+      )
+    })
+    .boxed()
+}
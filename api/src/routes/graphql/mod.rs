@@ -1,48 +1,527 @@
+pub mod operation;
 pub mod schema;
+mod traced;
 
+use self::operation::{is_introspection, Operation, OperationType};
+use self::schema::Schema;
+use crate::config::{Config, Introspection};
 use crate::context::Context;
+use crate::cookie::{self, Cookies, Csrf};
 use crate::db::Db;
+use crate::error::Error;
+use crate::exporter::Exporter;
 use crate::hasher::Hasher;
+use crate::limiter::Limiter;
+use crate::logger;
+use crate::metrics;
+use crate::models::api_token::{self, ApiToken};
+use crate::models::session::Session;
+use crate::models::user::User;
+use crate::oidc::Oidc;
+use crate::routes::access::{
+  client_ip, request_id, traceparent, with_request_id, with_traceresponse,
+};
+use crate::scope::Scope;
 use crate::tokeniser::Tokeniser;
-use std::sync::Arc;
-use warp::{filters::BoxedFilter, Filter};
+use crate::trace::{Span, SpanContext, SpanKind};
+use crate::webauthn::Webauthn;
+use futures::{future, future::poll_fn, Future};
+use juniper::http::GraphQLRequest;
+use juniper::InputValue;
+use log::Level;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio_threadpool::{blocking, BlockingError};
+use warp::http::header::{self, HeaderValue};
+use warp::http::{Response, StatusCode};
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+type ContextFuture = Box<dyn Future<Item = Context, Error = Rejection> + Send>;
+type ResponseFuture = Box<dyn Future<Item = Response<Vec<u8>>, Error = Rejection> + Send>;
+/// Response body of an executed batch, whether it
+/// succeeded and `Set-Cookie` headers to send.
+type Executed = (Vec<u8>, bool, Vec<String>);
+
+/// A single GraphQL operation, as sent in a
+/// request body or query string.
+#[derive(Deserialize)]
+pub struct Request {
+  pub query: String,
+  #[serde(rename = "operationName")]
+  pub operation_name: Option<String>,
+  pub variables: Option<InputValue>,
+}
+
+/// Request body of either a single operation or
+/// a batch of operations executed in one round-trip.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum BatchRequest {
+  Single(Request),
+  Batch(Vec<Request>),
+}
+
+impl Request {
+  /// Reads a request from `GET` query parameters,
+  /// where `variables` is given as a JSON string.
+  pub fn from_params(mut params: HashMap<String, String>) -> Result<Request, Error> {
+    let query = params.remove("query").ok_or(Error::Str(
+      "Missing GraphQL query string in query parameters",
+    ))?;
+    let variables = match params.remove("variables") {
+      Some(variables) => Some(serde_json::from_str(&variables)?),
+      None => None,
+    };
+
+    Ok(Request {
+      query,
+      operation_name: params.remove("operationName"),
+      variables,
+    })
+  }
+
+  pub fn operation(&self) -> Option<Operation<'_>> {
+    Operation::find(&self.query, self.operation_name.as_deref())
+  }
+
+  /// Executes the request, returning the JSON result and
+  /// whether it passed parsing and validation.
+  fn execute(
+    &self,
+    schema: &Schema,
+    context: &Context,
+    introspection: Introspection,
+  ) -> Result<(serde_json::Value, bool), Error> {
+    let (name, kind) = match self.operation() {
+      Some(operation) => (
+        operation.name.unwrap_or("anonymous"),
+        operation.kind.as_str(),
+      ),
+      None => ("unknown", "unknown"),
+    };
+    // Names are chosen by clients, so would make
+    // unbounded series.
+    metrics::GRAPHQL_OPERATIONS.with_label_values(&[kind]).inc();
+
+    let mut span = Span::new(
+      &format!("{} {}", kind, name),
+      SpanKind::Internal,
+      Some(&context.trace),
+    )
+    .enter();
+    span.set_attribute("graphql.operation.name", name);
+    span.set_attribute("graphql.operation.type", kind);
+
+    if !introspection.allows(context.user.is_some()) && is_introspection(&self.query) {
+      span.set_error("Introspection is disabled");
+
+      return Ok((
+        json!({ "errors": [{ "message": "Introspection is disabled" }] }),
+        false,
+      ));
+    }
+
+    let is_query = self
+      .operation()
+      .map(|operation| operation.kind == OperationType::Query)
+      .unwrap_or(false);
+
+    if context.csrf == Csrf::Invalid && !is_query {
+      span.set_error("Invalid CSRF token");
+
+      return Ok((
+        json!({ "errors": [{
+          "message": "Invalid CSRF token",
+          "extensions": { "code": "INVALID_CSRF_TOKEN" },
+        }] }),
+        false,
+      ));
+    }
+
+    let request = GraphQLRequest::new(
+      self.query.clone(),
+      self.operation_name.clone(),
+      self.variables.clone(),
+    );
+    let response = request.execute(schema, context);
+    let is_ok = response.is_ok();
+    let response = serde_json::to_value(&response)?;
+
+    if let Some(errors) = response["errors"].as_array() {
+      span.set_error(&format!("{} GraphQL error(s)", errors.len()));
+
+      for error in errors {
+        logger::event(
+          Level::Warn,
+          "GraphQL error",
+          json!({
+            "request_id": context.request_id,
+            "operation": name,
+            "user": context.user,
+            "impersonator": context.actor,
+            "error": error["message"],
+            "code": error["extensions"]["code"],
+            "path": error["path"],
+          }),
+        );
+      }
+    }
+
+    Ok((response, is_ok))
+  }
+}
 
 pub fn context(
   db: Arc<Db>,
   hasher: Arc<Hasher>,
+  limiter: Arc<Limiter>,
   tokeniser: Arc<Tokeniser>,
+  webauthn: Arc<Webauthn>,
+  oidc: Arc<Oidc>,
+  config: &Config,
 ) -> BoxedFilter<(Context,)> {
+  let cookies = Arc::new(Cookies::new(config));
+  let deletion_grace_period = config.deletion_grace_period;
+  let exporter = Arc::new(Exporter::new(config));
+  let mfa_issuer = config.mfa_issuer.clone();
   let db = warp::any().map(move || db.clone());
   let hasher = warp::any().map(move || hasher.clone());
+  let limiter = warp::any().map(move || limiter.clone());
+  let mfa_issuer = warp::any().map(move || mfa_issuer.clone());
+  let oidc = warp::any().map(move || oidc.clone());
   let tokeniser = warp::any().map(move || tokeniser.clone());
+  let webauthn = warp::any().map(move || webauthn.clone());
 
   warp::any()
     .and(db)
     .and(hasher)
+    .and(limiter)
+    .and(mfa_issuer)
+    .and(oidc)
     .and(tokeniser)
+    .and(webauthn)
+    .and(client_ip(config.trust_forwarded_for))
+    .and(request_id())
+    .and(traceparent())
+    .and(warp::header::optional::<String>("user-agent"))
     .and(warp::header::optional::<String>("authorization"))
+    .and(warp::header::optional::<String>("cookie"))
+    .and(warp::header::optional::<String>(cookie::CSRF_HEADER))
     .and_then(
-      |db: Arc<Db>, hasher: Arc<Hasher>, tokeniser: Arc<Tokeniser>, auth_header: Option<String>| {
-        let mut user = None;
-
-        if let Some(token) = auth_header {
-          match (tokeniser.verify)(&token) {
-            Ok(claims) => user = Some(claims.sub),
-            _ => return Err(warp::reject::not_found()), // TODO Return UNAUTHORIZED
+      move |db: Arc<Db>,
+            hasher: Arc<Hasher>,
+            limiter: Arc<Limiter>,
+            mfa_issuer: String,
+            oidc: Arc<Oidc>,
+            tokeniser: Arc<Tokeniser>,
+            webauthn: Arc<Webauthn>,
+            ip: Option<IpAddr>,
+            request_id: String,
+            parent: Option<SpanContext>,
+            user_agent: Option<String>,
+            auth_header: Option<String>,
+            cookie_header: Option<String>,
+            csrf_header: Option<String>| {
+        // Browser clients' tokens are in cookies, which
+        // are sent with forged requests too, so they're
+        // only used with a matching CSRF token.
+        let (token, csrf) = match (auth_header, cookie_header) {
+          (None, Some(ref cookie_header)) if cookies.enabled => {
+            match cookie::get(cookie_header, cookie::SESSION_COOKIE) {
+              Some(token) => (
+                Some(token.to_string()),
+                Csrf::check(
+                  cookie::get(cookie_header, cookie::CSRF_COOKIE),
+                  csrf_header.as_deref(),
+                ),
+              ),
+              None => (None, Csrf::NotRequired),
+            }
           }
-        }
-
-        Ok(Context {
+          (auth_header, _) => (auth_header, Csrf::NotRequired),
+        };
+        let context = Context {
+          actor: None,
+          cookies: cookies.clone(),
+          csrf,
           db,
+          deletion_grace_period,
+          exporter: exporter.clone(),
           hasher,
+          ip,
+          limiter,
+          mfa_issuer,
+          oidc,
+          request_id,
+          scopes: Vec::new(),
+          session: None,
+          set_cookies: Mutex::new(Vec::new()),
           tokeniser,
-          user,
-        })
+          trace: parent
+            .as_ref()
+            .map(SpanContext::child)
+            .unwrap_or_else(SpanContext::root),
+          user: None,
+          user_agent,
+          webauthn,
+        };
+
+        authenticate(context, token)
       },
     )
     .boxed()
 }
 
-pub fn graphql(context: BoxedFilter<(Context,)>) -> BoxedFilter<(warp::http::Response<Vec<u8>>,)> {
-  juniper_warp::make_graphql_filter(schema::new(), context)
+/// Sets the user from `token`, either a JWT or an
+/// API token, along with the admin impersonating
+/// them, refusing users (or admins) who can't log
+/// in and revoked sessions. API tokens, sessions and
+/// statuses are looked up on the blocking thread
+/// pool.
+fn authenticate(mut context: Context, token: Option<String>) -> ContextFuture {
+  let token = match token {
+    Some(token) => token,
+    None => return Box::new(future::ok(context)),
+  };
+
+  let verified = if token.starts_with(api_token::PREFIX) {
+    None
+  } else {
+    match (context.tokeniser.verify)(&token) {
+      Ok(claims) => Some((
+        claims.sub,
+        Scope::split(&claims.scope),
+        claims.jti,
+        claims.act.map(|act| act.sub),
+      )),
+      Err(err) => return Box::new(future::result(refuse(context, &err))),
+    }
+  };
+
+  let db = context.db.clone();
+
+  Box::new(
+    poll_fn(move || {
+      blocking(|| {
+        let connection = db.connect()?;
+        let (user_id, scopes, session, actor) = match verified {
+          Some((user_id, ref scopes, session, actor)) => {
+            Session::touch(&connection, &session)?;
+            (user_id, scopes.clone(), Some(session), actor)
+          }
+          None => {
+            let (user_id, scopes) = ApiToken::authenticate(&connection, &token)?;
+            (user_id, scopes, None, None)
+          }
+        };
+        User::check_status(&connection, &user_id)?;
+
+        if let Some(ref actor) = actor {
+          User::check_status(&connection, actor)?;
+        }
+
+        Ok((user_id, scopes, session, actor))
+      })
+    })
+    .then(
+      move |result: Result<Result<_, Error>, BlockingError>| match result {
+        Ok(Ok((user_id, scopes, session, actor))) => {
+          context.actor = actor;
+          context.user = Some(user_id);
+          context.scopes = scopes;
+          context.session = session;
+          Ok(context)
+        }
+        Ok(Err(err)) => refuse(context, &err),
+        Err(_) => Err(invalid_token(
+          &context,
+          &Error::Str("Blocking thread pool unavailable"),
+        )),
+      },
+    ),
+  )
+}
+
+/// Refuses a request with an invalid token, unless
+/// it's from a session cookie, which is cleared so
+/// the client carries on logged out (i.e. to log
+/// in again).
+fn refuse(mut context: Context, err: &Error) -> Result<Context, Rejection> {
+  let rejection = invalid_token(&context, err);
+
+  if context.csrf == Csrf::NotRequired {
+    return Err(rejection);
+  }
+
+  context.csrf = Csrf::NotRequired;
+  context.logged_out();
+
+  Ok(context)
+}
+
+fn invalid_token(context: &Context, err: &Error) -> Rejection {
+  logger::event(
+    Level::Warn,
+    "Invalid token",
+    json!({ "request_id": context.request_id, "error": err.to_string() }),
+  );
+
+  warp::reject::not_found() // TODO Return UNAUTHORIZED
+}
+
+/// GraphQL endpoint. Accepts `GET` requests with
+/// `query`, `variables` and `operationName` query
+/// parameters for cacheable queries, and `POST`
+/// requests with a single or batched JSON body.
+/// Mutations are refused over `GET`, and
+/// introspection is refused unless `introspection`
+/// allows it for the caller.
+pub fn graphql(
+  context: BoxedFilter<(Context,)>,
+  introspection: Introspection,
+) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  let schema = Arc::new(schema::new());
+  let get_schema = schema.clone();
+
+  let get = warp::get2()
+    .and(context.clone())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(
+      move |context: Context, params: HashMap<String, String>| -> ResponseFuture {
+        let request = match Request::from_params(params) {
+          Ok(request) => request,
+          Err(err) => {
+            return Box::new(future::ok(with_traceresponse(
+              error(StatusCode::BAD_REQUEST, &err),
+              &context.trace,
+            )))
+          }
+        };
+
+        match request.operation() {
+          Some(ref operation) if operation.kind != OperationType::Query => {
+            let mut response = error(
+              StatusCode::METHOD_NOT_ALLOWED,
+              &Error::Str("Only queries can be sent with GET, use POST instead"),
+            );
+            response
+              .headers_mut()
+              .insert(header::ALLOW, HeaderValue::from_static("POST"));
+
+            return Box::new(future::ok(with_traceresponse(response, &context.trace)));
+          }
+          _ => (),
+        }
+
+        execute(
+          get_schema.clone(),
+          context,
+          introspection,
+          BatchRequest::Single(request),
+        )
+      },
+    );
+
+  let post = warp::post2().and(context).and(warp::body::json()).and_then(
+    move |context: Context, batch: BatchRequest| {
+      execute(schema.clone(), context, introspection, batch)
+    },
+  );
+
+  get.or(post).unify().boxed()
+}
+
+/// Executes `batch` on the blocking thread pool,
+/// responding with a JSON array for batched requests.
+fn execute(
+  schema: Arc<Schema>,
+  context: Context,
+  introspection: Introspection,
+  batch: BatchRequest,
+) -> ResponseFuture {
+  let request_id = context.request_id.clone();
+  let trace = context.trace;
+
+  Box::new(
+    poll_fn(move || {
+      blocking(|| {
+        let (body, is_ok) = match batch {
+          BatchRequest::Single(ref request) => request.execute(&schema, &context, introspection)?,
+          BatchRequest::Batch(ref requests) => {
+            let responses = requests
+              .iter()
+              .map(|request| request.execute(&schema, &context, introspection))
+              .collect::<Result<Vec<_>, Error>>()?;
+            let is_ok = responses.iter().all(|&(_, is_ok)| is_ok);
+            let values = responses.into_iter().map(|(value, _)| value).collect();
+
+            (serde_json::Value::Array(values), is_ok)
+          }
+        };
+
+        let cookies = context
+          .set_cookies
+          .lock()
+          .unwrap_or_else(PoisonError::into_inner)
+          .clone();
+
+        Ok((serde_json::to_vec(&body)?, is_ok, cookies))
+      })
+    })
+    .then(
+      move |result: Result<Result<Executed, Error>, BlockingError>| {
+        let response = match result {
+          Ok(Ok((body, is_ok, cookies))) => {
+            let mut response = Response::builder();
+            response
+              .status(if is_ok {
+                StatusCode::OK
+              } else {
+                StatusCode::BAD_REQUEST
+              })
+              .header(header::CONTENT_TYPE, "application/json");
+
+            for cookie in cookies {
+              response.header(header::SET_COOKIE, cookie);
+            }
+
+            response.body(body).expect("response is valid")
+          }
+          result => {
+            let error = match result {
+              Ok(Err(err)) => err.to_string(),
+              _ => "Blocking thread pool unavailable".to_string(),
+            };
+            logger::event(
+              Level::Error,
+              "GraphQL execution failed",
+              json!({ "request_id": request_id, "error": error }),
+            );
+
+            Response::builder()
+              .status(StatusCode::INTERNAL_SERVER_ERROR)
+              .body(Vec::new())
+              .expect("response is valid")
+          }
+        };
+
+        Ok(with_traceresponse(
+          with_request_id(response, &request_id),
+          &trace,
+        ))
+      },
+    ),
+  )
+}
+
+fn error(status: StatusCode, err: &Error) -> Response<Vec<u8>> {
+  Response::builder()
+    .status(status)
+    .header(header::CONTENT_TYPE, "application/json")
+    .body(
+      serde_json::to_vec(&json!({ "errors": [{ "message": err.to_string() }] }))
+        .expect("error is serialisable"),
+    )
+    .expect("response is valid")
 }
@@ -0,0 +1,186 @@
+use juniper::parser::{Lexer, Token};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperationType {
+  Query,
+  Mutation,
+  Subscription,
+}
+
+impl OperationType {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      OperationType::Query => "query",
+      OperationType::Mutation => "mutation",
+      OperationType::Subscription => "subscription",
+    }
+  }
+}
+
+/// An operation defined within a GraphQL document.
+#[derive(Debug, PartialEq)]
+pub struct Operation<'a> {
+  pub kind: OperationType,
+  pub name: Option<&'a str>,
+}
+
+enum State {
+  Definition,
+  Name,
+  Header,
+}
+
+impl<'a> Operation<'a> {
+  /// Finds the operation in `document` that would be
+  /// executed for `operation_name`. Returns `None`
+  /// if the document can't be lexed or the operation
+  /// is ambiguous, leaving Juniper to report the error.
+  pub fn find(document: &'a str, operation_name: Option<&str>) -> Option<Operation<'a>> {
+    let mut operations = Operation::all(document)?;
+
+    match operation_name {
+      Some(name) => operations
+        .into_iter()
+        .find(|operation| operation.name == Some(name)),
+      None if operations.len() == 1 => operations.pop(),
+      None => None,
+    }
+  }
+
+  /// Lists all operations defined in `document`,
+  /// skipping over fragment definitions.
+  pub fn all(document: &'a str) -> Option<Vec<Operation<'a>>> {
+    let mut operations = Vec::new();
+    let mut pending: Option<Operation> = None;
+    let mut state = State::Definition;
+    let mut depth = 0;
+    let mut parens = 0;
+
+    for token in Lexer::new(document) {
+      let token = token.ok()?.item;
+
+      if depth > 0 {
+        match token {
+          Token::CurlyOpen => depth += 1,
+          Token::CurlyClose => depth -= 1,
+          _ => (),
+        }
+
+        if depth == 0 {
+          state = State::Definition;
+        }
+
+        continue;
+      }
+
+      match (&state, token) {
+        (State::Definition, Token::Name(keyword)) => {
+          pending = match keyword {
+            "query" => Some(OperationType::Query),
+            "mutation" => Some(OperationType::Mutation),
+            "subscription" => Some(OperationType::Subscription),
+            _ => None,
+          }
+          .map(|kind| Operation { kind, name: None });
+          state = if pending.is_some() {
+            State::Name
+          } else {
+            State::Header
+          };
+        }
+        (State::Definition, Token::CurlyOpen) => {
+          operations.push(Operation {
+            kind: OperationType::Query,
+            name: None,
+          });
+          depth += 1;
+        }
+        (State::Name, Token::Name(name)) => {
+          if let Some(ref mut operation) = pending {
+            operation.name = Some(name);
+          }
+          state = State::Header;
+        }
+        (_, Token::ParenOpen) => {
+          parens += 1;
+          state = State::Header;
+        }
+        (_, Token::ParenClose) => parens -= 1,
+        (_, Token::CurlyOpen) if parens == 0 => {
+          operations.extend(pending.take());
+          depth += 1;
+        }
+        (_, Token::EndOfFile) => break,
+        _ => state = State::Header,
+      }
+    }
+
+    Some(operations)
+  }
+}
+
+/// Whether `document` selects the `__schema` or
+/// `__type` introspection fields.
+pub fn is_introspection(document: &str) -> bool {
+  Lexer::new(document).any(|token| match token {
+    Ok(token) => token.item == Token::Name("__schema") || token.item == Token::Name("__type"),
+    Err(_) => false,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_find_anonymous_query() {
+    let operation = Operation::find("{ User(userId: 1) { id } }", None).unwrap();
+
+    assert_eq!(operation.kind, OperationType::Query);
+    assert_eq!(operation.name, None);
+  }
+
+  #[test]
+  fn test_find_mutation() {
+    let document = "mutation ($user: UserLogin! = { email: \"a\" }) { login(user: $user) }";
+    let operation = Operation::find(document, None).unwrap();
+
+    assert_eq!(operation.kind, OperationType::Mutation);
+  }
+
+  #[test]
+  fn test_find_by_operation_name() {
+    let document = r#"
+      query Read { User(userId: 1) { ...Fields } }
+      fragment Fields on User { id name }
+      mutation Delete { deleteUser(userId: 1) }
+    "#;
+
+    assert_eq!(
+      Operation::find(document, Some("Delete")),
+      Some(Operation {
+        kind: OperationType::Mutation,
+        name: Some("Delete")
+      })
+    );
+    assert_eq!(
+      Operation::find(document, Some("Read")).unwrap().kind,
+      OperationType::Query
+    );
+    assert_eq!(Operation::find(document, None), None);
+  }
+
+  #[test]
+  fn test_is_introspection() {
+    assert!(is_introspection("{ __schema { types { name } } }"));
+    assert!(is_introspection(
+      "query { user: __type(name: \"User\") { name } }"
+    ));
+    assert!(!is_introspection("{ User(userId: 1) { __typename id } }"));
+  }
+
+  #[test]
+  fn test_find_invalid_document() {
+    assert_eq!(Operation::find("query { \"unterminated }", None), None);
+  }
+}
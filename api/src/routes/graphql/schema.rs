@@ -1,21 +1,81 @@
+use super::traced::Traced;
 use crate::context::Context;
 use crate::error::Error;
+use crate::limiter::Action;
 use crate::models::user::{User, UserCreate, UserUpdate, UserLogin};
+use crate::models::api_token::{ApiToken, ApiTokenCreate, ApiTokenCreated};
+use crate::models::audit_event::{self, AuditEvent, AuditEventFilter};
+use crate::models::data_export::DataExport;
+use crate::models::identity::Identity;
+use crate::models::mfa::Mfa;
+use crate::models::passkey::{Passkey, PasskeyAssertion, PasskeyRegistration};
+use crate::models::session::Session;
 use crate::models::group::{Group, GroupCreate, GroupUpdate};
-use juniper::{FieldResult, RootNode};
+use crate::logger;
+use crate::metrics;
+use crate::scope::Scope;
+use chrono::{DateTime, Utc};
+use log::Level;
+use juniper::RootNode;
 use uuid::Uuid;
 
 pub struct Query;
 
 graphql_object!(Query: Context |&self| {
-  field User(&executor, user_id: Uuid) -> FieldResult<User> {
-    let admin_id = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to view users"))?;
+  field User(&executor, user_id: Uuid) -> Result<User, Error> {
+    let admin_id = &executor.context().user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to view users"))?;
+    executor.context().require(&[Scope::UsersRead])?;
 
     Ok(User::read(&executor.context().db.connect()?, &admin_id, &user_id)?)
   }
 
-  field Group(&executor, group_id: Uuid) -> FieldResult<Group> {
-    let user_id = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to view groups"))?;
+  field Users(&executor, search: Option<String>, limit: Option<i32>, offset: Option<i32>) -> Result<Vec<User>, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to list users"))?;
+    context.require(&[Scope::UsersRead])?;
+
+    User::list(
+      &context.db.connect()?,
+      &admin_id,
+      search.as_deref(),
+      i64::from(limit.unwrap_or(50)),
+      i64::from(offset.unwrap_or(0))
+    )
+  }
+
+  field AuditEvents(&executor, filter: Option<AuditEventFilter>, limit: Option<i32>, offset: Option<i32>) -> Result<Vec<AuditEvent>, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to view the audit log"))?;
+    context.require(&[Scope::UsersRead])?;
+
+    AuditEvent::list(
+      &context.db.connect()?,
+      &admin_id,
+      &filter.unwrap_or_default(),
+      i64::from(limit.unwrap_or(50)),
+      i64::from(offset.unwrap_or(0))
+    )
+  }
+
+  field mySessions(&executor) -> Result<Vec<Session>, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to list sessions"))?;
+    context.require(&[Scope::CredentialsRead])?;
+
+    Session::list(&context.db.connect()?, &user_id, context.session.as_ref())
+  }
+
+  field listApiTokens(&executor) -> Result<Vec<ApiToken>, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to list API tokens"))?;
+    context.require(&[Scope::CredentialsRead])?;
+
+    ApiToken::list(&context.db.connect()?, &user_id)
+  }
+
+  field Group(&executor, group_id: Uuid) -> Result<Group, Error> {
+    let user_id = &executor.context().user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to view groups"))?;
+    executor.context().require(&[Scope::GroupsRead])?;
 
     Ok(Group::read(&executor.context().db.connect()?, &user_id, &group_id)?)
   }
@@ -24,70 +84,437 @@ graphql_object!(Query: Context |&self| {
 pub struct Mutation;
 
 graphql_object!(Mutation: Context |&self| {
-  field createUser(&executor, user: UserCreate) -> FieldResult<String> {
-    Ok(User::create(
-      &executor.context().db.connect()?,
-      &executor.context().hasher.generate,
-      &executor.context().tokeniser.generate,
+  field createUser(&executor, user: UserCreate) -> Result<String, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    context.limiter.attempt(&connection, Action::CreateUser, context.ip, None)?;
+
+    let token = User::create(
+      &connection,
+      &context.hasher.generate,
+      &context.tokeniser.generate,
       &user
-    )?)
+    )?;
+    context.audit_change(
+      &connection,
+      "create_user",
+      Some(user.id),
+      audit_event::diff(&json!({}), &json!({ "email": user.email, "name": user.name }))
+    )?;
+
+    context.logged_in(&connection, token)
   }
 
-  field updateUser(&executor, user: UserUpdate) -> FieldResult<bool> {
-    let admin = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to update user"))?;
-    
-    Ok(User::update(
-      &executor.context().db.connect()?,
-      &executor.context().hasher.generate,
-      &admin,
-      &user
-    )?)
+  field updateUser(&executor, user: UserUpdate) -> Result<bool, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to update user"))?;
+    context.require(&[Scope::UsersWrite])?;
+
+    // Emails and passwords log in too, so changing them needs
+    // the scope impersonation tokens lack.
+    if user.email.is_some() || user.password.is_some() {
+      context.require(&[Scope::CredentialsWrite])?;
+    }
+
+    let connection = context.db.connect()?;
+    let before = User::snapshot(&connection, &user.id)?;
+    let updated = User::update(&connection, &context.hasher.generate, &admin_id, &user)?;
+    context.audit_change(&connection, "update_user", Some(user.id), audit_event::diff(&before, &user.changes()))?;
+
+    Ok(updated)
   }
 
-  field deleteUser(&executor, user_id: Uuid) -> FieldResult<bool> {
-    let admin = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to delete user"))?;
+  field deleteUser(&executor, user_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to delete user"))?;
+    context.require(&[Scope::UsersWrite])?;
 
-    Ok(User::delete(&executor.context().db.connect()?, &admin, &user_id)?)
+    let connection = context.db.connect()?;
+    let deleted = User::delete(&connection, &admin_id, &user_id)?;
+    context.audit(&connection, "delete_user", Some(user_id), json!({}))?;
+
+    Ok(deleted)
   }
   
-  field login(&executor, user: UserLogin) -> FieldResult<String> {
-    Ok(User::login(
-      &executor.context().db.connect()?,
-      &executor.context().hasher.verify,
-      &executor.context().tokeniser.generate,
+  field requestDataExport(&executor) -> Result<DataExport, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to export data"))?;
+    context.require(&[Scope::UsersRead, Scope::GroupsRead, Scope::CredentialsRead])?;
+
+    let connection = context.db.connect()?;
+    let export = context.exporter.write(&DataExport::archive(&connection, &user_id)?)?;
+    context.audit(&connection, "request_data_export", Some(user_id), json!({}))?;
+
+    Ok(export)
+  }
+
+  field restoreUser(&executor, user_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to restore users"))?;
+    context.require(&[Scope::UsersWrite])?;
+
+    let connection = context.db.connect()?;
+    let restored = User::restore(&connection, &admin_id, &user_id, context.deletion_grace_period)?;
+    context.audit(&connection, "restore_user", Some(user_id), json!({}))?;
+
+    Ok(restored)
+  }
+
+  field suspendUser(&executor, user_id: Uuid, reason: Option<String>, expires_at: Option<DateTime<Utc>>) -> Result<bool, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to suspend users"))?;
+    context.require(&[Scope::UsersWrite])?;
+
+    let connection = context.db.connect()?;
+    let suspended = User::suspend(&connection, &admin_id, &user_id, reason.as_deref(), expires_at)?;
+    context.audit(&connection, "suspend_user", Some(user_id), json!({ "reason": reason, "expires_at": expires_at }))?;
+
+    Ok(suspended)
+  }
+
+  field reinstateUser(&executor, user_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to reinstate users"))?;
+    context.require(&[Scope::UsersWrite])?;
+
+    let connection = context.db.connect()?;
+    let reinstated = User::reinstate(&connection, &admin_id, &user_id)?;
+    context.audit(&connection, "reinstate_user", Some(user_id), json!({}))?;
+
+    Ok(reinstated)
+  }
+
+  field impersonateUser(&executor, user_id: Uuid) -> Result<String, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to impersonate users"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    let connection = context.db.connect()?;
+    let token = User::impersonate(&connection, &context.tokeniser.impersonate, &admin_id, &user_id)?;
+    context.audit(&connection, "impersonate_user", Some(user_id), json!({}))?;
+
+    Ok(token)
+  }
+
+  field assignRole(&executor, user_id: Uuid, role: String) -> Result<bool, Error> {
+    let context = executor.context();
+    let admin_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to assign roles"))?;
+    context.require(&[Scope::UsersWrite])?;
+
+    let connection = context.db.connect()?;
+    let before = User::snapshot(&connection, &user_id)?;
+    let assigned = User::assign_role(&connection, &admin_id, &user_id, &role)?;
+    context.audit_change(&connection, "assign_role", Some(user_id), audit_event::diff(&before, &json!({ "role": role })))?;
+
+    Ok(assigned)
+  }
+
+  field login(&executor, user: UserLogin) -> Result<String, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    context.limiter.attempt(&connection, Action::Login, context.ip, Some(&user.email))?;
+    context.limiter.unlocked(&connection, &user.email)?;
+
+    let token = User::login(
+      &connection,
+      &context.hasher.verify,
+      context.hasher.dummy(),
+      &context.tokeniser.generate,
+      &context.tokeniser.generate_challenge,
       &user
-    )?)
+    );
+    let result = match token {
+      Ok(_) => {
+        context.limiter.succeeded(&connection, &user.email)?;
+        "success"
+      }
+      Err(Error::InvalidCredentials) => {
+        context.limiter.failed(&connection, &user.email)?;
+        "failure"
+      }
+      Err(Error::MfaRequired(_)) => "mfa_required",
+      Err(_) => "failure",
+    };
+    metrics::LOGINS.with_label_values(&[result]).inc();
+    logger::event(Level::Info, "Login", json!({
+      "request_id": context.request_id,
+      "result": result,
+    }));
+    context.audit_login(
+      &connection,
+      login_user(context, &token),
+      json!({ "method": "password", "email": user.email, "result": result })
+    )?;
+
+    token.and_then(|token| context.logged_in(&connection, token))
   }
 
-  field createGroup(&executor, group: GroupCreate) -> FieldResult<bool> {
-    let user_id = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to create groups"))?;
-    
-    Ok(Group::create(
-      &executor.context().db.connect()?,
-      &user_id,
-      &group
-    )?)
+  field verifyMfa(&executor, token: String, code: String) -> Result<String, Error> {
+    let context = executor.context();
+    let user_id = (context.tokeniser.verify_challenge)(&token)?.sub;
+    let connection = context.db.connect()?;
+    let email = User::email(&connection, &user_id)?;
+    context.limiter.attempt(&connection, Action::VerifyMfa, context.ip, Some(&email))?;
+    context.limiter.unlocked(&connection, &email)?;
+
+    let verified = Mfa::verify(&connection, &context.hasher.verify, &user_id, &code);
+    let result = match verified {
+      Ok(_) => {
+        context.limiter.succeeded(&connection, &email)?;
+        "success"
+      }
+      Err(Error::InvalidMfaCode) => {
+        context.limiter.failed(&connection, &email)?;
+        "failure"
+      }
+      Err(_) => "failure",
+    };
+    metrics::LOGINS.with_label_values(&[result]).inc();
+    logger::event(Level::Info, "MFA verified", json!({
+      "request_id": context.request_id,
+      "result": result,
+    }));
+    context.audit_login(&connection, Some(user_id), json!({ "method": "mfa", "result": result }))?;
+    verified?;
+
+    context.logged_in(&connection, (context.tokeniser.generate)(user_id)?)
   }
 
-  field updateGroup(&executor, group: GroupUpdate) -> FieldResult<bool> {
-    let admin = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to update group"))?;
-    
-    Ok(Group::update(
-      &executor.context().db.connect()?,
-      &admin,
-      &group
-    )?)
+  field enrolMfa(&executor) -> Result<String, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to enrol MFA"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    Mfa::enrol(&context.db.connect()?, &context.mfa_issuer, &user_id)
+  }
+
+  field confirmMfa(&executor, code: String) -> Result<Vec<String>, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to confirm MFA"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    Mfa::confirm(&context.db.connect()?, &context.hasher.generate, &user_id, &code)
+  }
+
+  field disableMfa(&executor, code: String) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to disable MFA"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    Mfa::disable(&context.db.connect()?, &context.hasher.verify, &user_id, &code)
   }
 
-  field deleteGroup(&executor, group_id: Uuid) -> FieldResult<bool> {
-    let admin = &executor.context().user.ok_or(Error::Str("Unauthorised - Must be logged in to delete user"))?;
+  field beginPasskeyRegistration(&executor) -> Result<String, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to register passkeys"))?;
+    context.require(&[Scope::CredentialsWrite])?;
 
-    Ok(Group::delete(&executor.context().db.connect()?, &admin, &group_id)?)
+    Passkey::begin_registration(&context.db.connect()?, &context.webauthn, &user_id)
+  }
+
+  field finishPasskeyRegistration(&executor, credential: PasskeyRegistration) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to register passkeys"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    Passkey::register(&context.db.connect()?, &context.webauthn, &user_id, &credential)
+  }
+
+  field beginPasskeyLogin(&executor) -> Result<String, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    context.limiter.attempt(&connection, Action::PasskeyLogin, context.ip, None)?;
+
+    Passkey::begin_login(&connection, &context.webauthn)
+  }
+
+  field finishPasskeyLogin(&executor, credential: PasskeyAssertion) -> Result<String, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    let token = Passkey::login(
+      &connection,
+      &context.webauthn,
+      &context.tokeniser.generate,
+      &credential
+    );
+    let result = if token.is_ok() { "success" } else { "failure" };
+    metrics::LOGINS.with_label_values(&[result]).inc();
+    logger::event(Level::Info, "Passkey login", json!({
+      "request_id": context.request_id,
+      "result": result,
+    }));
+    context.audit_login(&connection, login_user(context, &token), json!({ "method": "passkey", "result": result }))?;
+
+    token.and_then(|token| context.logged_in(&connection, token))
+  }
+
+  field beginOidcLogin(&executor, provider: String) -> Result<String, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    context.limiter.attempt(&connection, Action::OidcLogin, context.ip, None)?;
+
+    // Logged in users link the identity to themselves.
+    if context.user.is_some() {
+      context.require(&[Scope::CredentialsWrite])?;
+    }
+
+    Identity::begin(&connection, &context.oidc, &provider, context.user.as_ref())
+  }
+
+  field finishOidcLogin(&executor, state: String, code: String) -> Result<String, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    let token = Identity::login(
+      &connection,
+      &context.oidc,
+      &context.hasher.generate,
+      &context.tokeniser.generate,
+      &context.tokeniser.generate_challenge,
+      context.user.as_ref(),
+      &state,
+      &code
+    );
+    let result = match token {
+      Ok(_) => "success",
+      Err(Error::MfaRequired(_)) => "mfa_required",
+      Err(_) => "failure",
+    };
+    metrics::LOGINS.with_label_values(&[result]).inc();
+    logger::event(Level::Info, "OIDC login", json!({
+      "request_id": context.request_id,
+      "result": result,
+    }));
+    context.audit_login(&connection, login_user(context, &token), json!({ "method": "oidc", "result": result }))?;
+
+    token.and_then(|token| context.logged_in(&connection, token))
+  }
+
+  field createApiToken(&executor, api_token: ApiTokenCreate) -> Result<ApiTokenCreated, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to create API tokens"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+    // Tokens can't be granted more than the one creating them.
+    let scopes = Scope::parse_all(&api_token.scopes)?;
+    context.require(&scopes)?;
+
+    ApiToken::create(&context.db.connect()?, &user_id, &api_token, &scopes)
+  }
+
+  field revokeApiToken(&executor, api_token_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to revoke API tokens"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    let connection = context.db.connect()?;
+    let revoked = ApiToken::revoke(&connection, &user_id, &api_token_id)?;
+    context.audit(&connection, "revoke_api_token", Some(user_id), json!({ "api_token_id": api_token_id }))?;
+
+    Ok(revoked)
+  }
+
+  field revokeSession(&executor, session_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to revoke sessions"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    let connection = context.db.connect()?;
+    let revoked = Session::revoke(&connection, &user_id, &session_id)?;
+    context.audit(&connection, "revoke_session", Some(user_id), json!({ "session_id": session_id }))?;
+
+    Ok(revoked)
+  }
+
+  field revokeOtherSessions(&executor) -> Result<i32, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to revoke sessions"))?;
+    context.require(&[Scope::CredentialsWrite])?;
+
+    let connection = context.db.connect()?;
+    let revoked = Session::revoke_others(&connection, &user_id, context.session.as_ref())?;
+    context.audit(&connection, "revoke_other_sessions", Some(user_id), json!({ "revoked": revoked }))?;
+
+    Ok(revoked as i32)
+  }
+
+  field logout(&executor) -> Result<bool, Error> {
+    let context = executor.context();
+    let connection = context.db.connect()?;
+    let revoked = match (context.user, context.session) {
+      (Some(user_id), Some(session_id)) => {
+        let revoked = Session::revoke(&connection, &user_id, &session_id)?;
+        context.audit(&connection, "logout", Some(user_id), json!({ "session_id": session_id }))?;
+        revoked
+      }
+      _ => false,
+    };
+    context.logged_out();
+
+    Ok(revoked)
+  }
+
+  field createGroup(&executor, group: GroupCreate) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to create groups"))?;
+    context.require(&[Scope::GroupsWrite])?;
+
+    let connection = context.db.connect()?;
+    let created = Group::create(&connection, &user_id, &group)?;
+    context.audit_change(&connection, "create_group", Some(group.id), audit_event::diff(&json!({}), &json!({ "name": group.name })))?;
+
+    Ok(created)
+  }
+
+  field updateGroup(&executor, group: GroupUpdate) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to update group"))?;
+    context.require(&[Scope::GroupsWrite])?;
+
+    let connection = context.db.connect()?;
+    let before = Group::snapshot(&connection, &group.id)?;
+    let updated = Group::update(&connection, &user_id, &group)?;
+    context.audit_change(&connection, "update_group", Some(group.id), audit_event::diff(&before, &group.changes()))?;
+
+    Ok(updated)
+  }
+
+  field deleteGroup(&executor, group_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to delete groups"))?;
+    context.require(&[Scope::GroupsWrite])?;
+
+    let connection = context.db.connect()?;
+    let deleted = Group::delete(&connection, &user_id, &group_id)?;
+    context.audit(&connection, "delete_group", Some(group_id), json!({}))?;
+
+    Ok(deleted)
+  }
+
+  field restoreGroup(&executor, group_id: Uuid) -> Result<bool, Error> {
+    let context = executor.context();
+    let user_id = context.user.ok_or(Error::Unauthorised("Unauthorised - Must be logged in to restore groups"))?;
+    context.require(&[Scope::GroupsWrite])?;
+
+    let connection = context.db.connect()?;
+    let restored = Group::restore(&connection, &user_id, &group_id, context.deletion_grace_period)?;
+    context.audit(&connection, "restore_group", Some(group_id), json!({}))?;
+
+    Ok(restored)
   }
 });
 
-pub type Schema = RootNode<'static, Query, Mutation>;
+/// Who a login attempt's `token` is for, if it
+/// got far enough to know, i.e. they need MFA.
+fn login_user(context: &Context, token: &Result<String, Error>) -> Option<Uuid> {
+  match token {
+    Ok(token) => (context.tokeniser.verify)(token).ok(),
+    Err(Error::MfaRequired(challenge)) => (context.tokeniser.verify_challenge)(challenge).ok(),
+    Err(_) => None,
+  }
+  .map(|claims| claims.sub)
+}
+
+pub type Schema = RootNode<'static, Traced<Query>, Traced<Mutation>>;
 
 pub fn new() -> Schema {
-  Schema::new(Query, Mutation)
+  Schema::new(Traced(Query), Traced(Mutation))
 }
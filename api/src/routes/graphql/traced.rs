@@ -0,0 +1,45 @@
+use crate::trace::{Span, SpanKind};
+use juniper::meta::MetaType;
+use juniper::{Arguments, DefaultScalarValue, ExecutionResult, Executor, GraphQLType, Registry};
+
+/// A root type (i.e. `Query`) that records a span
+/// named after each of its fields as they resolve,
+/// so resolvers don't start their own.
+pub struct Traced<T>(pub T);
+
+impl<T> GraphQLType for Traced<T>
+where
+  T: GraphQLType<TypeInfo = ()>,
+{
+  type Context = T::Context;
+  type TypeInfo = ();
+
+  fn name(info: &()) -> Option<&str> {
+    T::name(info)
+  }
+
+  fn meta<'r>(info: &(), registry: &mut Registry<'r>) -> MetaType<'r>
+  where
+    DefaultScalarValue: 'r,
+  {
+    T::meta(info, registry)
+  }
+
+  fn resolve_field(
+    &self,
+    info: &(),
+    field_name: &str,
+    arguments: &Arguments,
+    executor: &Executor<T::Context>,
+  ) -> ExecutionResult {
+    let name = format!("{}.{}", T::name(info).unwrap_or_default(), field_name);
+    let mut span = Span::child(&name, SpanKind::Internal).enter();
+    let result = self.0.resolve_field(info, field_name, arguments, executor);
+
+    if let Err(ref err) = result {
+      span.set_error(err.message());
+    }
+
+    result
+  }
+}
@@ -0,0 +1,98 @@
+use crate::db::Db;
+use crate::tokeniser::Tokeniser;
+use futures::{future::poll_fn, Future};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_threadpool::blocking;
+use uuid::Uuid;
+use warp::http::{header, Response, StatusCode};
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+/// How long readiness waits for a pooled connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Health probes for orchestrators. `/healthz`
+/// responds while the process is serving requests.
+/// `/readyz` responds `503 Service Unavailable`
+/// unless a database connection can be taken from
+/// the pool, migrations are current and tokens can
+/// be signed, with details of each check.
+pub fn health(db: Arc<Db>, tokeniser: Arc<Tokeniser>) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  let healthz = warp::path("healthz")
+    .and(warp::path::end())
+    .map(|| json(StatusCode::OK, &json!({ "status": "ok" })));
+
+  let readyz = warp::path("readyz")
+    .and(warp::path::end())
+    .and_then(move || {
+      let db = db.clone();
+      let tokeniser = tokeniser.clone();
+
+      poll_fn(move || blocking(|| readiness(&db, &tokeniser))).then(|result| {
+        Ok::<_, Rejection>(match result {
+          Ok((true, checks)) => json(StatusCode::OK, &checks),
+          Ok((false, checks)) => json(StatusCode::SERVICE_UNAVAILABLE, &checks),
+          Err(_) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Vec::new())
+            .expect("response is valid"),
+        })
+      })
+    });
+
+  warp::get2().and(healthz.or(readyz).unify()).boxed()
+}
+
+/// Runs the readiness checks, returning whether
+/// all of them passed along with their details.
+fn readiness(db: &Db, tokeniser: &Tokeniser) -> (bool, Value) {
+  let connection = db.connect_timeout(CONNECT_TIMEOUT);
+  let migrations = match connection {
+    Ok(ref conn) => match db.missing_migrations(conn) {
+      Ok(ref missing) if missing.is_empty() => Ok(()),
+      Ok(missing) => Err(format!("Missing migrations: {}", missing.join(", "))),
+      Err(err) => Err(err.to_string()),
+    },
+    Err(_) => Err("No database connection".to_string()),
+  };
+  let database = connection.map(|_| ()).map_err(|err| err.to_string());
+  let signing_keys = (tokeniser.generate)(Uuid::nil())
+    .and_then(|token| (tokeniser.verify)(&token))
+    .map(|_| ())
+    .map_err(|err| err.to_string());
+
+  let checks = [
+    ("database", database),
+    ("migrations", migrations),
+    ("signing_keys", signing_keys),
+  ];
+  let ready = checks.iter().all(|(_, check)| check.is_ok());
+  let details = checks
+    .iter()
+    .map(|(name, check)| {
+      let detail = match check {
+        Ok(()) => json!({ "status": "ok" }),
+        Err(err) => json!({ "status": "error", "error": err }),
+      };
+
+      (name.to_string(), detail)
+    })
+    .collect::<Map<String, Value>>();
+
+  (
+    ready,
+    json!({
+      "status": if ready { "ok" } else { "error" },
+      "checks": details,
+    }),
+  )
+}
+
+fn json(status: StatusCode, body: &Value) -> Response<Vec<u8>> {
+  Response::builder()
+    .status(status)
+    .header(header::CONTENT_TYPE, "application/json")
+    .body(serde_json::to_vec(body).expect("body is serialisable"))
+    .expect("response is valid")
+}
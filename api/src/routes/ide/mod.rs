@@ -0,0 +1,65 @@
+use crate::config::Ide;
+use warp::http::{header, Response};
+use warp::path::FullPath;
+use warp::{filters::BoxedFilter, Filter};
+
+/// Serves the configured GraphQL `ide` at `path`
+/// for the given GraphQL `endpoint`. Rejects all
+/// requests when the IDE is disabled.
+pub fn ide(ide: Ide, path: &str, endpoint: &'static str) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  let path = path.trim_matches('/').to_string();
+  let at_path = warp::path::full()
+    .and_then(move |full: FullPath| {
+      if full.as_str().trim_matches('/') == path {
+        Ok(())
+      } else {
+        Err(warp::reject::not_found())
+      }
+    })
+    .untuple_one();
+
+  match ide {
+    Ide::GraphiQL => warp::get2()
+      .and(at_path)
+      .and(juniper_warp::graphiql_filter(endpoint))
+      .boxed(),
+    Ide::Playground => warp::get2()
+      .and(at_path)
+      .map(move || {
+        Response::builder()
+          .header(header::CONTENT_TYPE, "text/html;charset=utf-8")
+          .body(playground_source(endpoint).into_bytes())
+          .expect("response is valid")
+      })
+      .boxed(),
+    Ide::Disabled => warp::any()
+      .and_then(|| Err::<Response<Vec<u8>>, _>(warp::reject::not_found()))
+      .boxed(),
+  }
+}
+
+fn playground_source(endpoint: &str) -> String {
+  format!(
+    r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8" />
+  <meta name="viewport" content="user-scalable=no, initial-scale=1.0, minimum-scale=1.0, maximum-scale=1.0, minimal-ui" />
+  <title>GraphQL Playground</title>
+  <link rel="stylesheet" href="//cdn.jsdelivr.net/npm/graphql-playground-react/build/static/css/index.css" />
+  <link rel="shortcut icon" href="//cdn.jsdelivr.net/npm/graphql-playground-react/build/favicon.png" />
+  <script src="//cdn.jsdelivr.net/npm/graphql-playground-react/build/static/js/middleware.js"></script>
+</head>
+<body>
+  <div id="root"></div>
+  <script>
+    window.addEventListener('load', function () {{
+      GraphQLPlayground.init(document.getElementById('root'), {{ endpoint: '{}' }});
+    }});
+  </script>
+</body>
+</html>
+"#,
+    endpoint
+  )
+}
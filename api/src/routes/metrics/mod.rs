@@ -0,0 +1,24 @@
+use crate::db::Db;
+use crate::metrics::export;
+use std::sync::Arc;
+use warp::http::{header, Response, StatusCode};
+use warp::{filters::BoxedFilter, Filter};
+
+/// Serves Prometheus metrics in the text
+/// exposition format at `/metrics`.
+pub fn metrics(db: Arc<Db>) -> BoxedFilter<(Response<Vec<u8>>,)> {
+  warp::get2()
+    .and(warp::path("metrics"))
+    .and(warp::path::end())
+    .map(move || match export(&db) {
+      Ok(body) => Response::builder()
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(body.into_bytes())
+        .expect("response is valid"),
+      Err(_) => Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Vec::new())
+        .expect("response is valid"),
+    })
+    .boxed()
+}
@@ -1 +1,7 @@
+pub mod access;
+pub mod cors;
+pub mod export;
 pub mod graphql;
+pub mod health;
+pub mod ide;
+pub mod metrics;
@@ -0,0 +1,97 @@
+use crate::error::Error;
+
+/// Something a token is allowed to do. Login tokens
+/// are granted every scope, API tokens the ones
+/// they're created with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+  UsersRead,
+  UsersWrite,
+  GroupsRead,
+  GroupsWrite,
+  /// Viewing MFA, passkeys, linked identities
+  /// and API tokens.
+  CredentialsRead,
+  CredentialsWrite,
+}
+
+impl Scope {
+  pub const ALL: [Scope; 6] = [
+    Scope::UsersRead,
+    Scope::UsersWrite,
+    Scope::GroupsRead,
+    Scope::GroupsWrite,
+    Scope::CredentialsRead,
+    Scope::CredentialsWrite,
+  ];
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Scope::UsersRead => "users:read",
+      Scope::UsersWrite => "users:write",
+      Scope::GroupsRead => "groups:read",
+      Scope::GroupsWrite => "groups:write",
+      Scope::CredentialsRead => "credentials:read",
+      Scope::CredentialsWrite => "credentials:write",
+    }
+  }
+
+  pub fn parse(scope: &str) -> Option<Scope> {
+    Scope::ALL
+      .iter()
+      .cloned()
+      .find(|known| known.as_str() == scope)
+  }
+
+  /// Parses requested `scopes`, refusing unknown ones.
+  pub fn parse_all(scopes: &[String]) -> Result<Vec<Scope>, Error> {
+    scopes
+      .iter()
+      .map(|scope| Scope::parse(scope).ok_or(Error::Str("Unknown scope")))
+      .collect()
+  }
+
+  /// Space separated, as in the `scope` claim.
+  pub fn join(scopes: &[Scope]) -> String {
+    scopes
+      .iter()
+      .map(|scope| scope.as_str())
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+
+  /// Reads a `scope` claim, ignoring scopes this
+  /// version doesn't know.
+  pub fn split(scope: &str) -> Vec<Scope> {
+    scope.split_whitespace().filter_map(Scope::parse).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    for scope in Scope::ALL.iter() {
+      assert_eq!(Scope::parse(scope.as_str()), Some(*scope));
+    }
+
+    assert_eq!(Scope::parse("users:delete"), None);
+  }
+
+  #[test]
+  fn test_parse_all_unknown() {
+    let scopes = vec!["users:read".to_string(), "admin".to_string()];
+
+    assert!(Scope::parse_all(&scopes).is_err());
+  }
+
+  #[test]
+  fn test_join_split() {
+    let scopes = [Scope::UsersRead, Scope::GroupsWrite];
+
+    assert_eq!(Scope::join(&scopes), "users:read groups:write");
+    assert_eq!(Scope::split("users:read  unknown groups:write"), scopes);
+  }
+}
@@ -0,0 +1,121 @@
+use crate::error::Error;
+use futures::{Async, Future, Poll, Stream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio_signal::IoStream;
+
+/// Resolves on the first `SIGINT` or `SIGTERM`
+/// (or Ctrl-C on Windows).
+pub fn signal() -> impl Future<Item = (), Error = Error> {
+  tokio_signal::ctrl_c()
+    .join(terminate())
+    .and_then(|(interrupt, terminate)| {
+      interrupt
+        .select(terminate)
+        .into_future()
+        .map_err(|(err, _)| err)
+    })
+    .map(|_| ())
+    .map_err(Error::from)
+}
+
+#[cfg(unix)]
+fn terminate() -> impl Future<Item = IoStream<()>, Error = std::io::Error> {
+  use tokio_signal::unix::{Signal, SIGTERM};
+
+  Signal::new(SIGTERM).map(|signal| Box::new(signal.map(|_| ())) as IoStream<()>)
+}
+
+#[cfg(not(unix))]
+fn terminate() -> impl Future<Item = IoStream<()>, Error = std::io::Error> {
+  use futures::{future, stream};
+
+  future::ok(Box::new(stream::empty()) as IoStream<()>)
+}
+
+/// Stream that ends once `until` resolves or fails.
+pub struct Until<S, F> {
+  stream: S,
+  until: F,
+  done: bool,
+}
+
+/// Ends `stream` once `until` resolves, i.e. to
+/// stop accepting connections on shutdown.
+pub fn until<S: Stream, F: Future>(stream: S, until: F) -> Until<S, F> {
+  Until {
+    stream,
+    until,
+    done: false,
+  }
+}
+
+impl<S: Stream, F: Future> Stream for Until<S, F> {
+  type Item = S::Item;
+  type Error = S::Error;
+
+  fn poll(&mut self) -> Poll<Option<S::Item>, S::Error> {
+    if !self.done {
+      match self.until.poll() {
+        Ok(Async::NotReady) => return self.stream.poll(),
+        _ => self.done = true,
+      }
+    }
+
+    Ok(Async::Ready(None))
+  }
+}
+
+/// Waits up to `timeout` for the tasks left on
+/// `runtime` (i.e. in-flight requests) to finish.
+/// Returns whether they finished in time.
+pub fn drain(runtime: Runtime, timeout: Duration) -> bool {
+  let (done, drained) = mpsc::channel();
+
+  thread::spawn(move || {
+    let _ = runtime.shutdown_on_idle().wait();
+    let _ = done.send(());
+  });
+
+  drained.recv_timeout(timeout).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::{future, stream, sync::oneshot};
+  use std::time::Instant;
+
+  #[test]
+  fn test_until_ends_stream() {
+    let (stop, stopped) = oneshot::channel::<()>();
+    let mut stream = until(stream::repeat::<_, ()>(1), stopped).wait();
+
+    assert_eq!(stream.next(), Some(Ok(1)));
+
+    stop.send(()).unwrap();
+
+    assert_eq!(stream.next(), None);
+    assert_eq!(stream.next(), None);
+  }
+
+  #[test]
+  fn test_drain_idle_runtime() {
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(future::lazy(|| Ok(())));
+
+    assert!(drain(runtime, Duration::from_secs(5)));
+  }
+
+  #[test]
+  fn test_drain_timeout() {
+    let mut runtime = Runtime::new().unwrap();
+    runtime.spawn(future::empty());
+    let started = Instant::now();
+
+    assert!(!drain(runtime, Duration::from_millis(100)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+  }
+}
@@ -0,0 +1,238 @@
+use crate::error::Error;
+use futures::future::{self, Either};
+use futures::{Future, Stream};
+use hyper::client::connect::{Connect, Connected, Destination};
+use hyper::client::HttpConnector;
+use native_tls::{Identity, TlsAcceptor as NativeTlsAcceptor, TlsConnector as NativeTlsConnector};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tls::{TlsAcceptor, TlsConnector, TlsStream};
+
+/// Maximum number of TLS handshakes in progress at once.
+const HANDSHAKES: usize = 128;
+
+struct Loaded {
+  modified: (SystemTime, SystemTime),
+  acceptor: Arc<TlsAcceptor>,
+}
+
+/// Accepts TLS connections using a PEM certificate
+/// chain and PKCS #8 key, reloading both when either
+/// file changes.
+pub struct Tls {
+  cert: PathBuf,
+  key: PathBuf,
+  loaded: RwLock<Loaded>,
+}
+
+impl Tls {
+  /// Creates a new `Tls` instance, failing if the
+  /// certificate or key can't be loaded.
+  pub fn new(cert: &Path, key: &Path) -> Result<Tls, Error> {
+    let loaded = load(cert, key)?;
+
+    Ok(Tls {
+      cert: cert.to_path_buf(),
+      key: key.to_path_buf(),
+      loaded: RwLock::new(loaded),
+    })
+  }
+
+  /// Returns the current acceptor, reloading the
+  /// certificate and key if either file changed.
+  /// Keeps the previous acceptor if reloading fails.
+  pub fn acceptor(&self) -> Arc<TlsAcceptor> {
+    let loaded = self.loaded.read().unwrap();
+    let modified = match modified(&self.cert, &self.key) {
+      Ok(modified) if modified != loaded.modified => modified,
+      _ => return loaded.acceptor.clone(),
+    };
+
+    drop(loaded);
+
+    let mut loaded = self.loaded.write().unwrap();
+
+    match load(&self.cert, &self.key) {
+      Ok(reloaded) => *loaded = reloaded,
+      Err(_) => loaded.modified = modified,
+    }
+
+    loaded.acceptor.clone()
+  }
+
+  /// Accepts connections from `listener` and performs
+  /// their TLS handshakes. Failed connections and
+  /// handshakes are dropped rather than ending the stream.
+  pub fn incoming(
+    self: Arc<Self>,
+    listener: TcpListener,
+  ) -> impl Stream<Item = TlsStream<TcpStream>, Error = io::Error> {
+    listener
+      .incoming()
+      .then(|socket| Ok(socket.ok()))
+      .filter_map(|socket| socket)
+      .map(move |socket| {
+        self
+          .acceptor()
+          .accept(socket)
+          .then(|stream| Ok(stream.ok()))
+      })
+      .buffer_unordered(HANDSHAKES)
+      .filter_map(|stream| stream)
+  }
+}
+
+/// A client connection, either in the clear or TLS.
+pub trait Io: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Io for T {}
+
+/// Connects hyper clients to `https` URLs over TLS,
+/// verifying certificates against the system's
+/// roots, and to `http` URLs in the clear.
+#[derive(Clone)]
+pub struct Connector {
+  http: HttpConnector,
+  tls: TlsConnector,
+}
+
+impl Connector {
+  pub fn new() -> Result<Connector, Error> {
+    let mut http = HttpConnector::new(1);
+    http.enforce_http(false);
+
+    Ok(Connector {
+      http,
+      tls: TlsConnector::from(NativeTlsConnector::new()?),
+    })
+  }
+}
+
+impl Connect for Connector {
+  type Transport = Box<dyn Io>;
+  type Error = io::Error;
+  type Future = Box<dyn Future<Item = (Box<dyn Io>, Connected), Error = io::Error> + Send>;
+
+  fn connect(&self, destination: Destination) -> Self::Future {
+    let tls = match destination.scheme() {
+      // Without the brackets around IPv6 addresses.
+      "https" => Some((
+        self.tls.clone(),
+        destination
+          .host()
+          .trim_start_matches('[')
+          .trim_end_matches(']')
+          .to_string(),
+      )),
+      _ => None,
+    };
+
+    Box::new(
+      self
+        .http
+        .connect(destination)
+        .and_then(move |(stream, connected)| match tls {
+          Some((tls, host)) => Either::A(
+            tls
+              .connect(&host, stream)
+              .map(|stream| (Box::new(stream) as Box<dyn Io>, connected))
+              .map_err(io::Error::other),
+          ),
+          None => Either::B(future::ok((Box::new(stream) as Box<dyn Io>, connected))),
+        }),
+    )
+  }
+}
+
+fn modified(cert: &Path, key: &Path) -> Result<(SystemTime, SystemTime), Error> {
+  Ok((
+    fs::metadata(cert)?.modified()?,
+    fs::metadata(key)?.modified()?,
+  ))
+}
+
+fn load(cert: &Path, key: &Path) -> Result<Loaded, Error> {
+  let modified = modified(cert, key)?;
+  let identity = Identity::from_pkcs8(&fs::read(cert)?, &fs::read(key)?)?;
+
+  Ok(Loaded {
+    modified,
+    acceptor: Arc::new(TlsAcceptor::from(NativeTlsAcceptor::new(identity)?)),
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::thread;
+  use std::time::Duration;
+  use uuid::Uuid;
+
+  const CERT: &str = include_str!("../../tests/fixtures/tls/cert.pem");
+  const KEY: &str = include_str!("../../tests/fixtures/tls/key.pem");
+
+  fn write_identity() -> (PathBuf, PathBuf) {
+    let dir = env::temp_dir().join(Uuid::new_v4().to_string());
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("cert.pem"), CERT).unwrap();
+    fs::write(dir.join("key.pem"), KEY).unwrap();
+
+    (dir.join("cert.pem"), dir.join("key.pem"))
+  }
+
+  #[test]
+  fn test_load_identity() {
+    let (cert, key) = write_identity();
+
+    assert_eq!(Tls::new(&cert, &key).is_ok(), true);
+  }
+
+  #[test]
+  fn test_load_invalid_identity() {
+    let (cert, key) = write_identity();
+    fs::write(&key, "invalid").unwrap();
+
+    assert_eq!(Tls::new(&cert, &key).is_err(), true);
+  }
+
+  #[test]
+  fn test_reload_identity() {
+    let (cert, key) = write_identity();
+    let tls = Tls::new(&cert, &key).unwrap();
+    let acceptor = tls.acceptor();
+
+    assert!(Arc::ptr_eq(&acceptor, &tls.acceptor()));
+
+    rewrite(&cert, CERT);
+
+    assert!(!Arc::ptr_eq(&acceptor, &tls.acceptor()));
+  }
+
+  #[test]
+  fn test_keep_identity_on_invalid_reload() {
+    let (cert, key) = write_identity();
+    let tls = Tls::new(&cert, &key).unwrap();
+    let acceptor = tls.acceptor();
+
+    rewrite(&key, "invalid");
+
+    assert!(Arc::ptr_eq(&acceptor, &tls.acceptor()));
+  }
+
+  /// Writes `contents` to `path` until the modified
+  /// time changes, for coarse timestamp resolutions.
+  fn rewrite(path: &Path, contents: &str) {
+    let modified = fs::metadata(path).unwrap().modified().unwrap();
+
+    while fs::metadata(path).unwrap().modified().unwrap() == modified {
+      thread::sleep(Duration::from_millis(10));
+      fs::write(path, contents).unwrap();
+    }
+  }
+}
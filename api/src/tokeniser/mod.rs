@@ -1,64 +1,138 @@
 use crate::error::Error;
+use crate::scope::Scope;
 use chrono::{Duration, Utc};
 use jwt::{decode, encode, Header, Validation};
 use uuid::Uuid;
 
 pub type TokenGenerator = Box<dyn Fn(Uuid) -> Result<String, Error> + Send + Sync>;
+/// Signs a token for a user (`sub`) on behalf of an admin (`act`).
+pub type ImpersonationGenerator = Box<dyn Fn(Uuid, Uuid) -> Result<String, Error> + Send + Sync>;
 pub type TokenVerifier = Box<dyn Fn(&str) -> Result<Claims, Error> + Send + Sync>;
 
 pub struct Tokeniser {
   pub generate: TokenGenerator,
   pub verify: TokenVerifier,
+  /// Short-lived tokens for users part way through
+  /// logging in, exchanged for a token once their
+  /// MFA code is verified. Issued separately, so
+  /// they aren't accepted in place of tokens.
+  pub generate_challenge: TokenGenerator,
+  pub verify_challenge: TokenVerifier,
+  /// Tokens for admins to act as another user, naming
+  /// the admin in the `act` claim. They're verified
+  /// like any other token, but can't change credentials.
+  pub impersonate: ImpersonationGenerator,
 }
 
+/// Scopes for impersonation, everything but
+/// changing credentials.
+const IMPERSONATION_SCOPES: [Scope; 5] = [
+  Scope::UsersRead,
+  Scope::UsersWrite,
+  Scope::GroupsRead,
+  Scope::GroupsWrite,
+  Scope::CredentialsRead,
+];
+
 impl Tokeniser {
   pub fn new(secret: &str) -> Tokeniser {
     let iss = env!("CARGO_PKG_NAME").to_string();
-    let secret_clone1 = secret.to_string();
-    let secret_clone2 = secret.to_string(); // Can't `move` twice, don't want `Arc`
-    let validation_config = Validation {
-      iss: Some(iss.clone()),
-      ..Default::default()
-    };
+    let challenge_iss = format!("{}/mfa", iss);
 
     Tokeniser {
-      generate: Box::new(move |user_id: Uuid| {
-        let iat = Utc::now();
-        let exp = iat + Duration::minutes(15);
-        let jti = Uuid::new_v4();
-
-        Ok(encode(
-          &Header::default(),
-          &Claims {
-            exp: exp.timestamp(),
-            iat: iat.timestamp(),
-            iss: iss.clone(),
-            jti,
-            sub: user_id,
-          },
-          secret_clone1.as_bytes(),
-        )?)
-      }),
-      verify: Box::new(move |token: &str| {
-        Ok(decode::<Claims>(token, secret_clone2.as_bytes(), &validation_config)?.claims)
-      }),
+      generate: generator(secret, iss.clone(), Duration::minutes(15)),
+      verify: verifier(secret, iss.clone()),
+      generate_challenge: generator(secret, challenge_iss.clone(), Duration::minutes(5)),
+      verify_challenge: verifier(secret, challenge_iss),
+      impersonate: impersonator(secret, iss),
     }
   }
 }
 
+fn generator(secret: &str, iss: String, ttl: Duration) -> TokenGenerator {
+  let secret = secret.to_string();
+
+  Box::new(move |user_id: Uuid| sign(&secret, &iss, ttl, user_id, &Scope::ALL, None))
+}
+
+fn impersonator(secret: &str, iss: String) -> ImpersonationGenerator {
+  let secret = secret.to_string();
+
+  Box::new(move |user_id: Uuid, actor_id: Uuid| {
+    sign(
+      &secret,
+      &iss,
+      Duration::minutes(15),
+      user_id,
+      &IMPERSONATION_SCOPES,
+      Some(Actor { sub: actor_id }),
+    )
+  })
+}
+
+fn sign(
+  secret: &str,
+  iss: &str,
+  ttl: Duration,
+  sub: Uuid,
+  scopes: &[Scope],
+  act: Option<Actor>,
+) -> Result<String, Error> {
+  let iat = Utc::now();
+  let exp = iat + ttl;
+
+  Ok(encode(
+    &Header::default(),
+    &Claims {
+      act,
+      exp: exp.timestamp(),
+      iat: iat.timestamp(),
+      iss: iss.to_string(),
+      jti: Uuid::new_v4(),
+      scope: Scope::join(scopes),
+      sub,
+    },
+    secret.as_bytes(),
+  )?)
+}
+
+fn verifier(secret: &str, iss: String) -> TokenVerifier {
+  let secret = secret.to_string();
+  let validation_config = Validation {
+    iss: Some(iss),
+    ..Default::default()
+  };
+
+  Box::new(move |token: &str| {
+    Ok(decode::<Claims>(token, secret.as_bytes(), &validation_config)?.claims)
+  })
+}
+
 /// Claims for Json Web Token (JWT):
 ///
+/// - Actor (`act`): Admin impersonating the subject, if any.
 /// - Expiry (`exp`): When the token expires.
 /// - Issued at (`iat`): When the token was issued.
 /// - Issuer (`iss`): Verifies the service that issued the token.
 /// - Json web token ID (`jti`): Useful for blacklisting issued tokens.
+/// - Scope (`scope` or `scp`): Space separated scopes the token is granted, all of them for logins.
 /// - Subject (`sub`): Unique subject identifier of the token, in this case the `User` UUID.
 #[derive(Deserialize, Serialize)]
 pub struct Claims {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub act: Option<Actor>,
   pub exp: i64,
   pub iat: i64,
   pub iss: String,
   pub jti: Uuid,
+  #[serde(alias = "scp")]
+  pub scope: String,
+  pub sub: Uuid,
+}
+
+/// Who is acting for the subject (RFC 8693).
+#[derive(Deserialize, Serialize)]
+pub struct Actor {
   pub sub: Uuid,
 }
 
@@ -100,4 +174,57 @@ mod tests {
 
     assert_eq!(verified_token.sub, id);
   }
+
+  #[test]
+  fn test_challenge_not_token() {
+    let tokeniser = Tokeniser::new("secret");
+    let id = Uuid::new_v4();
+    let challenge = &(tokeniser.generate_challenge)(id).unwrap();
+    let token = &(tokeniser.generate)(id).unwrap();
+
+    assert_eq!((tokeniser.verify_challenge)(challenge).unwrap().sub, id);
+    assert_eq!((tokeniser.verify)(challenge).is_ok(), false);
+    assert_eq!((tokeniser.verify_challenge)(token).is_ok(), false);
+  }
+
+  #[test]
+  fn test_scope_claim() {
+    let secret = "secret";
+    let id = Uuid::new_v4();
+
+    let tokeniser = Tokeniser::new(secret);
+    let token = &(tokeniser.generate)(id).unwrap();
+    let verified_token = (tokeniser.verify)(token).unwrap();
+
+    assert_eq!(Scope::split(&verified_token.scope), Scope::ALL.to_vec());
+  }
+
+  #[test]
+  fn test_scp_claim() {
+    let tokeniser = Tokeniser::new("secret");
+    let claims = json!({
+      "exp": Utc::now().timestamp() + 60,
+      "iat": Utc::now().timestamp(),
+      "iss": env!("CARGO_PKG_NAME"),
+      "jti": Uuid::new_v4(),
+      "scp": "users:read",
+      "sub": Uuid::new_v4(),
+    });
+    let token = encode(&Header::default(), &claims, b"secret").unwrap();
+
+    assert_eq!((tokeniser.verify)(&token).unwrap().scope, "users:read");
+  }
+
+  #[test]
+  fn test_impersonate() {
+    let tokeniser = Tokeniser::new("secret");
+    let id = Uuid::new_v4();
+    let admin_id = Uuid::new_v4();
+    let token = &(tokeniser.impersonate)(id, admin_id).unwrap();
+    let claims = (tokeniser.verify)(token).unwrap();
+
+    assert_eq!(claims.sub, id);
+    assert_eq!(claims.act.unwrap().sub, admin_id);
+    assert!(!Scope::split(&claims.scope).contains(&Scope::CredentialsWrite));
+  }
 }
@@ -0,0 +1,181 @@
+use crate::error::Error;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac};
+
+/// Digits in each code.
+const DIGITS: u32 = 6;
+/// Seconds each code is valid for.
+const PERIOD: i64 = 30;
+/// Steps either side of now that codes are
+/// accepted from, to allow for clock drift.
+const SKEW: i64 = 1;
+/// RFC 4648 base32 alphabet, used by authenticator apps.
+const ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Generates a random 160 bit secret, base32 encoded.
+pub fn secret() -> Result<String, Error> {
+  Ok(encode(&random(20)?))
+}
+
+/// Fills a buffer of `length` from the system's
+/// secure random number generator.
+pub fn random(length: usize) -> Result<Vec<u8>, Error> {
+  let mut bytes = vec![0; length];
+  SystemRandom::new()
+    .fill(&mut bytes)
+    .map_err(|_| Error::Str("Failed to generate random bytes"))?;
+
+  Ok(bytes)
+}
+
+/// `otpauth://` URI for enrolling `secret` in an
+/// authenticator app, usually shown as a QR code.
+pub fn uri(secret: &str, issuer: &str, account: &str) -> String {
+  format!(
+    "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+    escape(issuer),
+    escape(account),
+    secret,
+    escape(issuer),
+    DIGITS,
+    PERIOD
+  )
+}
+
+/// Code for the time `step` (RFC 6238).
+pub fn code(secret: &[u8], step: i64) -> u32 {
+  let key = hmac::SigningKey::new(&digest::SHA1, secret);
+  let hash = hmac::sign(&key, &step.to_be_bytes());
+  let hash = hash.as_ref();
+  let offset = (hash[hash.len() - 1] & 0xf) as usize;
+  let truncated = u32::from_be_bytes([
+    hash[offset] & 0x7f,
+    hash[offset + 1],
+    hash[offset + 2],
+    hash[offset + 3],
+  ]);
+
+  truncated % 10u32.pow(DIGITS)
+}
+
+/// Finds the time step within `SKEW` of `time`
+/// (seconds since the epoch) that `code` is for.
+pub fn verify(secret: &str, code: &str, time: i64) -> Option<i64> {
+  let secret = decode(secret)?;
+
+  if code.len() != DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+    return None;
+  }
+
+  let code = code.parse::<u32>().ok()?;
+  let now = time / PERIOD;
+
+  // Checks every step, so timing doesn't reveal which matched.
+  (now - SKEW..=now + SKEW).fold(None, |found, step| {
+    if self::code(&secret, step) == code {
+      found.or(Some(step))
+    } else {
+      found
+    }
+  })
+}
+
+/// Base32 encodes `bytes` without padding.
+pub fn encode(bytes: &[u8]) -> String {
+  let mut encoded = String::new();
+  let mut buffer = 0u32;
+  let mut bits = 0;
+
+  for byte in bytes {
+    buffer = (buffer << 8) | u32::from(*byte);
+    bits += 8;
+
+    while bits >= 5 {
+      bits -= 5;
+      encoded.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+    }
+  }
+
+  if bits > 0 {
+    encoded.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+  }
+
+  encoded
+}
+
+/// Decodes unpadded base32, ignoring case.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+  let mut bytes = Vec::new();
+  let mut buffer = 0u32;
+  let mut bits = 0;
+
+  for char in encoded.trim_end_matches('=').bytes() {
+    let value = ALPHABET
+      .iter()
+      .position(|&letter| letter == char.to_ascii_uppercase())?;
+    buffer = (buffer << 5) | value as u32;
+    bits += 5;
+
+    if bits >= 8 {
+      bits -= 8;
+      bytes.push((buffer >> bits) as u8);
+    }
+  }
+
+  Some(bytes)
+}
+
+/// Percent-encodes all but unreserved characters.
+fn escape(value: &str) -> String {
+  value
+    .bytes()
+    .map(|byte| match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        (byte as char).to_string()
+      }
+      _ => format!("%{:02X}", byte),
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// RFC 6238 SHA1 test secret.
+  const SECRET: &[u8] = b"12345678901234567890";
+
+  #[test]
+  fn test_code() {
+    // RFC 6238 vectors, truncated to 6 digits.
+    assert_eq!(code(SECRET, 59 / PERIOD), 287_082);
+    assert_eq!(code(SECRET, 1_111_111_109 / PERIOD), 81_804);
+    assert_eq!(code(SECRET, 2_000_000_000 / PERIOD), 279_037);
+  }
+
+  #[test]
+  fn test_verify() {
+    let secret = encode(SECRET);
+
+    assert_eq!(verify(&secret, "081804", 1_111_111_109), Some(37_037_036));
+    assert_eq!(verify(&secret, "081804", 1_111_111_139), Some(37_037_036));
+    assert_eq!(verify(&secret, "081804", 1_111_111_169), None);
+    assert_eq!(verify(&secret, "81804", 1_111_111_109), None);
+  }
+
+  #[test]
+  fn test_base32() {
+    assert_eq!(encode(b"foobar"), "MZXW6YTBOI");
+    assert_eq!(decode("mzxw6ytboi").unwrap(), b"foobar");
+    assert_eq!(decode(&encode(SECRET)).unwrap(), SECRET);
+    assert_eq!(decode("1"), None);
+  }
+
+  #[test]
+  fn test_uri() {
+    assert_eq!(
+      uri("MZXW6YTBOI", "Graphy", "a@b.c"),
+      "otpauth://totp/Graphy:a%40b.c?secret=MZXW6YTBOI&issuer=Graphy&algorithm=SHA1&digits=6&period=30"
+    );
+  }
+}
@@ -0,0 +1,189 @@
+use super::Span;
+use crate::config::TraceExporter;
+use crate::error::Error;
+use crate::logger;
+use futures::{Future, Stream};
+use hyper::{header, Body, Client, Request};
+use log::Level;
+use serde_json::Value;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::runtime::current_thread::Runtime;
+use tokio::timer::Timeout;
+
+/// Spans sent to the collector per request.
+const BATCH_SIZE: usize = 512;
+/// Longest a span waits before being sent.
+const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+  static ref EXPORTER: RwLock<Option<Exporter>> = RwLock::new(None);
+}
+
+enum Exporter {
+  Stdout,
+  File(Mutex<File>),
+  Otlp {
+    spans: Mutex<Sender<Value>>,
+    worker: JoinHandle<()>,
+  },
+}
+
+/// Starts exporting finished spans. Spans are
+/// written as OTLP JSON, one per line for
+/// `stdout` and files, or batched and posted
+/// to an OTLP/HTTP collector.
+pub fn init(exporter: &TraceExporter) -> Result<(), Error> {
+  let exporter = match exporter {
+    TraceExporter::Disabled => return Ok(()),
+    TraceExporter::Stdout => Exporter::Stdout,
+    TraceExporter::File(path) => Exporter::File(Mutex::new(
+      OpenOptions::new().create(true).append(true).open(path)?,
+    )),
+    TraceExporter::Otlp(endpoint) => {
+      let (spans, received) = mpsc::channel();
+      let endpoint = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+
+      Exporter::Otlp {
+        spans: Mutex::new(spans),
+        worker: thread::Builder::new()
+          .name("trace-exporter".to_string())
+          .spawn(move || batch(&endpoint, &received))?,
+      }
+    }
+  };
+
+  *EXPORTER.write().expect("exporter lock is poisoned") = Some(exporter);
+
+  Ok(())
+}
+
+/// Stops exporting, sending any spans that
+/// are still batched.
+pub fn shutdown() {
+  let exporter = EXPORTER.write().expect("exporter lock is poisoned").take();
+
+  match exporter {
+    Some(Exporter::File(file)) => {
+      let _ = file.lock().map(|mut file| file.flush());
+    }
+    Some(Exporter::Otlp { spans, worker }) => {
+      // Disconnecting the channel flushes the last batch.
+      drop(spans);
+      let _ = worker.join();
+    }
+    _ => (),
+  }
+}
+
+pub(super) fn export(span: &Span) {
+  let exporter = EXPORTER.read().expect("exporter lock is poisoned");
+
+  match *exporter {
+    Some(Exporter::Stdout) => {
+      let stdout = io::stdout();
+      let _ = writeln!(stdout.lock(), "{}", span.to_json());
+    }
+    Some(Exporter::File(ref file)) => {
+      if let Ok(mut file) = file.lock() {
+        let _ = writeln!(file, "{}", span.to_json());
+      }
+    }
+    Some(Exporter::Otlp { ref spans, .. }) => {
+      if let Ok(spans) = spans.lock() {
+        let _ = spans.send(span.to_json());
+      }
+    }
+    None => (),
+  }
+}
+
+/// Sends spans to `endpoint` once `BATCH_SIZE`
+/// have finished or `BATCH_INTERVAL` has passed,
+/// until `spans` disconnects.
+fn batch(endpoint: &str, spans: &Receiver<Value>) {
+  let mut runtime = match Runtime::new() {
+    Ok(runtime) => runtime,
+    Err(err) => return failed(&err.to_string()),
+  };
+  let client = Client::new();
+  let mut batch = Vec::new();
+  let mut deadline = Instant::now() + BATCH_INTERVAL;
+
+  loop {
+    let timeout = deadline.saturating_duration_since(Instant::now());
+    let disconnected = match spans.recv_timeout(timeout) {
+      Ok(span) => {
+        batch.push(span);
+
+        if batch.len() < BATCH_SIZE {
+          continue;
+        }
+
+        false
+      }
+      Err(RecvTimeoutError::Timeout) => false,
+      Err(RecvTimeoutError::Disconnected) => true,
+    };
+
+    if !batch.is_empty() {
+      let request = Request::post(endpoint)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(payload(batch.split_off(0)).to_string()))
+        .expect("request is valid");
+      let sent = client
+        .request(request)
+        .and_then(|response| {
+          let status = response.status();
+          response.into_body().concat2().map(move |_| status)
+        })
+        .map_err(|err| err.to_string());
+
+      match runtime.block_on(Timeout::new(sent, EXPORT_TIMEOUT)) {
+        Ok(ref status) if status.is_success() => (),
+        Ok(status) => failed(&format!("Collector responded {}", status)),
+        Err(err) => failed(&err.into_inner().unwrap_or_else(|| "Timed out".to_string())),
+      }
+    }
+
+    if disconnected {
+      return;
+    }
+
+    deadline = Instant::now() + BATCH_INTERVAL;
+  }
+}
+
+/// Wraps `spans` in an OTLP export request.
+fn payload(spans: Vec<Value>) -> Value {
+  json!({
+    "resourceSpans": [{
+      "resource": {
+        "attributes": [{
+          "key": "service.name",
+          "value": { "stringValue": env!("CARGO_PKG_NAME") },
+        }],
+      },
+      "scopeSpans": [{
+        "scope": {
+          "name": env!("CARGO_PKG_NAME"),
+          "version": env!("CARGO_PKG_VERSION"),
+        },
+        "spans": spans,
+      }],
+    }],
+  })
+}
+
+fn failed(error: &str) {
+  logger::event(
+    Level::Warn,
+    "Trace export failed",
+    json!({ "error": error }),
+  );
+}
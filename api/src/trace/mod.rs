@@ -0,0 +1,328 @@
+mod exporter;
+
+pub use self::exporter::{init, shutdown};
+
+use serde_json::Value;
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+thread_local! {
+  /// Spans entered on this thread, innermost last.
+  static ENTERED: RefCell<Vec<SpanContext>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Identifies a span within a trace, as propagated
+/// by the W3C `traceparent` header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpanContext {
+  pub trace_id: [u8; 16],
+  pub span_id: [u8; 8],
+  pub sampled: bool,
+}
+
+impl SpanContext {
+  /// Starts a new, sampled trace.
+  pub fn root() -> SpanContext {
+    SpanContext {
+      trace_id: *Uuid::new_v4().as_bytes(),
+      span_id: span_id(),
+      sampled: true,
+    }
+  }
+
+  /// Creates a context for a child span in the same trace.
+  pub fn child(&self) -> SpanContext {
+    SpanContext {
+      span_id: span_id(),
+      ..*self
+    }
+  }
+
+  /// Parses a `traceparent` header, i.e.
+  /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+  pub fn from_traceparent(header: &str) -> Option<SpanContext> {
+    let parts = header.trim().split('-').collect::<Vec<_>>();
+
+    let (version, trace_id, span_id, flags) = match parts.as_slice() {
+      [version, trace_id, span_id, flags, ..] => (*version, *trace_id, *span_id, *flags),
+      _ => return None,
+    };
+
+    // Later versions may append fields, but version `00` may not.
+    if version == "ff"
+      || (version == "00" && parts.len() != 4)
+      || decode::<[u8; 1]>(version).is_none()
+    {
+      return None;
+    }
+
+    let trace_id = decode::<[u8; 16]>(trace_id)?;
+    let span_id = decode::<[u8; 8]>(span_id)?;
+    let flags = decode::<[u8; 1]>(flags)?;
+
+    if trace_id == [0; 16] || span_id == [0; 8] {
+      return None;
+    }
+
+    Some(SpanContext {
+      trace_id,
+      span_id,
+      sampled: flags[0] & 1 == 1,
+    })
+  }
+
+  /// Formats the context as a `traceparent` header.
+  pub fn to_traceparent(&self) -> String {
+    format!(
+      "00-{}-{}-{:02x}",
+      encode(&self.trace_id),
+      encode(&self.span_id),
+      self.sampled as u8
+    )
+  }
+
+  /// The span entered most recently on this thread.
+  pub fn current() -> Option<SpanContext> {
+    ENTERED.with(|entered| entered.borrow().last().cloned())
+  }
+}
+
+/// OTLP span kind.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanKind {
+  Internal = 1,
+  Server = 2,
+  Client = 3,
+}
+
+/// A timed operation, exported when dropped
+/// if its trace is sampled.
+pub struct Span {
+  context: SpanContext,
+  parent_id: Option<[u8; 8]>,
+  name: String,
+  kind: SpanKind,
+  start: SystemTime,
+  attributes: Vec<(String, Value)>,
+  error: Option<String>,
+  entered: bool,
+}
+
+impl Span {
+  /// Starts a span as a child of `parent`, or of a
+  /// new trace if there's no parent.
+  pub fn new(name: &str, kind: SpanKind, parent: Option<&SpanContext>) -> Span {
+    Span {
+      context: parent
+        .map(SpanContext::child)
+        .unwrap_or_else(SpanContext::root),
+      parent_id: parent.map(|parent| parent.span_id),
+      name: name.to_string(),
+      kind,
+      start: SystemTime::now(),
+      attributes: Vec::new(),
+      error: None,
+      entered: false,
+    }
+  }
+
+  /// Starts a span as a child of the span entered
+  /// on this thread. Spans outside of a trace
+  /// (i.e. migrations at startup) aren't exported.
+  pub fn child(name: &str, kind: SpanKind) -> Span {
+    match SpanContext::current() {
+      Some(parent) => Span::new(name, kind, Some(&parent)),
+      None => {
+        let mut span = Span::new(name, kind, None);
+        span.context.sampled = false;
+        span
+      }
+    }
+  }
+
+  /// Uses `context` as this span's own context, for
+  /// spans whose IDs were handed out before they
+  /// started (i.e. the HTTP request span).
+  pub fn with_context(mut self, context: SpanContext) -> Span {
+    self.context = context;
+    self
+  }
+
+  pub fn with_start(mut self, start: SystemTime) -> Span {
+    self.start = start;
+    self
+  }
+
+  /// Makes this the parent of spans started on this
+  /// thread with `Span::child` until it's dropped.
+  pub fn enter(mut self) -> Span {
+    ENTERED.with(|entered| entered.borrow_mut().push(self.context));
+    self.entered = true;
+    self
+  }
+
+  pub fn context(&self) -> &SpanContext {
+    &self.context
+  }
+
+  pub fn set_attribute<V: Into<Value>>(&mut self, key: &str, value: V) {
+    self.attributes.push((key.to_string(), value.into()));
+  }
+
+  /// Marks the span as failed with `message`.
+  pub fn set_error(&mut self, message: &str) {
+    self.error = Some(message.to_string());
+  }
+
+  /// Encodes the span as OTLP JSON, ending now.
+  pub fn to_json(&self) -> Value {
+    let attributes = self
+      .attributes
+      .iter()
+      .map(|(key, value)| json!({ "key": key, "value": attribute(value) }))
+      .collect::<Vec<_>>();
+    let status = match self.error {
+      Some(ref message) => json!({ "code": 2, "message": message }),
+      None => json!({ "code": 0 }),
+    };
+
+    json!({
+      "traceId": encode(&self.context.trace_id),
+      "spanId": encode(&self.context.span_id),
+      "parentSpanId": self.parent_id.as_ref().map(|id| encode(id)).unwrap_or_default(),
+      "name": self.name,
+      "kind": self.kind as u8,
+      "startTimeUnixNano": nanos(self.start).to_string(),
+      "endTimeUnixNano": nanos(SystemTime::now()).to_string(),
+      "attributes": attributes,
+      "status": status,
+    })
+  }
+}
+
+impl Drop for Span {
+  fn drop(&mut self) {
+    if self.entered {
+      ENTERED.with(|entered| {
+        entered.borrow_mut().pop();
+      });
+    }
+
+    if self.context.sampled {
+      exporter::export(self);
+    }
+  }
+}
+
+fn span_id() -> [u8; 8] {
+  let mut id = [0; 8];
+  id.copy_from_slice(&Uuid::new_v4().as_bytes()[..8]);
+  id
+}
+
+fn nanos(time: SystemTime) -> u128 {
+  time
+    .duration_since(UNIX_EPOCH)
+    .map(|duration| duration.as_nanos())
+    .unwrap_or(0)
+}
+
+fn attribute(value: &Value) -> Value {
+  match value {
+    Value::Bool(value) => json!({ "boolValue": value }),
+    Value::Number(value) if value.is_f64() => json!({ "doubleValue": value }),
+    Value::Number(value) => json!({ "intValue": value.to_string() }),
+    Value::String(value) => json!({ "stringValue": value }),
+    value => json!({ "stringValue": value.to_string() }),
+  }
+}
+
+fn encode(bytes: &[u8]) -> String {
+  bytes.iter().fold(String::new(), |mut hex, byte| {
+    let _ = write!(hex, "{:02x}", byte);
+    hex
+  })
+}
+
+/// Decodes lowercase hex that fills `T` exactly.
+fn decode<T: Default + AsMut<[u8]>>(hex: &str) -> Option<T> {
+  let mut bytes = T::default();
+
+  if hex.len() != bytes.as_mut().len() * 2
+    || !hex
+      .bytes()
+      .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+  {
+    return None;
+  }
+
+  for (i, byte) in bytes.as_mut().iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+  }
+
+  Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+  #[test]
+  fn test_traceparent_round_trip() {
+    let context = SpanContext::from_traceparent(TRACEPARENT).unwrap();
+
+    assert!(context.sampled);
+    assert_eq!(context.to_traceparent(), TRACEPARENT);
+  }
+
+  #[test]
+  fn test_traceparent_not_sampled() {
+    let context =
+      SpanContext::from_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")
+        .unwrap();
+
+    assert!(!context.sampled);
+  }
+
+  #[test]
+  fn test_traceparent_invalid() {
+    for header in &[
+      "",
+      "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+      "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+      "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+      "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+      "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+      "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+    ] {
+      assert_eq!(SpanContext::from_traceparent(header), None, "{}", header);
+    }
+  }
+
+  #[test]
+  fn test_traceparent_future_version() {
+    let header = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra";
+
+    assert!(SpanContext::from_traceparent(header).is_some());
+  }
+
+  #[test]
+  fn test_child_spans() {
+    let parent = Span::new("parent", SpanKind::Server, None).enter();
+    let child = Span::child("child", SpanKind::Internal);
+    let json = child.to_json();
+
+    assert_eq!(child.context().trace_id, parent.context().trace_id);
+    assert_eq!(json["parentSpanId"], encode(&parent.context().span_id));
+
+    drop(child);
+    drop(parent);
+
+    assert_eq!(SpanContext::current(), None);
+    assert!(!Span::child("orphan", SpanKind::Internal).context().sampled);
+  }
+}
@@ -0,0 +1,176 @@
+use std::convert::TryFrom;
+
+/// Deepest nesting decoded, authenticators only
+/// use a couple of levels.
+const MAX_DEPTH: usize = 8;
+
+/// A CBOR (RFC 8949) item, limited to the types
+/// authenticators send. Tags, floats and indefinite
+/// lengths aren't supported.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+  Integer(i64),
+  Bytes(Vec<u8>),
+  Text(String),
+  Array(Vec<Value>),
+  Map(Vec<(Value, Value)>),
+  Bool(bool),
+  Null,
+}
+
+impl Value {
+  /// Value for `key` if this is a map.
+  pub fn get(&self, key: &Value) -> Option<&Value> {
+    match self {
+      Value::Map(entries) => entries
+        .iter()
+        .find(|(entry, _)| entry == key)
+        .map(|(_, value)| value),
+      _ => None,
+    }
+  }
+}
+
+/// Decodes the first item in `input`, returning
+/// it with the bytes that follow.
+pub fn decode(input: &[u8]) -> Option<(Value, &[u8])> {
+  item(input, 0)
+}
+
+fn item(input: &[u8], depth: usize) -> Option<(Value, &[u8])> {
+  if depth > MAX_DEPTH {
+    return None;
+  }
+
+  let (&initial, rest) = input.split_first()?;
+  let info = initial & 0x1f;
+  let (argument, mut rest) = argument(info, rest)?;
+
+  let value = match initial >> 5 {
+    0 => Value::Integer(i64::try_from(argument).ok()?),
+    1 => Value::Integer(-1 - i64::try_from(argument).ok()?),
+    major @ 2 | major @ 3 => {
+      let length = usize::try_from(argument).ok()?;
+
+      if rest.len() < length {
+        return None;
+      }
+
+      let (bytes, remaining) = rest.split_at(length);
+      rest = remaining;
+
+      if major == 2 {
+        Value::Bytes(bytes.to_vec())
+      } else {
+        Value::Text(String::from_utf8(bytes.to_vec()).ok()?)
+      }
+    }
+    4 => {
+      let mut items = Vec::new();
+
+      for _ in 0..argument {
+        let (value, remaining) = item(rest, depth + 1)?;
+        items.push(value);
+        rest = remaining;
+      }
+
+      Value::Array(items)
+    }
+    5 => {
+      let mut entries = Vec::new();
+
+      for _ in 0..argument {
+        let (key, remaining) = item(rest, depth + 1)?;
+        let (value, remaining) = item(remaining, depth + 1)?;
+        entries.push((key, value));
+        rest = remaining;
+      }
+
+      Value::Map(entries)
+    }
+    7 => match info {
+      20 => Value::Bool(false),
+      21 => Value::Bool(true),
+      22 => Value::Null,
+      _ => return None,
+    },
+    _ => return None,
+  };
+
+  Some((value, rest))
+}
+
+/// Reads the argument following an initial byte
+/// with additional information `info`.
+fn argument(info: u8, input: &[u8]) -> Option<(u64, &[u8])> {
+  let length = match info {
+    0..=23 => return Some((u64::from(info), input)),
+    24 => 1,
+    25 => 2,
+    26 => 4,
+    27 => 8,
+    _ => return None,
+  };
+
+  if input.len() < length {
+    return None;
+  }
+
+  let (bytes, rest) = input.split_at(length);
+  let argument = bytes
+    .iter()
+    .fold(0, |argument, byte| (argument << 8) | u64::from(*byte));
+
+  Some((argument, rest))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn value(input: &[u8]) -> Option<Value> {
+    decode(input).map(|(value, _)| value)
+  }
+
+  #[test]
+  fn test_decode_scalars() {
+    // RFC 8949 appendix A examples.
+    assert_eq!(value(&[0x00]), Some(Value::Integer(0)));
+    assert_eq!(value(&[0x19, 0x03, 0xe8]), Some(Value::Integer(1000)));
+    assert_eq!(value(&[0x38, 0x63]), Some(Value::Integer(-100)));
+    assert_eq!(value(&[0x43, 1, 2, 3]), Some(Value::Bytes(vec![1, 2, 3])));
+    assert_eq!(value(&[0x61, 0x61]), Some(Value::Text("a".to_string())));
+    assert_eq!(value(&[0xf5]), Some(Value::Bool(true)));
+    assert_eq!(value(&[0xf6]), Some(Value::Null));
+  }
+
+  #[test]
+  fn test_decode_collections() {
+    let map = value(&[0xa2, 0x01, 0x02, 0x20, 0x82, 0x03, 0x04]).unwrap();
+
+    assert_eq!(map.get(&Value::Integer(1)), Some(&Value::Integer(2)));
+    assert_eq!(
+      map.get(&Value::Integer(-1)),
+      Some(&Value::Array(vec![Value::Integer(3), Value::Integer(4)]))
+    );
+    assert_eq!(map.get(&Value::Integer(2)), None);
+  }
+
+  #[test]
+  fn test_decode_rest() {
+    let (value, rest) = decode(&[0x01, 0x02, 0x03]).unwrap();
+
+    assert_eq!(value, Value::Integer(1));
+    assert_eq!(rest, &[0x02, 0x03]);
+  }
+
+  #[test]
+  fn test_decode_invalid() {
+    assert_eq!(value(&[]), None);
+    assert_eq!(value(&[0x43, 1, 2]), None);
+    assert_eq!(value(&[0x82, 0x01]), None);
+    assert_eq!(value(&[0x5f]), None);
+    assert_eq!(value(&[0xc0, 0x00]), None);
+    assert_eq!(value(&[0x81; 16]), None);
+  }
+}
@@ -0,0 +1,343 @@
+mod cbor;
+
+use self::cbor::Value as Cbor;
+use crate::config::Config;
+use crate::error::Error;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, signature};
+use serde_json::Value;
+use untrusted::Input;
+use uuid::Uuid;
+
+/// Milliseconds clients have to complete a ceremony,
+/// and how long its challenge lasts.
+pub const TIMEOUT: i64 = 300_000;
+/// COSE algorithm for ECDSA with P-256 and SHA-256,
+/// the only one accepted.
+const ES256: i64 = -7;
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+const MALFORMED_ATTESTATION: &str = "Invalid passkey - Malformed attestation";
+
+/// Relying party for passkey (WebAuthn) ceremonies.
+/// Verifies what authenticators return, without
+/// checking attestation, so any authenticator can
+/// be registered.
+pub struct Webauthn {
+  /// Domain passkeys are scoped to, i.e. `example.com`.
+  pub rp_id: String,
+  /// Name shown by authenticators.
+  pub rp_name: String,
+  /// Origin the client runs on, i.e. `https://example.com`.
+  pub origin: String,
+}
+
+/// A newly registered credential.
+pub struct Credential {
+  pub id: Vec<u8>,
+  /// Uncompressed P-256 point.
+  pub public_key: Vec<u8>,
+  pub sign_count: u32,
+}
+
+impl Webauthn {
+  /// Creates a new `Webauthn` from the `webauthn`
+  /// settings in `config`.
+  pub fn new(config: &Config) -> Webauthn {
+    Webauthn {
+      rp_id: config.webauthn_rp_id.clone(),
+      rp_name: config.mfa_issuer.clone(),
+      origin: config.webauthn_origin.clone(),
+    }
+  }
+
+  /// Options for `navigator.credentials.create()`.
+  /// Discoverable credentials are required, so they
+  /// can be used without entering an email.
+  pub fn creation_options(
+    &self,
+    challenge: &[u8],
+    user_id: &Uuid,
+    email: &str,
+    exclude: &[Vec<u8>],
+  ) -> Value {
+    let exclude = exclude
+      .iter()
+      .map(|id| json!({ "type": "public-key", "id": encode(id) }))
+      .collect::<Vec<_>>();
+
+    json!({
+      "rp": { "id": self.rp_id, "name": self.rp_name },
+      "user": {
+        "id": encode(user_id.as_bytes()),
+        "name": email,
+        "displayName": email,
+      },
+      "challenge": encode(challenge),
+      "pubKeyCredParams": [{ "type": "public-key", "alg": ES256 }],
+      "timeout": TIMEOUT,
+      "excludeCredentials": exclude,
+      "authenticatorSelection": {
+        "residentKey": "required",
+        "requireResidentKey": true,
+        "userVerification": "required",
+      },
+      "attestation": "none",
+    })
+  }
+
+  /// Options for `navigator.credentials.get()`.
+  pub fn request_options(&self, challenge: &[u8]) -> Value {
+    json!({
+      "challenge": encode(challenge),
+      "rpId": self.rp_id,
+      "timeout": TIMEOUT,
+      "userVerification": "required",
+    })
+  }
+
+  /// Checks `client_data_json` is for a ceremony of
+  /// `kind` (i.e. `webauthn.create`) on our origin,
+  /// returning its challenge.
+  pub fn client_data(&self, client_data_json: &[u8], kind: &str) -> Result<Vec<u8>, Error> {
+    let client_data = serde_json::from_slice::<Value>(client_data_json)
+      .map_err(|_| Error::InvalidPasskey("Invalid passkey - Malformed client data"))?;
+
+    if client_data["type"] != kind {
+      return Err(Error::InvalidPasskey("Invalid passkey - Wrong ceremony"));
+    }
+
+    if client_data["origin"] != self.origin.as_str() || client_data["crossOrigin"] == true {
+      return Err(Error::InvalidPasskey("Invalid passkey - Wrong origin"));
+    }
+
+    client_data["challenge"]
+      .as_str()
+      .ok_or(Error::InvalidPasskey(
+        "Invalid passkey - Malformed client data",
+      ))
+      .and_then(decode)
+  }
+
+  /// Verifies an attestation object from registering,
+  /// returning the new credential.
+  pub fn register(&self, attestation_object: &[u8]) -> Result<Credential, Error> {
+    let attestation = cbor::decode(attestation_object)
+      .ok_or(Error::InvalidPasskey(MALFORMED_ATTESTATION))?
+      .0;
+    let auth_data = match attestation.get(&Cbor::Text("authData".to_string())) {
+      Some(Cbor::Bytes(auth_data)) => auth_data,
+      _ => return Err(Error::InvalidPasskey(MALFORMED_ATTESTATION)),
+    };
+    let (flags, sign_count, attested) = self.authenticator_data(auth_data)?;
+
+    if flags & ATTESTED_CREDENTIAL == 0 || attested.len() < 18 {
+      return Err(Error::InvalidPasskey(MALFORMED_ATTESTATION));
+    }
+
+    // Skips the authenticator's AAGUID.
+    let length = usize::from(u16::from_be_bytes([attested[16], attested[17]]));
+    let attested = &attested[18..];
+
+    if attested.len() < length {
+      return Err(Error::InvalidPasskey(MALFORMED_ATTESTATION));
+    }
+
+    let (id, key) = attested.split_at(length);
+    let key = cbor::decode(key)
+      .ok_or(Error::InvalidPasskey(MALFORMED_ATTESTATION))?
+      .0;
+
+    Ok(Credential {
+      id: id.to_vec(),
+      public_key: public_key(&key)?,
+      sign_count,
+    })
+  }
+
+  /// Verifies an assertion from logging in was signed
+  /// by `public_key`, returning its sign count.
+  pub fn authenticate(
+    &self,
+    authenticator_data: &[u8],
+    client_data_json: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+  ) -> Result<u32, Error> {
+    let (_, sign_count, _) = self.authenticator_data(authenticator_data)?;
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(digest::digest(&digest::SHA256, client_data_json).as_ref());
+
+    signature::verify(
+      &signature::ECDSA_P256_SHA256_ASN1,
+      Input::from(public_key),
+      Input::from(&signed),
+      Input::from(signature),
+    )
+    .map_err(|_| Error::InvalidPasskey("Invalid passkey - Bad signature"))?;
+
+    Ok(sign_count)
+  }
+
+  /// Checks authenticator data is for our relying
+  /// party and the user was verified, returning its
+  /// flags, sign count and the bytes that follow.
+  fn authenticator_data<'a>(&self, data: &'a [u8]) -> Result<(u8, u32, &'a [u8]), Error> {
+    if data.len() < 37 {
+      return Err(Error::InvalidPasskey(
+        "Invalid passkey - Malformed authenticator data",
+      ));
+    }
+
+    let rp_id_hash = digest::digest(&digest::SHA256, self.rp_id.as_bytes());
+
+    if &data[..32] != rp_id_hash.as_ref() {
+      return Err(Error::InvalidPasskey(
+        "Invalid passkey - Wrong relying party",
+      ));
+    }
+
+    let flags = data[32];
+
+    if flags & USER_PRESENT == 0 || flags & USER_VERIFIED == 0 {
+      return Err(Error::InvalidPasskey("Invalid passkey - User not verified"));
+    }
+
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    Ok((flags, sign_count, &data[37..]))
+  }
+}
+
+/// Random challenge for a ceremony.
+pub fn challenge() -> Result<Vec<u8>, Error> {
+  let mut challenge = vec![0; 32];
+  SystemRandom::new()
+    .fill(&mut challenge)
+    .map_err(|_| Error::Str("Failed to generate random bytes"))?;
+
+  Ok(challenge)
+}
+
+/// Base64url encodes `bytes` without padding,
+/// as used throughout WebAuthn.
+pub fn encode(bytes: &[u8]) -> String {
+  base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Decodes base64url, with or without padding.
+pub fn decode(encoded: &str) -> Result<Vec<u8>, Error> {
+  base64::decode_config(encoded.trim_end_matches('='), base64::URL_SAFE_NO_PAD)
+    .map_err(|_| Error::InvalidPasskey("Invalid passkey - Malformed base64url"))
+}
+
+/// Converts a COSE EC2 P-256 key to an uncompressed point.
+fn public_key(key: &Cbor) -> Result<Vec<u8>, Error> {
+  let field = |label| key.get(&Cbor::Integer(label));
+
+  match (field(1), field(3), field(-1), field(-2), field(-3)) {
+    (
+      Some(Cbor::Integer(2)),
+      Some(Cbor::Integer(ES256)),
+      Some(Cbor::Integer(1)),
+      Some(Cbor::Bytes(x)),
+      Some(Cbor::Bytes(y)),
+    ) if x.len() == 32 && y.len() == 32 => {
+      let mut point = vec![0x04];
+      point.extend_from_slice(x);
+      point.extend_from_slice(y);
+
+      Ok(point)
+    }
+    _ => Err(Error::InvalidPasskey(
+      "Invalid passkey - Unsupported key, only ES256 is accepted",
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn webauthn() -> Webauthn {
+    Webauthn {
+      rp_id: "localhost".to_string(),
+      rp_name: "Graphy".to_string(),
+      origin: "http://localhost:8000".to_string(),
+    }
+  }
+
+  fn authenticator_data(rp_id: &str, flags: u8) -> Vec<u8> {
+    let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes())
+      .as_ref()
+      .to_vec();
+    data.push(flags);
+    data.extend_from_slice(&7u32.to_be_bytes());
+    data
+  }
+
+  #[test]
+  fn test_client_data() {
+    let client_data = |kind: &str, origin: &str| {
+      json!({ "type": kind, "challenge": "AQID", "origin": origin }).to_string()
+    };
+
+    assert_eq!(
+      webauthn()
+        .client_data(
+          client_data("webauthn.get", "http://localhost:8000").as_bytes(),
+          "webauthn.get"
+        )
+        .unwrap(),
+      vec![1, 2, 3]
+    );
+    assert!(webauthn()
+      .client_data(
+        client_data("webauthn.create", "http://localhost:8000").as_bytes(),
+        "webauthn.get"
+      )
+      .is_err());
+    assert!(webauthn()
+      .client_data(
+        client_data("webauthn.get", "https://evil.test").as_bytes(),
+        "webauthn.get"
+      )
+      .is_err());
+  }
+
+  #[test]
+  fn test_authenticator_data() {
+    let flags = USER_PRESENT | USER_VERIFIED;
+    let data = authenticator_data("localhost", flags);
+    let (_, sign_count, rest) = webauthn().authenticator_data(&data).unwrap();
+
+    assert_eq!(sign_count, 7);
+    assert!(rest.is_empty());
+    assert!(webauthn()
+      .authenticator_data(&authenticator_data("evil.test", flags))
+      .is_err());
+    assert!(webauthn()
+      .authenticator_data(&authenticator_data("localhost", USER_PRESENT))
+      .is_err());
+  }
+
+  #[test]
+  fn test_public_key() {
+    let key = Cbor::Map(vec![
+      (Cbor::Integer(1), Cbor::Integer(2)),
+      (Cbor::Integer(3), Cbor::Integer(ES256)),
+      (Cbor::Integer(-1), Cbor::Integer(1)),
+      (Cbor::Integer(-2), Cbor::Bytes(vec![1; 32])),
+      (Cbor::Integer(-3), Cbor::Bytes(vec![2; 32])),
+    ]);
+    let point = public_key(&key).unwrap();
+
+    assert_eq!(point.len(), 65);
+    assert_eq!(point[0], 0x04);
+    assert!(matches!(
+      public_key(&Cbor::Map(vec![(Cbor::Integer(1), Cbor::Integer(1))])),
+      Err(Error::InvalidPasskey(_))
+    ));
+  }
+}
@@ -1,6 +1,19 @@
-use api::config::Config;
+use api::config::{Config, OidcProvider};
 use api::db::Db;
+use api::webauthn::encode;
+use jwt::{Algorithm, Header};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{ECDSAKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+use serde_json::Value;
+use std::collections::HashMap;
 use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use untrusted::Input;
+use url::Url;
 
 pub fn config() -> Config {
   // TODO: Temporary fix. Try to pass these as `args`
@@ -40,3 +53,315 @@ pub fn db(config: &Config) -> Db {
   )
   .unwrap()
 }
+
+/// Local OpenID Connect provider, signing ID tokens
+/// with the fixture key, for testing logins.
+pub struct OidcMock {
+  pub issuer: String,
+  grants: Arc<Mutex<HashMap<String, Grant>>>,
+}
+
+/// What a user agreed to at the authorization
+/// endpoint, redeemed with its code.
+struct Grant {
+  claims: Value,
+  redirect_uri: String,
+  code_challenge: String,
+}
+
+impl OidcMock {
+  pub const CLIENT_ID: &'static str = "graphy";
+  pub const CLIENT_SECRET: &'static str = "secret";
+
+  pub fn start() -> OidcMock {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let grants = Arc::new(Mutex::new(HashMap::new()));
+    let mock = OidcMock {
+      issuer: issuer.clone(),
+      grants: grants.clone(),
+    };
+
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        respond(&issuer, &grants, stream.unwrap());
+      }
+    });
+
+    mock
+  }
+
+  pub fn provider(&self, name: &str) -> OidcProvider {
+    OidcProvider {
+      name: name.to_string(),
+      issuer: self.issuer.clone(),
+      client_id: OidcMock::CLIENT_ID.to_string(),
+      client_secret: Some(OidcMock::CLIENT_SECRET.to_string()),
+      redirect_uri: "http://localhost:8000/callback".to_string(),
+    }
+  }
+
+  /// Logs `sub` in at the authorization `url`,
+  /// returning the state and code they'd be
+  /// redirected back with.
+  pub fn authorize(&self, url: &str, sub: &str, email: &str) -> (String, String) {
+    let url = Url::parse(url).unwrap();
+    let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    let code = encode(uuid::Uuid::new_v4().as_bytes());
+
+    assert_eq!(url.path(), "/authorize");
+    assert_eq!(params["response_type"], "code");
+    assert_eq!(params["client_id"], OidcMock::CLIENT_ID);
+    assert_eq!(params["code_challenge_method"], "S256");
+
+    self.grants.lock().unwrap().insert(
+      code.clone(),
+      Grant {
+        claims: json!({
+          "iss": self.issuer,
+          "aud": OidcMock::CLIENT_ID,
+          "sub": sub,
+          "email": email,
+          "email_verified": true,
+          "nonce": params["nonce"],
+          "exp": chrono::Utc::now().timestamp() + 300,
+        }),
+        redirect_uri: params["redirect_uri"].clone(),
+        code_challenge: params["code_challenge"].clone(),
+      },
+    );
+
+    (params["state"].clone(), code)
+  }
+}
+
+fn respond(issuer: &str, grants: &Mutex<HashMap<String, Grant>>, mut stream: TcpStream) {
+  let mut request = Vec::new();
+  let mut buffer = [0; 4096];
+
+  // Read the head, then as much body as it says.
+  let split = loop {
+    let read = stream.read(&mut buffer).unwrap();
+    request.extend_from_slice(&buffer[..read]);
+
+    if read == 0 {
+      return;
+    }
+
+    if let Some(split) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+      break split + 4;
+    }
+  };
+  let head = String::from_utf8_lossy(&request[..split]).to_string();
+  let length = head
+    .lines()
+    .find(|line| line.to_lowercase().starts_with("content-length:"))
+    .map_or(0, |line| line[15..].trim().parse().unwrap());
+
+  while request.len() < split + length {
+    let read = stream.read(&mut buffer).unwrap();
+    request.extend_from_slice(&buffer[..read]);
+  }
+
+  let path = head.split_whitespace().nth(1).unwrap().to_string();
+  let form: HashMap<String, String> = url::form_urlencoded::parse(&request[split..])
+    .into_owned()
+    .collect();
+  let (status, body) = match path.as_str() {
+    "/.well-known/openid-configuration" => (
+      200,
+      json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "jwks_uri": format!("{}/jwks", issuer),
+      }),
+    ),
+    "/jwks" => (
+      200,
+      serde_json::from_str(&fs::read_to_string("tests/fixtures/oidc/jwks.json").unwrap()).unwrap(),
+    ),
+    "/token" => token(grants, &form),
+    _ => (404, json!({ "error": "not_found" })),
+  };
+  let body = body.to_string();
+
+  stream
+    .write_all(
+      format!(
+        "HTTP/1.0 {} -\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+      )
+      .as_bytes(),
+    )
+    .unwrap();
+}
+
+/// Redeems a code once, checking the client and
+/// PKCE verifier.
+fn token(grants: &Mutex<HashMap<String, Grant>>, form: &HashMap<String, String>) -> (u16, Value) {
+  let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+  let grant = match grants.lock().unwrap().remove(field("code")) {
+    Some(grant) => grant,
+    None => return (400, json!({ "error": "invalid_grant" })),
+  };
+  let code_challenge =
+    encode(digest::digest(&digest::SHA256, field("code_verifier").as_bytes()).as_ref());
+
+  if field("grant_type") != "authorization_code"
+    || field("client_id") != OidcMock::CLIENT_ID
+    || field("client_secret") != OidcMock::CLIENT_SECRET
+    || field("redirect_uri") != grant.redirect_uri
+    || code_challenge != grant.code_challenge
+  {
+    return (400, json!({ "error": "invalid_grant" }));
+  }
+
+  let header = Header {
+    kid: Some("test".to_string()),
+    ..Header::new(Algorithm::RS256)
+  };
+  let key = fs::read("tests/fixtures/oidc/key.der").unwrap();
+  let id_token = jwt::encode(&header, &grant.claims, &key).unwrap();
+
+  (200, json!({ "token_type": "Bearer", "id_token": id_token }))
+}
+
+/// Software WebAuthn authenticator holding a single
+/// ES256 passkey, for testing ceremonies.
+pub struct Authenticator {
+  key_pair: ECDSAKeyPair,
+  public_key: Vec<u8>,
+  credential_id: Vec<u8>,
+  user_handle: String,
+  origin: String,
+  /// Incremented before each assertion.
+  pub sign_count: u32,
+}
+
+impl Authenticator {
+  pub fn new(origin: &str) -> Authenticator {
+    let rng = SystemRandom::new();
+    let pkcs8 = ECDSAKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+    let key_pair =
+      ECDSAKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, Input::from(pkcs8.as_ref()))
+        .unwrap();
+    let mut credential_id = vec![0; 16];
+    rng.fill(&mut credential_id).unwrap();
+
+    Authenticator {
+      key_pair,
+      // The uncompressed public key ends the document.
+      public_key: pkcs8.as_ref()[pkcs8.as_ref().len() - 65..].to_vec(),
+      credential_id,
+      user_handle: String::new(),
+      origin: origin.to_string(),
+      sign_count: 0,
+    }
+  }
+
+  /// Answers `navigator.credentials.create()` `options`.
+  pub fn register(&mut self, options: &str) -> Value {
+    let options: Value = serde_json::from_str(options).unwrap();
+    let client_data = self.client_data("webauthn.create", &options["challenge"]);
+    let mut auth_data = self.authenticator_data(options["rp"]["id"].as_str().unwrap(), 0x45);
+    auth_data.extend_from_slice(&[0; 16]);
+    auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+    auth_data.extend_from_slice(&self.credential_id);
+    auth_data.extend_from_slice(&self.cose_key());
+
+    let mut attestation_object = cbor_head(5, 3);
+    attestation_object.extend(cbor_text("fmt"));
+    attestation_object.extend(cbor_text("none"));
+    attestation_object.extend(cbor_text("attStmt"));
+    attestation_object.extend(cbor_head(5, 0));
+    attestation_object.extend(cbor_text("authData"));
+    attestation_object.extend(cbor_bytes(&auth_data));
+    self.user_handle = options["user"]["id"].as_str().unwrap().to_string();
+
+    json!({
+      "id": encode(&self.credential_id),
+      "clientDataJson": encode(client_data.as_bytes()),
+      "attestationObject": encode(&attestation_object),
+    })
+  }
+
+  /// Answers `navigator.credentials.get()` `options`.
+  pub fn assert(&mut self, options: &str) -> Value {
+    let options: Value = serde_json::from_str(options).unwrap();
+    let client_data = self.client_data("webauthn.get", &options["challenge"]);
+    self.sign_count += 1;
+    let auth_data = self.authenticator_data(options["rpId"].as_str().unwrap(), 0x05);
+    let mut signed = auth_data.clone();
+    signed.extend_from_slice(digest::digest(&digest::SHA256, client_data.as_bytes()).as_ref());
+    let signature = self
+      .key_pair
+      .sign(Input::from(&signed), &SystemRandom::new())
+      .unwrap();
+
+    json!({
+      "id": encode(&self.credential_id),
+      "clientDataJson": encode(client_data.as_bytes()),
+      "authenticatorData": encode(&auth_data),
+      "signature": encode(signature.as_ref()),
+      "userHandle": self.user_handle,
+    })
+  }
+
+  fn client_data(&self, kind: &str, challenge: &Value) -> String {
+    json!({
+      "type": kind,
+      "challenge": challenge,
+      "origin": self.origin,
+      "crossOrigin": false,
+    })
+    .to_string()
+  }
+
+  /// RP ID hash, `flags` (user present, verified and
+  /// attested credential data) and sign count.
+  fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+    let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes())
+      .as_ref()
+      .to_vec();
+    data.push(flags);
+    data.extend_from_slice(&self.sign_count.to_be_bytes());
+    data
+  }
+
+  /// COSE EC2 key, `{1: 2, 3: -7, -1: 1, -2: x, -3: y}`.
+  fn cose_key(&self) -> Vec<u8> {
+    let mut key = cbor_head(5, 5);
+    key.extend_from_slice(&[0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21]);
+    key.extend(cbor_bytes(&self.public_key[1..33]));
+    key.push(0x22);
+    key.extend(cbor_bytes(&self.public_key[33..]));
+    key
+  }
+}
+
+fn cbor_head(major: u8, length: usize) -> Vec<u8> {
+  if length < 24 {
+    vec![major << 5 | length as u8]
+  } else if length < 256 {
+    vec![major << 5 | 24, length as u8]
+  } else {
+    let mut head = vec![major << 5 | 25];
+    head.extend_from_slice(&(length as u16).to_be_bytes());
+    head
+  }
+}
+
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+  let mut encoded = cbor_head(2, bytes.len());
+  encoded.extend_from_slice(bytes);
+  encoded
+}
+
+fn cbor_text(text: &str) -> Vec<u8> {
+  let mut encoded = cbor_head(3, text.len());
+  encoded.extend_from_slice(text.as_bytes());
+  encoded
+}
@@ -1,11 +1,25 @@
 extern crate api;
+extern crate chrono;
 extern crate diesel;
+extern crate jsonwebtoken as jwt;
+extern crate ring;
+#[macro_use]
 extern crate serde_json;
+extern crate serde_urlencoded;
+extern crate untrusted;
+extern crate url;
 extern crate uuid;
 
-use api::{hasher::Hasher, models::user::User, models::user::UserCreate, tokeniser::Tokeniser};
+use api::{
+  config::Ide, config::Introspection, config::TraceExporter, hasher::Hasher, introspection,
+  introspection::SchemaFormat, models::user::User, models::user::UserCreate, retention,
+  tokeniser::Tokeniser, totp,
+};
+use chrono::Utc;
 use serde_json::Value;
+use std::fs;
 use std::str;
+use std::time::Duration;
 use uuid::Uuid;
 mod common;
 
@@ -19,6 +33,171 @@ fn it_graphiql() {
   assert_eq!(res.status(), 200);
 }
 
+#[test]
+fn it_playground() {
+  let mut config = common::config();
+  config.ide = Ide::Playground;
+  config.ide_path = "/playground".to_string();
+  let server = common::server(&config);
+
+  let res = warp::test::request()
+    .method("GET")
+    .path("/playground")
+    .reply(&server);
+  let body = str::from_utf8(res.body()).unwrap();
+
+  assert_eq!(res.status(), 200);
+  assert!(body.contains("GraphQLPlayground"));
+  assert_eq!(
+    warp::test::request()
+      .method("GET")
+      .path("/")
+      .reply(&server)
+      .status(),
+    404
+  );
+}
+
+#[test]
+fn it_ide_disabled() {
+  let mut config = common::config();
+  config.ide = Ide::Disabled;
+  let server = common::server(&config);
+
+  let res = warp::test::request().method("GET").path("/").reply(&server);
+
+  assert_eq!(res.status(), 404);
+}
+
+#[test]
+fn it_healthz() {
+  let config = common::config();
+  let server = common::server(&config);
+
+  let res = warp::test::request()
+    .method("GET")
+    .path("/healthz")
+    .reply(&server);
+  let body: Value = serde_json::from_slice(res.body()).unwrap();
+
+  assert_eq!(res.status(), 200);
+  assert_eq!(body["status"], "ok");
+}
+
+#[test]
+fn it_readyz() {
+  let config = common::config();
+  let server = common::server(&config);
+
+  let res = warp::test::request()
+    .method("GET")
+    .path("/readyz")
+    .reply(&server);
+  let body: Value = serde_json::from_slice(res.body()).unwrap();
+
+  assert_eq!(res.status(), 200);
+  assert_eq!(body["status"], "ok");
+  for check in &["database", "migrations", "signing_keys"] {
+    assert_eq!(body["checks"][check]["status"], "ok");
+  }
+}
+
+#[test]
+fn it_request_id() {
+  let config = common::config();
+  let server = common::server(&config);
+
+  let propagated = warp::test::request()
+    .header("x-request-id", "gateway-1234")
+    .header("content-type", "application/json")
+    .method("POST")
+    .path("/graphql")
+    .body(r#"{ "query": "{ __type(name: \"User\") { name } }" }"#)
+    .reply(&server);
+  let generated = warp::test::request()
+    .method("GET")
+    .path("/not-found")
+    .reply(&server);
+  let request_id = generated.headers()["x-request-id"].to_str().unwrap();
+
+  assert_eq!(propagated.headers()["x-request-id"], "gateway-1234");
+  assert_eq!(generated.status(), 404);
+  assert!(Uuid::parse_str(request_id).is_ok());
+}
+
+#[test]
+fn it_introspection_disabled() {
+  let mut config = common::config();
+  config.introspection = Introspection::Disabled;
+  let server = common::server(&config);
+
+  let res = warp::test::request()
+    .header("content-type", "application/json")
+    .method("POST")
+    .path("/graphql")
+    .body(r#"{ "query": "{ __schema { queryType { name } } }" }"#)
+    .reply(&server);
+  let json: Value = serde_json::from_str(str::from_utf8(res.body()).unwrap()).unwrap();
+  let error = &json["errors"][0]["message"].as_str().unwrap();
+
+  assert_eq!(res.status(), 400);
+  assert!(json["data"].is_null());
+  assert_eq!(error, &"Introspection is disabled");
+}
+
+#[test]
+fn it_introspection_unauthenticated() {
+  let mut config = common::config();
+  config.introspection = Introspection::Authenticated;
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let query = r#"{ "query": "{ __type(name: \"User\") { name } }" }"#;
+  let id = Uuid::new_v4();
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: format!("{}-test@test.com", id),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+
+  let unauthenticated = warp::test::request()
+    .header("content-type", "application/json")
+    .method("POST")
+    .path("/graphql")
+    .body(query)
+    .reply(&server);
+  let authenticated = warp::test::request()
+    .header("content-type", "application/json")
+    .header("authorization", token)
+    .method("POST")
+    .path("/graphql")
+    .body(query)
+    .reply(&server);
+  let json: Value = serde_json::from_str(str::from_utf8(authenticated.body()).unwrap()).unwrap();
+
+  assert_eq!(unauthenticated.status(), 400);
+  assert_eq!(authenticated.status(), 200);
+  assert_eq!(json["data"]["__type"]["name"], "User");
+}
+
+#[test]
+fn it_schema_up_to_date() {
+  let schema = introspection::export(SchemaFormat::Sdl).unwrap();
+
+  assert!(
+    schema == include_str!("../schema.graphql"),
+    "`schema.graphql` is out of date, run `cargo run -- schema > schema.graphql`"
+  );
+}
+
 #[test]
 fn it_create_user() {
   let config = common::config();
@@ -66,7 +245,7 @@ fn it_read_user() {
 
   let config = common::config();
   let db = common::db(&config);
-  let hasher = Hasher::new(&config.hash_salt);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
   let tokeniser = Tokeniser::new(&config.token_secret);
   let server = common::server(&config);
   let token = User::create(
@@ -118,7 +297,7 @@ fn it_read_user_unauthenticated() {
 
   let config = common::config();
   let db = common::db(&config);
-  let hasher = Hasher::new(&config.hash_salt);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
   let tokeniser = Tokeniser::new(&config.token_secret);
   let server = common::server(&config);
   User::create(
@@ -158,6 +337,144 @@ fn it_read_user_unauthenticated() {
   assert_eq!(error, &"Unauthorised - Must be logged in to view users");
 }
 
+#[test]
+fn it_read_user_get() {
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+  let password = "test";
+  let name = "Tester";
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: password.to_string(),
+      name: Some(name.to_string()),
+    },
+  )
+  .unwrap();
+  let params = serde_urlencoded::to_string(&[
+    (
+      "query",
+      "query ($userId: Uuid!) { User(userId: $userId) { id name email } }".to_string(),
+    ),
+    ("variables", format!(r#"{{ "userId": "{}" }}"#, id)),
+  ])
+  .unwrap();
+
+  let res = warp::test::request()
+    .header("authorization", token)
+    .method("GET")
+    .path(&format!("/graphql?{}", params))
+    .reply(&server);
+  let json: Value = serde_json::from_str(str::from_utf8(res.body()).unwrap()).unwrap();
+  let result_id = Uuid::parse_str(json["data"]["User"]["id"].as_str().unwrap()).unwrap();
+  let result_email = json["data"]["User"]["email"].as_str().unwrap();
+
+  assert_eq!(res.status(), 200);
+  assert_eq!(result_id, id);
+  assert_eq!(result_email, email);
+}
+
+#[test]
+fn it_refuse_mutation_get() {
+  let config = common::config();
+  let server = common::server(&config);
+  let params = serde_urlencoded::to_string([
+    (
+      "query",
+      "mutation Delete { deleteUser(userId: \"00000000-0000-0000-0000-000000000001\") }",
+    ),
+    ("operationName", "Delete"),
+  ])
+  .unwrap();
+
+  let res = warp::test::request()
+    .method("GET")
+    .path(&format!("/graphql?{}", params))
+    .reply(&server);
+  let json: Value = serde_json::from_str(str::from_utf8(res.body()).unwrap()).unwrap();
+  let error = &json["errors"][0]["message"].as_str().unwrap();
+
+  assert_eq!(res.status(), 405);
+  assert_eq!(res.headers()["allow"], "POST");
+  assert_eq!(
+    error,
+    &"Only queries can be sent with GET, use POST instead"
+  );
+}
+
+#[test]
+fn it_batch_operations() {
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+  let password = "test";
+  let name = "Tester";
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: password.to_string(),
+      name: Some(name.to_string()),
+    },
+  )
+  .unwrap();
+
+  let res = warp::test::request()
+    .header("content-type", "application/json")
+    .method("POST")
+    .path("/graphql")
+    .body(format!(
+      r#"
+      [
+        {{
+          "query": "mutation ($user: UserLogin!) {{\n  login(user: $user)\n}}\n",
+          "variables": {{
+            "user": {{
+              "email": "{}",
+              "password": "{}"
+            }}
+          }}
+        }},
+        {{
+          "query": "query ($userId: Uuid!) {{\n  User(userId: $userId) {{\n    id\n  }}\n}}\n",
+          "variables": {{
+            "userId": "{}"
+          }}
+        }}
+      ]
+      "#,
+      email, password, id
+    ))
+    .reply(&server);
+  let json: Value = serde_json::from_str(str::from_utf8(res.body()).unwrap()).unwrap();
+  let token = &json[0]["data"]["login"].as_str().unwrap();
+  let claims = (tokeniser.verify)(token).unwrap();
+  let error = &json[1]["errors"][0]["message"].as_str().unwrap();
+
+  assert_eq!(res.status(), 200);
+  assert_eq!(json.as_array().unwrap().len(), 2);
+  assert_eq!(claims.sub, id);
+  assert_eq!(error, &"Unauthorised - Must be logged in to view users");
+}
+
 // TODO: Finish integration tests
 
 // #[test]
@@ -190,7 +507,7 @@ fn it_login_user() {
 
   let config = common::config();
   let db = common::db(&config);
-  let hasher = Hasher::new(&config.hash_salt);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
   let tokeniser = Tokeniser::new(&config.token_secret);
   let server = common::server(&config);
   User::create(
@@ -234,5 +551,1700 @@ fn it_login_user() {
   assert_eq!(claims.sub, id);
 }
 
+#[test]
+fn it_login_invalid_credentials() {
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+
+  let login = |email: &str, password: &str| {
+    warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(format!(
+        r#"{{ "query": "mutation {{ login(user: {{ email: \"{}\", password: \"{}\" }}) }}" }}"#,
+        email, password
+      ))
+      .reply(&server)
+  };
+  let wrong_password = login(&email, "wrong");
+  let unknown_email = login(&format!("{}-unknown@test.com", id), "wrong");
+  let long_email = login(&format!("{}@test.com", "a".repeat(300)), "wrong");
+  let json: Value = serde_json::from_slice(wrong_password.body()).unwrap();
+
+  assert_eq!(wrong_password.status(), unknown_email.status());
+  assert_eq!(wrong_password.body(), unknown_email.body());
+  assert_eq!(wrong_password.body(), long_email.body());
+  assert_eq!(
+    json["errors"][0]["extensions"]["code"],
+    "INVALID_CREDENTIALS"
+  );
+}
+
+#[test]
+fn it_metrics() {
+  let config = common::config();
+  let server = common::server(&config);
+
+  let res = warp::test::request()
+    .header("content-type", "application/json")
+    .method("POST")
+    .path("/graphql")
+    .body(format!(
+      r#"{{ "query": "mutation Login {{ login(user: {{ email: \"{}@test.com\", password: \"test\" }}) }}" }}"#,
+      Uuid::new_v4()
+    ))
+    .reply(&server);
+  let json: Value = serde_json::from_slice(res.body()).unwrap();
+
+  assert_eq!(
+    json["errors"][0]["extensions"]["code"],
+    "INVALID_CREDENTIALS"
+  );
+
+  let res = warp::test::request()
+    .method("GET")
+    .path("/metrics")
+    .reply(&server);
+  let body = str::from_utf8(res.body()).unwrap();
+
+  assert_eq!(res.status(), 200);
+  for metric in &[
+    "http_requests_total{status=\"200\"}",
+    "graphql_operations_total{type=\"mutation\"}",
+    "graphql_resolver_errors_total{code=\"INVALID_CREDENTIALS\"}",
+    "logins_total{result=\"failure\"}",
+    "db_pool_connections{state=\"idle\"}",
+    "db_pool_wait_seconds_count",
+  ] {
+    assert!(body.contains(metric), "missing {}", metric);
+  }
+}
+
 // #[test]
 // fn it_login_user_unauthenticated() { assert_eq!(false, true); }
+
+#[test]
+fn it_trace() {
+  let mut config = common::config();
+  let path = std::env::temp_dir().join(format!("traces-{}.jsonl", Uuid::new_v4()));
+  config.trace_exporter = TraceExporter::File(path.clone());
+  api::trace::init(&config.trace_exporter).unwrap();
+  let server = common::server(&config);
+
+  let res = warp::test::request()
+    .header(
+      "traceparent",
+      "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+    )
+    .header("content-type", "application/json")
+    .method("POST")
+    .path("/graphql")
+    .body(format!(
+      r#"{{ "query": "mutation Login {{ login(user: {{ email: \"{}@test.com\", password: \"test\" }}) }}" }}"#,
+      Uuid::new_v4()
+    ))
+    .reply(&server);
+  let traceresponse = res.headers()["traceresponse"].to_str().unwrap();
+  let spans = fs::read_to_string(&path)
+    .unwrap()
+    .lines()
+    .map(|line| serde_json::from_str::<Value>(line).unwrap())
+    .filter(|span| span["traceId"] == "4bf92f3577b34da6a3ce929d0e0e4736")
+    .collect::<Vec<_>>();
+  let names = spans
+    .iter()
+    .map(|span| span["name"].as_str().unwrap())
+    .collect::<Vec<_>>();
+  let span = |name: &str| spans.iter().find(|span| span["name"] == name).unwrap();
+  let request = span("POST /graphql");
+  let _ = fs::remove_file(&path);
+
+  assert!(traceresponse.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+  assert_eq!(request["parentSpanId"], "00f067aa0ba902b7");
+  assert_eq!(request["kind"], 2);
+  assert!(traceresponse.contains(request["spanId"].as_str().unwrap()));
+  for name in &["mutation Login", "Mutation.login", "SELECT"] {
+    assert!(names.contains(name), "missing span {}", name);
+  }
+  assert_eq!(span("mutation Login")["parentSpanId"], request["spanId"]);
+  assert_eq!(
+    span("SELECT")["parentSpanId"],
+    span("Mutation.login")["spanId"]
+  );
+}
+
+#[test]
+fn it_login_lockout() {
+  let mut config = common::config();
+  config.lockout_threshold = 2;
+  config.rate_limit_account = 0;
+  let server = common::server(&config);
+  let email = format!("{}@test.com", Uuid::new_v4());
+
+  let login = || {
+    let res = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(format!(
+        r#"{{ "query": "mutation {{ login(user: {{ email: \"{}\", password: \"test\" }}) }}" }}"#,
+        email
+      ))
+      .reply(&server);
+
+    serde_json::from_slice::<Value>(res.body()).unwrap()["errors"][0]["extensions"].clone()
+  };
+
+  assert_eq!(login()["code"], "INVALID_CREDENTIALS");
+  assert_eq!(login()["code"], "INVALID_CREDENTIALS");
+
+  let locked = login();
+
+  assert_eq!(locked["code"], "ACCOUNT_LOCKED");
+  assert!(locked["retryAfter"].as_i64().unwrap() > 0);
+  assert!(locked["retryAfter"].as_i64().unwrap() <= 60);
+}
+
+#[test]
+fn it_rate_limit_ip() {
+  let mut config = common::config();
+  config.rate_limit_ip = 2;
+  config.trust_forwarded_for = true;
+  let server = common::server(&config);
+  let id = Uuid::new_v4();
+  let ip = format!(
+    "10.{}.{}.{}",
+    id.as_bytes()[0],
+    id.as_bytes()[1],
+    id.as_bytes()[2]
+  );
+
+  let login = || {
+    let res = warp::test::request()
+      .header("x-forwarded-for", format!("203.0.113.1, {}", ip))
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(format!(
+        r#"{{ "query": "mutation {{ login(user: {{ email: \"{}@test.com\", password: \"test\" }}) }}" }}"#,
+        Uuid::new_v4()
+      ))
+      .reply(&server);
+
+    serde_json::from_slice::<Value>(res.body()).unwrap()["errors"][0]["extensions"].clone()
+  };
+
+  assert_eq!(login()["code"], "INVALID_CREDENTIALS");
+  assert_eq!(login()["code"], "INVALID_CREDENTIALS");
+  assert_eq!(login()["code"], "RATE_LIMITED");
+}
+
+#[test]
+fn it_mfa() {
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+
+  let mutation = |auth: Option<&str>, mutation: String| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": format!("mutation {{ {} }}", mutation) }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    serde_json::from_slice::<Value>(req.reply(&server).body()).unwrap()
+  };
+  let login = || {
+    mutation(
+      None,
+      format!(r#"login(user: {{ email: "{}", password: "test" }})"#, email),
+    )
+  };
+
+  let uri = mutation(Some(&token), "enrolMfa".to_string());
+  let uri = uri["data"]["enrolMfa"].as_str().unwrap();
+  let secret = uri
+    .split("secret=")
+    .nth(1)
+    .unwrap()
+    .split('&')
+    .next()
+    .unwrap();
+  let code = format!(
+    "{:06}",
+    totp::code(&totp::decode(secret).unwrap(), Utc::now().timestamp() / 30)
+  );
+
+  assert!(uri.starts_with("otpauth://totp/Graphy:"));
+  assert!(login()["data"]["login"].is_string());
+
+  let confirmed = mutation(Some(&token), format!(r#"confirmMfa(code: "{}")"#, code));
+  let recovery_codes = confirmed["data"]["confirmMfa"].as_array().unwrap();
+  let challenge = login();
+  let extensions = &challenge["errors"][0]["extensions"];
+  let mfa_token = extensions["mfaToken"].as_str().unwrap();
+  let verify = |code: &str| {
+    mutation(
+      None,
+      format!(r#"verifyMfa(token: "{}", code: "{}")"#, mfa_token, code),
+    )
+  };
+
+  assert_eq!(recovery_codes.len(), 10);
+  assert_eq!(extensions["code"], "MFA_REQUIRED");
+
+  let replayed = verify(&code);
+  let recovered = verify(recovery_codes[0].as_str().unwrap());
+  let reused = verify(recovery_codes[0].as_str().unwrap());
+
+  assert_eq!(
+    replayed["errors"][0]["extensions"]["code"],
+    "INVALID_MFA_CODE"
+  );
+  assert!(recovered["data"]["verifyMfa"].is_string());
+  assert_eq!(
+    reused["errors"][0]["extensions"]["code"],
+    "INVALID_MFA_CODE"
+  );
+
+  let disabled = mutation(
+    Some(&token),
+    format!(
+      r#"disableMfa(code: "{}")"#,
+      recovery_codes[1].as_str().unwrap().to_uppercase()
+    ),
+  );
+
+  assert_eq!(disabled["data"]["disableMfa"], true);
+  assert!(login()["data"]["login"].is_string());
+}
+
+#[test]
+fn it_passkey() {
+  let id = Uuid::new_v4();
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: format!("{}-test@test.com", id),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+  let mut authenticator = common::Authenticator::new(&config.webauthn_origin);
+
+  let request = |auth: Option<&str>, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    serde_json::from_slice::<Value>(req.reply(&server).body()).unwrap()
+  };
+  let begin_login = || {
+    request(None, "mutation { beginPasskeyLogin }", Value::Null)["data"]["beginPasskeyLogin"]
+      .as_str()
+      .unwrap()
+      .to_string()
+  };
+  let finish_login = |credential: &Value| {
+    request(
+      None,
+      "mutation ($credential: PasskeyAssertion!) { finishPasskeyLogin(credential: $credential) }",
+      json!({ "credential": credential }),
+    )
+  };
+
+  let options = request(
+    Some(&token),
+    "mutation { beginPasskeyRegistration }",
+    Value::Null,
+  );
+  let credential = authenticator.register(
+    options["data"]["beginPasskeyRegistration"]
+      .as_str()
+      .unwrap(),
+  );
+  let registered = request(
+    Some(&token),
+    "mutation ($credential: PasskeyRegistration!) { finishPasskeyRegistration(credential: $credential) }",
+    json!({ "credential": credential }),
+  );
+
+  assert_eq!(registered["data"]["finishPasskeyRegistration"], true);
+
+  let assertion = authenticator.assert(&begin_login());
+  let logged_in = finish_login(&assertion);
+  let passkey_token = logged_in["data"]["finishPasskeyLogin"].as_str().unwrap();
+  let replayed = finish_login(&assertion);
+
+  assert_eq!((tokeniser.verify)(passkey_token).unwrap().sub, id);
+  assert_eq!(
+    replayed["errors"][0]["extensions"]["code"],
+    "INVALID_PASSKEY"
+  );
+
+  // A cloned authenticator repeats sign counts.
+  authenticator.sign_count = 0;
+  let cloned = finish_login(&authenticator.assert(&begin_login()));
+
+  assert_eq!(cloned["errors"][0]["extensions"]["code"], "INVALID_PASSKEY");
+  assert_eq!(
+    cloned["errors"][0]["message"],
+    "Invalid passkey - Sign count did not increase"
+  );
+}
+
+#[test]
+fn it_oidc() {
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+
+  let mock = common::OidcMock::start();
+  let mut config = common::config();
+  config.oidc_providers = vec![mock.provider("mock")];
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+
+  let request = |auth: Option<&str>, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    serde_json::from_slice::<Value>(req.reply(&server).body()).unwrap()
+  };
+  let begin = |auth: Option<&str>| {
+    request(
+      auth,
+      r#"mutation { beginOidcLogin(provider: "mock") }"#,
+      Value::Null,
+    )["data"]["beginOidcLogin"]
+      .as_str()
+      .unwrap()
+      .to_string()
+  };
+  let finish_as = |auth: Option<&str>, state: &str, code: &str| {
+    request(
+      auth,
+      "mutation ($state: String!, $code: String!) { finishOidcLogin(state: $state, code: $code) }",
+      json!({ "state": state, "code": code }),
+    )
+  };
+  let finish = |state: &str, code: &str| finish_as(None, state, code);
+  let user_id = |response: &Value| {
+    (tokeniser.verify)(response["data"]["finishOidcLogin"].as_str().unwrap())
+      .unwrap()
+      .sub
+  };
+
+  // Unknown identities create a user with their email.
+  let (state, code) = mock.authorize(&begin(None), &format!("{}-a", id), &email);
+  let created = finish(&state, &code);
+  let created_id = user_id(&created);
+
+  let token = created["data"]["finishOidcLogin"].as_str().unwrap();
+  let user = request(
+    Some(token),
+    "query ($userId: Uuid!) { User(userId: $userId) { email } }",
+    json!({ "userId": created_id }),
+  );
+
+  assert_eq!(user["data"]["User"]["email"], email);
+  assert_eq!(
+    finish(&state, &code)["errors"][0]["extensions"]["code"],
+    "INVALID_OIDC_LOGIN"
+  );
+
+  let (state, code) = mock.authorize(&begin(None), &format!("{}-a", id), &email);
+
+  assert_eq!(user_id(&finish(&state, &code)), created_id);
+
+  // Logged in users link identities to themselves.
+  let (state, code) = mock.authorize(&begin(Some(token)), &format!("{}-b", id), "other@test.com");
+
+  assert_eq!(user_id(&finish_as(Some(token), &state, &code)), created_id);
+
+  // Nobody else can finish linking, i.e. from a
+  // link they were sent, logged in or not.
+  let (state, code) = mock.authorize(
+    &begin(None),
+    &format!("{}-d", id),
+    &format!("{}-d@test.com", id),
+  );
+  let other = finish(&state, &code)["data"]["finishOidcLogin"]
+    .as_str()
+    .unwrap()
+    .to_string();
+
+  for auth in &[None, Some(other.as_str())] {
+    let (state, code) = mock.authorize(
+      &begin(Some(token)),
+      &format!("{}-e", id),
+      &format!("{}-e@test.com", id),
+    );
+
+    assert_eq!(
+      finish_as(*auth, &state, &code)["errors"][0]["extensions"]["code"],
+      "UNAUTHORISED"
+    );
+  }
+
+  // So the identity wasn't linked.
+  let (state, code) = mock.authorize(
+    &begin(None),
+    &format!("{}-e", id),
+    &format!("{}-e@test.com", id),
+  );
+
+  assert_ne!(user_id(&finish(&state, &code)), created_id);
+
+  // Identities can't take over existing accounts by email.
+  let (state, code) = mock.authorize(&begin(None), &format!("{}-c", id), &email);
+
+  assert_eq!(
+    finish(&state, &code)["errors"][0]["extensions"]["code"],
+    "EMAIL_TAKEN"
+  );
+
+  let unknown = request(
+    None,
+    r#"mutation { beginOidcLogin(provider: "other") }"#,
+    Value::Null,
+  );
+
+  assert!(unknown["errors"][0]["message"]
+    .as_str()
+    .unwrap()
+    .starts_with("Unknown OIDC provider"));
+}
+
+#[test]
+fn it_api_token() {
+  let id = Uuid::new_v4();
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: format!("{}-test@test.com", id),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+
+  let request = |auth: &str, query: &str, variables: Value| {
+    warp::test::request()
+      .header("content-type", "application/json")
+      .header("authorization", auth)
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string())
+      .reply(&server)
+  };
+  let list = |auth: &str| {
+    let res = request(
+      auth,
+      "{ listApiTokens { id name scopes lastUsedAt } }",
+      Value::Null,
+    );
+
+    serde_json::from_slice::<Value>(res.body()).unwrap()["data"]["listApiTokens"].clone()
+  };
+  let create = |expires_at: &str| {
+    let res = request(
+      &token,
+      "mutation ($apiToken: ApiTokenCreate!) { createApiToken(apiToken: $apiToken) { token apiToken { id } } }",
+      json!({ "apiToken": { "name": "CI", "scopes": ["users:read", "credentials:read"], "expiresAt": expires_at } }),
+    );
+
+    serde_json::from_slice::<Value>(res.body()).unwrap()
+  };
+  let read_user = |auth: &str| {
+    request(
+      auth,
+      "query ($userId: Uuid!) { User(userId: $userId) { id } }",
+      json!({ "userId": id }),
+    )
+  };
+
+  let created = create(&(Utc::now() + chrono::Duration::days(30)).to_rfc3339());
+  let api_token = created["data"]["createApiToken"]["token"].as_str().unwrap();
+  let api_token_id = &created["data"]["createApiToken"]["apiToken"]["id"];
+
+  assert!(api_token.starts_with("gph_"));
+  assert!(list(&token)[0]["lastUsedAt"].is_null());
+
+  let read = read_user(api_token);
+  let read: Value = serde_json::from_slice(read.body()).unwrap();
+
+  assert_eq!(read["data"]["User"]["id"], json!(id));
+  assert_eq!(list(api_token)[0]["id"], *api_token_id);
+  assert_eq!(list(api_token)[0]["name"], "CI");
+  assert_eq!(
+    list(api_token)[0]["scopes"],
+    json!(["users:read", "credentials:read"])
+  );
+  assert!(list(api_token)[0]["lastUsedAt"].is_string());
+
+  let expired = create(&(Utc::now() - chrono::Duration::days(1)).to_rfc3339());
+
+  assert_eq!(expired["errors"][0]["extensions"]["code"], "BAD_REQUEST");
+
+  let revoked = request(
+    &token,
+    "mutation ($apiTokenId: Uuid!) { revokeApiToken(apiTokenId: $apiTokenId) }",
+    json!({ "apiTokenId": api_token_id }),
+  );
+  let revoked: Value = serde_json::from_slice(revoked.body()).unwrap();
+
+  assert_eq!(revoked["data"]["revokeApiToken"], true);
+  assert!(read_user(api_token).status().is_client_error());
+  assert!(read_user("gph_unknown").status().is_client_error());
+}
+
+#[test]
+fn it_scopes() {
+  let id = Uuid::new_v4();
+
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: format!("{}-test@test.com", id),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+
+  let request = |auth: &str, query: &str, variables: Value| {
+    let res = warp::test::request()
+      .header("content-type", "application/json")
+      .header("authorization", auth)
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string())
+      .reply(&server);
+
+    serde_json::from_slice::<Value>(res.body()).unwrap()
+  };
+  let create = |auth: &str, scopes: Value| {
+    request(
+      auth,
+      "mutation ($apiToken: ApiTokenCreate!) { createApiToken(apiToken: $apiToken) { token } }",
+      json!({ "apiToken": { "name": "Read only", "scopes": scopes } }),
+    )
+  };
+
+  let read_only = create(&token, json!(["users:read"]));
+  let read_only = read_only["data"]["createApiToken"]["token"]
+    .as_str()
+    .unwrap();
+
+  let read = request(
+    read_only,
+    "query ($userId: Uuid!) { User(userId: $userId) { id } }",
+    json!({ "userId": id }),
+  );
+
+  assert_eq!(read["data"]["User"]["id"], json!(id));
+
+  let updated = request(
+    read_only,
+    "mutation ($user: UserUpdate!) { updateUser(user: $user) }",
+    json!({ "user": { "id": id, "name": "Tester" } }),
+  );
+
+  assert_eq!(updated["errors"][0]["extensions"]["code"], "FORBIDDEN");
+  assert_eq!(
+    updated["errors"][0]["extensions"]["missingScopes"],
+    json!(["users:write"])
+  );
+  assert_eq!(
+    updated["errors"][0]["message"],
+    "Forbidden - Missing scope users:write"
+  );
+
+  // Tokens can't create tokens with more scopes than they have.
+  let escalated = create(read_only, json!(["users:write"]));
+
+  assert_eq!(escalated["errors"][0]["extensions"]["code"], "FORBIDDEN");
+  assert_eq!(
+    create(&token, json!(["users:delete"]))["errors"][0]["extensions"]["code"],
+    "BAD_REQUEST"
+  );
+}
+
+#[test]
+fn it_admin() {
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let user = |role: &str| {
+    let id = Uuid::new_v4();
+    let email = format!("{}-test@test.com", id);
+    let token = User::create(
+      &db.connect().unwrap(),
+      &hasher.generate,
+      &tokeniser.generate,
+      &UserCreate {
+        id,
+        email: email.clone(),
+        password: "test".to_string(),
+        name: None,
+      },
+    )
+    .unwrap();
+    User::assign_role_by_email(&db.connect().unwrap(), &email, role).unwrap();
+
+    (id, email, token)
+  };
+
+  let request = |auth: Option<&str>, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    serde_json::from_slice::<Value>(req.reply(&server).body()).unwrap()
+  };
+  let code = |response: Value| response["errors"][0]["extensions"]["code"].clone();
+
+  let (admin_id, _, admin) = user("superadmin");
+  let (_, _, support) = user("support");
+  let (id, email, token) = user("user");
+
+  let search = |auth: &str| {
+    request(
+      Some(auth),
+      "query ($search: String) { Users(search: $search) { id email role } }",
+      json!({ "search": id.to_string() }),
+    )
+  };
+
+  assert_eq!(code(search(&token)), "UNAUTHORISED");
+  assert_eq!(
+    search(&support)["data"]["Users"],
+    json!([{ "id": id, "email": email, "role": "user" }])
+  );
+
+  let suspend = |auth: &str, user_id: &Uuid| {
+    request(
+      Some(auth),
+      "mutation ($userId: Uuid!) { suspendUser(userId: $userId, reason: \"Spam\") }",
+      json!({ "userId": user_id }),
+    )
+  };
+
+  // Support can't act on users with more permissions.
+  assert_eq!(code(suspend(&support, &admin_id)), "UNAUTHORISED");
+  assert_eq!(code(suspend(&token, &admin_id)), "UNAUTHORISED");
+
+  let impersonated = request(
+    Some(&admin),
+    "mutation ($userId: Uuid!) { impersonateUser(userId: $userId) }",
+    json!({ "userId": id }),
+  );
+  let impersonated = impersonated["data"]["impersonateUser"].as_str().unwrap();
+  let claims = (tokeniser.verify)(impersonated).unwrap();
+
+  assert_eq!(claims.sub, id);
+  assert_eq!(claims.act.unwrap().sub, admin_id);
+  assert_eq!(
+    code(request(
+      Some(impersonated),
+      "mutation { enrolMfa }",
+      Value::Null
+    )),
+    "FORBIDDEN"
+  );
+
+  // Nor take the account over by changing how it logs in.
+  let update = |auth: &str, user: Value| {
+    request(
+      Some(auth),
+      "mutation ($user: UserUpdate!) { updateUser(user: $user) }",
+      json!({ "user": user }),
+    )
+  };
+
+  assert_eq!(
+    code(update(impersonated, json!({ "id": id, "password": "taken" }))),
+    "FORBIDDEN"
+  );
+  assert_eq!(
+    code(update(impersonated, json!({ "id": id, "email": "taken@test.com" }))),
+    "FORBIDDEN"
+  );
+  assert_eq!(
+    update(impersonated, json!({ "id": id, "name": "Renamed" }))["data"]["updateUser"],
+    true
+  );
+
+  let assign = |auth: &str, role: &str| {
+    request(
+      Some(auth),
+      "mutation ($userId: Uuid!, $role: String!) { assignRole(userId: $userId, role: $role) }",
+      json!({ "userId": id, "role": role }),
+    )
+  };
+
+  assert_eq!(code(assign(&support, "superadmin")), "UNAUTHORISED");
+  assert_eq!(code(assign(&admin, "root")), "BAD_REQUEST");
+  assert_eq!(assign(&admin, "support")["data"]["assignRole"], true);
+
+  assert_eq!(
+    code(request(
+      Some(&support),
+      "mutation ($userId: Uuid!, $expiresAt: DateTimeUtc!) { suspendUser(userId: $userId, expiresAt: $expiresAt) }",
+      json!({ "userId": id, "expiresAt": "2000-01-01T00:00:00Z" }),
+    )),
+    "BAD_REQUEST"
+  );
+
+  // Every login method is refused once suspended.
+  let mut authenticator = common::Authenticator::new(&config.webauthn_origin);
+  let options = request(
+    Some(&token),
+    "mutation { beginPasskeyRegistration }",
+    Value::Null,
+  );
+  let credential = authenticator.register(
+    options["data"]["beginPasskeyRegistration"]
+      .as_str()
+      .unwrap(),
+  );
+  request(
+    Some(&token),
+    "mutation ($credential: PasskeyRegistration!) { finishPasskeyRegistration(credential: $credential) }",
+    json!({ "credential": credential }),
+  );
+  let passkey_login = |authenticator: &mut common::Authenticator| {
+    let options = request(None, "mutation { beginPasskeyLogin }", Value::Null);
+    let assertion = authenticator.assert(options["data"]["beginPasskeyLogin"].as_str().unwrap());
+
+    request(
+      None,
+      "mutation ($credential: PasskeyAssertion!) { finishPasskeyLogin(credential: $credential) }",
+      json!({ "credential": assertion }),
+    )
+  };
+
+  assert!(passkey_login(&mut authenticator)["data"]["finishPasskeyLogin"].is_string());
+  assert_eq!(suspend(&support, &id)["data"]["suspendUser"], true);
+
+  let login = || {
+    request(
+      None,
+      "mutation ($user: UserLogin!) { login(user: $user) }",
+      json!({ "user": { "email": email, "password": "test" } }),
+    )
+  };
+
+  assert_eq!(code(login()), "ACCOUNT_SUSPENDED");
+  assert_eq!(code(passkey_login(&mut authenticator)), "ACCOUNT_SUSPENDED");
+
+  // Tokens issued before the suspension are refused too.
+  let refused = warp::test::request()
+    .method("POST")
+    .path("/graphql")
+    .header("authorization", token.as_str())
+    .header("content-type", "application/json")
+    .body(json!({ "query": "{ Users { id } }" }).to_string())
+    .reply(&server);
+
+  assert!(refused.status().is_client_error());
+
+  let reinstate = |auth: &str| {
+    request(
+      Some(auth),
+      "mutation ($userId: Uuid!) { reinstateUser(userId: $userId) }",
+      json!({ "userId": id }),
+    )
+  };
+
+  let (_, _, other) = user("user");
+
+  assert_eq!(code(reinstate(&other)), "UNAUTHORISED");
+  assert_eq!(reinstate(&support)["data"]["reinstateUser"], true);
+  assert!(login()["data"]["login"].is_string());
+}
+
+#[test]
+fn it_group_policy() {
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let user = |role: &str| {
+    let id = Uuid::new_v4();
+    let email = format!("{}-test@test.com", id);
+    let token = User::create(
+      &db.connect().unwrap(),
+      &hasher.generate,
+      &tokeniser.generate,
+      &UserCreate {
+        id,
+        email: email.clone(),
+        password: "test".to_string(),
+        name: None,
+      },
+    )
+    .unwrap();
+    User::assign_role_by_email(&db.connect().unwrap(), &email, role).unwrap();
+
+    (id, token)
+  };
+
+  let request = |auth: &str, query: &str, variables: Value| {
+    serde_json::from_slice::<Value>(
+      warp::test::request()
+        .header("content-type", "application/json")
+        .header("authorization", auth)
+        .method("POST")
+        .path("/graphql")
+        .body(json!({ "query": query, "variables": variables }).to_string())
+        .reply(&server)
+        .body(),
+    )
+    .unwrap()
+  };
+  let error = |response: Value| response["errors"][0].clone();
+
+  let (_, member) = user("user");
+  let (_, outsider) = user("user");
+  let (admin_id, admin) = user("superadmin");
+  let group_id = Uuid::new_v4();
+  let read = |auth: &str| {
+    request(
+      auth,
+      "query ($groupId: Uuid!) { Group(groupId: $groupId) { name } }",
+      json!({ "groupId": group_id }),
+    )
+  };
+  let update = |auth: &str, name: &str| {
+    request(
+      auth,
+      "mutation ($group: GroupUpdate!) { updateGroup(group: $group) }",
+      json!({ "group": { "id": group_id, "name": name } }),
+    )
+  };
+
+  request(
+    &member,
+    "mutation ($group: GroupCreate!) { createGroup(group: $group) }",
+    json!({ "group": { "id": group_id, "name": "Test", "createdAt": Utc::now() } }),
+  );
+
+  assert_eq!(update(&member, "Renamed")["data"]["updateGroup"], true);
+  assert_eq!(read(&member)["data"]["Group"]["name"], "Renamed");
+
+  // Groups are only open to their members, admins included.
+  for token in &[&outsider, &admin] {
+    for response in [read(token), update(token, "Taken")] {
+      assert_eq!(error(response)["extensions"]["code"], "UNAUTHORISED");
+    }
+  }
+
+  assert_eq!(read(&member)["data"]["Group"]["name"], "Renamed");
+  assert_eq!(
+    error(request(
+      &admin,
+      "mutation ($userId: Uuid!) { suspendUser(userId: $userId) }",
+      json!({ "userId": admin_id }),
+    ))["message"],
+    "Unauthorised - Users can't suspend themselves"
+  );
+}
+
+#[test]
+fn it_soft_delete() {
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let user = |role: &str| {
+    let id = Uuid::new_v4();
+    let email = format!("{}-test@test.com", id);
+    let token = User::create(
+      &db.connect().unwrap(),
+      &hasher.generate,
+      &tokeniser.generate,
+      &UserCreate {
+        id,
+        email: email.clone(),
+        password: "test".to_string(),
+        name: None,
+      },
+    )
+    .unwrap();
+    User::assign_role_by_email(&db.connect().unwrap(), &email, role).unwrap();
+
+    (id, email, token)
+  };
+
+  let request = |auth: Option<&str>, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    serde_json::from_slice::<Value>(req.reply(&server).body()).unwrap()
+  };
+  let code = |response: Value| response["errors"][0]["extensions"]["code"].clone();
+
+  let (_, _, admin) = user("superadmin");
+  let (id, email, token) = user("user");
+  let group_id = Uuid::new_v4();
+  let group = |query: &str| request(Some(&token), query, json!({ "groupId": group_id }));
+
+  request(
+    Some(&token),
+    "mutation ($group: GroupCreate!) { createGroup(group: $group) }",
+    json!({ "group": { "id": group_id, "name": "Test", "createdAt": Utc::now() } }),
+  );
+
+  assert_eq!(
+    group("mutation ($groupId: Uuid!) { deleteGroup(groupId: $groupId) }")["data"]["deleteGroup"],
+    true
+  );
+  assert_eq!(
+    code(group(
+      "query ($groupId: Uuid!) { Group(groupId: $groupId) { id } }"
+    )),
+    "NOT_FOUND"
+  );
+  assert_eq!(
+    group("mutation ($groupId: Uuid!) { restoreGroup(groupId: $groupId) }")["data"]["restoreGroup"],
+    true
+  );
+  assert_eq!(
+    group("query ($groupId: Uuid!) { Group(groupId: $groupId) { id } }")["data"]["Group"]["id"],
+    json!(group_id)
+  );
+
+  let login = || {
+    request(
+      None,
+      "mutation ($user: UserLogin!) { login(user: $user) }",
+      json!({ "user": { "email": email, "password": "test" } }),
+    )
+  };
+  let restore = |auth: &str| {
+    request(
+      Some(auth),
+      "mutation ($userId: Uuid!) { restoreUser(userId: $userId) }",
+      json!({ "userId": id }),
+    )
+  };
+
+  assert_eq!(
+    request(
+      Some(&token),
+      "mutation ($userId: Uuid!) { deleteUser(userId: $userId) }",
+      json!({ "userId": id }),
+    )["data"]["deleteUser"],
+    true
+  );
+  assert_eq!(code(login()), "INVALID_CREDENTIALS");
+  assert_eq!(restore(&admin)["data"]["restoreUser"], true);
+  assert!(login()["data"]["login"].is_string());
+
+  // Purging only removes rows past the grace period.
+  use api::models::schema::users;
+  use diesel::prelude::*;
+
+  let connection = db.connect().unwrap();
+  let (purged_id, _, purged_token) = user("user");
+  let exists = || {
+    users::table
+      .find(purged_id)
+      .count()
+      .get_result::<i64>(&connection)
+      .unwrap()
+      == 1
+  };
+
+  assert!(User::delete(&connection, &purged_id, &purged_id).unwrap());
+  retention::purge(&connection, config.deletion_grace_period).unwrap();
+  assert!(exists());
+
+  retention::purge(&connection, Duration::from_secs(0)).unwrap();
+  assert!(!exists());
+
+  // Tokens outlive the user, but are refused.
+  assert!(warp::test::request()
+    .header("content-type", "application/json")
+    .header("authorization", purged_token)
+    .method("POST")
+    .path("/graphql")
+    .body(json!({ "query": "{ __type(name: \"User\") { name } }" }).to_string())
+    .reply(&server)
+    .status()
+    .is_client_error());
+}
+
+#[test]
+fn it_data_export() {
+  let mut config = common::config();
+  config.export_dir = std::env::temp_dir().join(format!("graphy-exports-{}", Uuid::new_v4()));
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+  let token = User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: "test".to_string(),
+      name: Some("Test".to_string()),
+    },
+  )
+  .unwrap();
+  let request = |query: &str, variables: Value| {
+    let response = warp::test::request()
+      .header("content-type", "application/json")
+      .header("authorization", token.as_str())
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string())
+      .reply(&server);
+
+    serde_json::from_slice::<Value>(response.body()).unwrap()
+  };
+  let group_id = Uuid::new_v4();
+
+  request(
+    "mutation ($group: GroupCreate!) { createGroup(group: $group) }",
+    json!({ "group": { "id": group_id, "name": "Exported", "createdAt": Utc::now() } }),
+  );
+
+  // Events by others, i.e. an admin suspending the user.
+  use api::models::audit_event::{AuditEvent, AuditEventCreate};
+
+  AuditEvent::record(
+    &db.connect().unwrap(),
+    &AuditEventCreate {
+      id: Uuid::new_v4(),
+      actor_id: Some(Uuid::new_v4()),
+      impersonator_id: None,
+      action: "suspend_user",
+      target_id: Some(id),
+      ip: Some("203.0.113.9".to_string()),
+      user_agent: None,
+      request_id: "test",
+      details: json!({}),
+      diff: json!({}),
+    },
+  )
+  .unwrap();
+
+  let export = request(
+    "mutation { requestDataExport { url expiresAt } }",
+    Value::Null,
+  );
+  let url = export["data"]["requestDataExport"]["url"].as_str().unwrap();
+  let export_id = &url["/exports/".len().."/exports/".len() + 36];
+  let download = warp::test::request().path(url).reply(&server);
+
+  assert_eq!(download.status(), 200);
+  assert_eq!(
+    download.headers()["content-disposition"],
+    format!("attachment; filename=\"export-{}.json\"", export_id).as_str()
+  );
+
+  let archive = serde_json::from_slice::<Value>(download.body()).unwrap();
+
+  assert_eq!(archive["profile"]["email"], json!(email));
+  assert_eq!(archive["groups"][0]["id"], json!(group_id));
+  assert_eq!(archive["groups"][0]["name"], "Exported");
+
+  let suspended = archive["audit_events"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .find(|event| event["action"] == "suspend_user")
+    .unwrap();
+
+  assert_eq!(suspended["target_id"], json!(id));
+  assert_eq!(suspended["actor_id"], Value::Null);
+  assert_eq!(suspended["ip"], Value::Null);
+
+  // Links can't be extended or reused for other exports.
+  let tampered = url.replace("expires=", "expires=1");
+
+  assert_eq!(
+    warp::test::request()
+      .path(&tampered)
+      .reply(&server)
+      .status(),
+    404
+  );
+  assert_eq!(
+    warp::test::request()
+      .path(&url.replace(export_id, &Uuid::new_v4().to_string()))
+      .reply(&server)
+      .status(),
+    404
+  );
+}
+
+#[test]
+fn it_audit_events() {
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let user = |role: &str| {
+    let id = Uuid::new_v4();
+    let email = format!("{}-test@test.com", id);
+    let token = User::create(
+      &db.connect().unwrap(),
+      &hasher.generate,
+      &tokeniser.generate,
+      &UserCreate {
+        id,
+        email: email.clone(),
+        password: "test".to_string(),
+        name: None,
+      },
+    )
+    .unwrap();
+    User::assign_role_by_email(&db.connect().unwrap(), &email, role).unwrap();
+
+    (id, email, token)
+  };
+  let request = |auth: Option<&str>, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .header("user-agent", "graphy-test")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    serde_json::from_slice::<Value>(req.reply(&server).body()).unwrap()
+  };
+  let events = |auth: &str, filter: Value| {
+    request(
+      Some(auth),
+      "query ($filter: AuditEventFilter) {
+        AuditEvents(filter: $filter) {
+          actorId impersonatorId targetId action userAgent details diff
+        }
+      }",
+      json!({ "filter": filter }),
+    )
+  };
+
+  let (admin_id, _, admin) = user("superadmin");
+  let (id, email, token) = user("user");
+
+  request(
+    Some(&token),
+    "mutation ($user: UserUpdate!) { updateUser(user: $user) }",
+    json!({ "user": { "id": id, "name": "Audited", "password": "changed" } }),
+  );
+
+  let updated = events(&admin, json!({ "targetId": id, "action": "update_user" }));
+  let event = &updated["data"]["AuditEvents"][0];
+
+  assert_eq!(event["actorId"], json!(id));
+  assert_eq!(event["impersonatorId"], Value::Null);
+  assert_eq!(event["userAgent"], "graphy-test");
+  assert_eq!(
+    serde_json::from_str::<Value>(event["diff"].as_str().unwrap()).unwrap(),
+    json!({
+      "name": { "from": null, "to": "Audited" },
+      "password": "[REDACTED]",
+    })
+  );
+
+  // Changes made impersonating users name the admin.
+  let impersonated = request(
+    Some(&admin),
+    "mutation ($userId: Uuid!) { impersonateUser(userId: $userId) }",
+    json!({ "userId": id }),
+  )["data"]["impersonateUser"]
+    .as_str()
+    .unwrap()
+    .to_string();
+  request(
+    Some(&impersonated),
+    "mutation ($user: UserUpdate!) { updateUser(user: $user) }",
+    json!({ "user": { "id": id, "name": "Impersonated" } }),
+  );
+
+  let impersonated = events(&admin, json!({ "impersonatorId": admin_id }));
+  let event = &impersonated["data"]["AuditEvents"][0];
+
+  assert_eq!(event["action"], "update_user");
+  assert_eq!(event["actorId"], json!(id));
+  assert_eq!(event["impersonatorId"], json!(admin_id));
+
+  // Logins are recorded, failed or not.
+  for password in &["wrong", "changed"] {
+    request(
+      None,
+      "mutation ($user: UserLogin!) { login(user: $user) }",
+      json!({ "user": { "email": email, "password": password } }),
+    );
+  }
+
+  let logins = events(
+    &admin,
+    json!({ "action": "login", "since": Utc::now() - chrono::Duration::minutes(1) }),
+  );
+  // Timestamps are the test transaction's, so order isn't checked.
+  let mut results: Vec<String> = logins["data"]["AuditEvents"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|event| serde_json::from_str::<Value>(event["details"].as_str().unwrap()).unwrap())
+    .filter(|details| details["email"] == json!(email))
+    .map(|details| details["result"].as_str().unwrap().to_string())
+    .collect();
+  results.sort();
+
+  assert_eq!(results, vec!["failure", "success"]);
+  assert_eq!(
+    events(&token, json!({}))["errors"][0]["extensions"]["code"],
+    "UNAUTHORISED"
+  );
+
+  // Events can't be changed or removed.
+  use api::models::audit_event::{AuditEvent, AuditEventCreate};
+  use api::models::schema::audit_events;
+  use diesel::prelude::*;
+
+  let connection = db.connect().unwrap();
+  let event_id = Uuid::new_v4();
+
+  AuditEvent::record(
+    &connection,
+    &AuditEventCreate {
+      id: event_id,
+      actor_id: None,
+      impersonator_id: None,
+      action: "test",
+      target_id: None,
+      ip: None,
+      user_agent: None,
+      request_id: "test",
+      details: json!({}),
+      diff: json!({}),
+    },
+  )
+  .unwrap();
+
+  assert!(diesel::update(audit_events::table.find(event_id))
+    .set(audit_events::action.eq("tampered"))
+    .execute(&connection)
+    .is_err());
+  assert!(diesel::delete(audit_events::table.find(event_id))
+    .execute(&connection)
+    .is_err());
+}
+
+#[test]
+fn it_sessions() {
+  let config = common::config();
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+  User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+  let request = |auth: Option<&str>, user_agent: &str, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .header("user-agent", user_agent)
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(auth) = auth {
+      req = req.header("authorization", auth);
+    }
+
+    req.reply(&server)
+  };
+  let login = |user_agent: &str| {
+    let response = request(
+      None,
+      user_agent,
+      "mutation ($user: UserLogin!) { login(user: $user) }",
+      json!({ "user": { "email": email, "password": "test" } }),
+    );
+
+    serde_json::from_slice::<Value>(response.body()).unwrap()["data"]["login"]
+      .as_str()
+      .unwrap()
+      .to_string()
+  };
+  let sessions = |token: &str| {
+    let response = request(
+      Some(token),
+      "test",
+      "query { mySessions { id device userAgent current } }",
+      Value::Null,
+    );
+
+    serde_json::from_slice::<Value>(response.body()).unwrap()["data"]["mySessions"].clone()
+  };
+
+  let phone = login("Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X)");
+  let laptop = login("curl/8.4.0");
+  let listed = sessions(&phone);
+  let current: Vec<&Value> = listed
+    .as_array()
+    .unwrap()
+    .iter()
+    .filter(|session| session["current"] == true)
+    .collect();
+
+  assert_eq!(listed.as_array().unwrap().len(), 2);
+  assert_eq!(current.len(), 1);
+  assert_eq!(current[0]["device"], "iPhone");
+
+  // Revoked sessions' tokens are refused.
+  let revoked = request(
+    Some(&phone),
+    "test",
+    "mutation { revokeOtherSessions }",
+    Value::Null,
+  );
+
+  assert_eq!(
+    serde_json::from_slice::<Value>(revoked.body()).unwrap()["data"]["revokeOtherSessions"],
+    1
+  );
+  assert!(request(
+    Some(&laptop),
+    "test",
+    "query { mySessions { id } }",
+    Value::Null
+  )
+  .status()
+  .is_client_error());
+
+  let revoked = request(
+    Some(&phone),
+    "test",
+    "mutation ($sessionId: Uuid!) { revokeSession(sessionId: $sessionId) }",
+    json!({ "sessionId": current[0]["id"] }),
+  );
+
+  assert_eq!(
+    serde_json::from_slice::<Value>(revoked.body()).unwrap()["data"]["revokeSession"],
+    true
+  );
+  assert!(request(
+    Some(&phone),
+    "test",
+    "query { mySessions { id } }",
+    Value::Null
+  )
+  .status()
+  .is_client_error());
+}
+
+#[test]
+fn it_cookie_sessions() {
+  let mut config = common::config();
+  config.cookie_sessions = true;
+  let db = common::db(&config);
+  let hasher = Hasher::new(&config.hash_salt).unwrap();
+  let tokeniser = Tokeniser::new(&config.token_secret);
+  let server = common::server(&config);
+  let id = Uuid::new_v4();
+  let email = format!("{}-test@test.com", id);
+  User::create(
+    &db.connect().unwrap(),
+    &hasher.generate,
+    &tokeniser.generate,
+    &UserCreate {
+      id,
+      email: email.clone(),
+      password: "test".to_string(),
+      name: None,
+    },
+  )
+  .unwrap();
+  let request = |cookie: Option<&str>, csrf: Option<&str>, query: &str, variables: Value| {
+    let mut req = warp::test::request()
+      .header("content-type", "application/json")
+      .method("POST")
+      .path("/graphql")
+      .body(json!({ "query": query, "variables": variables }).to_string());
+
+    if let Some(cookie) = cookie {
+      req = req.header("cookie", cookie);
+    }
+    if let Some(csrf) = csrf {
+      req = req.header("x-csrf-token", csrf);
+    }
+
+    req.reply(&server)
+  };
+  fn body<B: AsRef<[u8]>>(response: &warp::http::Response<B>) -> Value {
+    serde_json::from_slice(response.body().as_ref()).unwrap()
+  }
+  fn set_cookies<B>(response: &warp::http::Response<B>) -> Vec<String> {
+    response
+      .headers()
+      .get_all("set-cookie")
+      .iter()
+      .map(|cookie| cookie.to_str().unwrap().to_string())
+      .collect()
+  }
+
+  let login = request(
+    None,
+    None,
+    "mutation ($user: UserLogin!) { login(user: $user) }",
+    json!({ "user": { "email": email, "password": "test" } }),
+  );
+  let csrf = body(&login)["data"]["login"].as_str().unwrap().to_string();
+  let cookies = set_cookies(&login);
+
+  // The token is only in an `HttpOnly` cookie.
+  assert_eq!(cookies.len(), 2);
+  assert!(cookies[0].starts_with("graphy_session="));
+  assert!(cookies[0].contains("HttpOnly; Secure; SameSite=Lax"));
+  assert_eq!(
+    cookies[1].split(';').next().unwrap(),
+    format!("graphy_csrf={}", csrf)
+  );
+
+  let cookie = cookies
+    .iter()
+    .map(|cookie| cookie.split(';').next().unwrap())
+    .collect::<Vec<_>>()
+    .join("; ");
+  let update = |csrf: Option<&str>| {
+    body(&request(
+      Some(&cookie),
+      csrf,
+      "mutation ($user: UserUpdate!) { updateUser(user: $user) }",
+      json!({ "user": { "id": id, "name": "Cookie" } }),
+    ))
+  };
+
+  assert_eq!(
+    body(&request(
+      Some(&cookie),
+      None,
+      "query { mySessions { current } }",
+      Value::Null
+    ))["data"]["mySessions"][0]["current"],
+    true
+  );
+  assert_eq!(
+    update(None)["errors"][0]["extensions"]["code"],
+    "INVALID_CSRF_TOKEN"
+  );
+  assert_eq!(
+    update(Some("forged"))["errors"][0]["extensions"]["code"],
+    "INVALID_CSRF_TOKEN"
+  );
+  assert_eq!(update(Some(&csrf))["data"]["updateUser"], true);
+
+  let logout = request(
+    Some(&cookie),
+    Some(&csrf),
+    "mutation { logout }",
+    Value::Null,
+  );
+
+  assert_eq!(body(&logout)["data"]["logout"], true);
+  assert!(set_cookies(&logout)
+    .iter()
+    .all(|cookie| cookie.contains("=; Max-Age=0;")));
+
+  // Revoked cookies are cleared, carrying on logged out.
+  let revoked = request(
+    Some(&cookie),
+    None,
+    "query { mySessions { id } }",
+    Value::Null,
+  );
+
+  assert_eq!(
+    body(&revoked)["errors"][0]["extensions"]["code"],
+    "UNAUTHORISED"
+  );
+  assert_eq!(set_cookies(&revoked).len(), 2);
+}
+
+#[test]
+fn it_cors() {
+  let mut config = common::config();
+  config.cors_origins = vec!["https://*.example.com".to_string()];
+  config.cors_credentials = true;
+  let server = common::server(&config);
+  let preflight = |origin: &str, method: &str| {
+    warp::test::request()
+      .method("OPTIONS")
+      .path("/graphql")
+      .header("origin", origin)
+      .header("access-control-request-method", method)
+      .header(
+        "access-control-request-headers",
+        "content-type, x-csrf-token",
+      )
+      .reply(&server)
+  };
+
+  let allowed = preflight("https://app.example.com", "POST");
+  assert_eq!(allowed.status(), 204);
+  assert_eq!(
+    allowed.headers()["access-control-allow-origin"],
+    "https://app.example.com"
+  );
+  assert_eq!(
+    allowed.headers()["access-control-allow-credentials"],
+    "true"
+  );
+  assert_eq!(allowed.headers()["access-control-max-age"], "600");
+
+  for (origin, method) in &[
+    ("https://example.evil.com", "POST"),
+    ("https://app.example.com", "DELETE"),
+  ] {
+    let denied = preflight(origin, method);
+    assert_eq!(denied.status(), 403);
+    assert!(!denied.headers().contains_key("access-control-allow-origin"));
+  }
+
+  let request = |origin: &str| {
+    warp::test::request()
+      .method("POST")
+      .path("/graphql")
+      .header("origin", origin)
+      .header("content-type", "application/json")
+      .body(json!({ "query": "{ __type(name: \"User\") { name } }" }).to_string())
+      .reply(&server)
+  };
+
+  let allowed = request("https://app.example.com");
+  assert_eq!(allowed.status(), 200);
+  assert_eq!(
+    allowed.headers()["access-control-allow-origin"],
+    "https://app.example.com"
+  );
+  assert_eq!(allowed.headers()["vary"], "Origin");
+
+  let denied = request("https://evil.com");
+  assert!(!denied.headers().contains_key("access-control-allow-origin"));
+}